@@ -0,0 +1,126 @@
+//! Feed-read throughput while another thread keeps writing.
+//!
+//! Run with `cargo bench --bench feed_read`. Compares the `RwLock` the
+//! handlers share the database through with the single `Mutex` they used to.
+
+use chrono::Utc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use web_api::database::Database;
+use web_api::feed::FeedQuery;
+use web_api::models::Post;
+
+const READERS: usize = 4;
+const INITIAL_POSTS: usize = 1_000;
+const RUN_FOR: Duration = Duration::from_secs(2);
+
+/// The two ways of sharing the database being compared.
+trait Shared: Send + Sync + 'static {
+    fn read_feed(&self) -> usize;
+    fn write_post(&self, post: Post);
+}
+
+impl Shared for Mutex<Database> {
+    fn read_feed(&self) -> usize {
+        let database = self.lock().unwrap_or_else(PoisonError::into_inner);
+        render_feed(&database)
+    }
+
+    fn write_post(&self, post: Post) {
+        let mut database = self.lock().unwrap_or_else(PoisonError::into_inner);
+        database.add_post(post).unwrap();
+    }
+}
+
+impl Shared for RwLock<Database> {
+    fn read_feed(&self) -> usize {
+        let database = self.read().unwrap_or_else(PoisonError::into_inner);
+        render_feed(&database)
+    }
+
+    fn write_post(&self, post: Post) {
+        let mut database = self.write().unwrap_or_else(PoisonError::into_inner);
+        database.add_post(post).unwrap();
+    }
+}
+
+/// Does what `PostFeedHandler` does with the lock held.
+fn render_feed(database: &Database) -> usize {
+    let query = FeedQuery::parse(Vec::new()).unwrap();
+    let page = query.page(database.posts());
+    serde_json::to_string(&page).unwrap().len()
+}
+
+fn post(n: usize) -> Post {
+    Post::new(
+        &format!("Post number {}", n),
+        "Benchmark body text that is long enough to be realistic.",
+        "bench",
+        Utc::now(),
+        Uuid::new_v4(),
+    )
+}
+
+fn seeded_database() -> Database {
+    let mut database = Database::new();
+    for n in 0..INITIAL_POSTS {
+        database.add_post(post(n)).unwrap();
+    }
+    database
+}
+
+/// Returns feed reads per second and writes per second.
+fn run<S: Shared>(shared: Arc<S>, with_writer: bool) -> (f64, f64) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let writes = Arc::new(AtomicUsize::new(0));
+
+    let mut threads = Vec::new();
+    for _ in 0..READERS {
+        let (shared, stop, reads) = (shared.clone(), stop.clone(), reads.clone());
+        threads.push(thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                shared.read_feed();
+                reads.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+    }
+    if with_writer {
+        let (shared, stop, writes) = (shared.clone(), stop.clone(), writes.clone());
+        threads.push(thread::spawn(move || {
+            let mut n = INITIAL_POSTS;
+            while !stop.load(Ordering::Relaxed) {
+                shared.write_post(post(n));
+                writes.fetch_add(1, Ordering::Relaxed);
+                n += 1;
+            }
+        }));
+    }
+
+    let started = Instant::now();
+    thread::sleep(RUN_FOR);
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let seconds = started.elapsed().as_secs_f64();
+    (
+        reads.load(Ordering::Relaxed) as f64 / seconds,
+        writes.load(Ordering::Relaxed) as f64 / seconds,
+    )
+}
+
+fn report(name: &str, (reads, writes): (f64, f64)) {
+    println!("{:<28} {:>10.0} reads/s {:>10.0} writes/s", name, reads, writes);
+}
+
+fn main() {
+    println!("{} readers, {} posts to start with, {:?} per run", READERS, INITIAL_POSTS, RUN_FOR);
+    report("mutex, readers only", run(Arc::new(Mutex::new(seeded_database())), false));
+    report("rwlock, readers only", run(Arc::new(RwLock::new(seeded_database())), false));
+    report("mutex, with writer", run(Arc::new(Mutex::new(seeded_database())), true));
+    report("rwlock, with writer", run(Arc::new(RwLock::new(seeded_database())), true));
+}
@@ -0,0 +1,87 @@
+use crate::auth::{Auth, AuthMiddleware};
+use crate::conditional::{ConditionalAfterMiddleware, ConditionalBeforeMiddleware};
+use crate::config::Config;
+use crate::cors::{Cors, PreflightHandler};
+use crate::database::Database;
+use crate::error::ErrorAfterMiddleware;
+use crate::handlers::{Handlers, JsonAfterMiddleware};
+use crate::limits::{BodyLimit, RateLimiter};
+use crate::logging::RequestLogger;
+use crate::metrics::{Metrics, MetricsHandler};
+use crate::openapi::OpenApiHandler;
+use crate::routes::Routes;
+
+use iron::Chain;
+use std::sync::{Arc, RwLock};
+
+/// Registers every route of the API. Each one must also be described in
+/// `openapi::document`.
+pub fn routes(handlers: Handlers, metrics: Arc<Metrics>) -> Routes {
+    let mut routes = Routes::new();
+    routes.get("/post_feed", handlers.post_feed, "post_feed");
+    routes.get("/post_feed/stream", handlers.post_stream, "post_stream");
+    routes.post("/post", handlers.post_post, "post_post");
+    routes.get("/post/:id", handlers.post, "post");
+    routes.put("/post/:id", handlers.post_put, "post_put");
+    routes.patch("/post/:id", handlers.post_patch, "post_patch");
+    routes.delete("/post/:id", handlers.post_delete, "post_delete");
+    routes.get("/trash", handlers.trash, "trash");
+    routes.post("/trash/:id/restore", handlers.trash_restore, "trash_restore");
+    routes.get("/post/:id/comments", handlers.comments, "comments");
+    routes.post("/post/:id/comments", handlers.comment_post, "comment_post");
+    routes.get("/post/:id/revisions", handlers.revisions, "revisions");
+    routes.get("/post/:id/revisions/:n", handlers.revision, "revision");
+    routes.get("/post/:id/revisions/:n/diff", handlers.revision_diff, "revision_diff");
+    routes.post(
+        "/post/:id/revisions/:n/restore",
+        handlers.revision_restore,
+        "revision_restore",
+    );
+    routes.get("/tags", handlers.tags, "tags");
+    routes.get("/tags/:tag/posts", handlers.tag_posts, "tag_posts");
+    routes.get("/search", handlers.search, "search");
+    routes.post("/register", handlers.register, "register");
+    routes.post("/login", handlers.login, "login");
+    routes.post("/admin/import", handlers.admin_import, "admin_import");
+    routes.get("/admin/export", handlers.admin_export, "admin_export");
+    routes.get("/admin/webhooks", handlers.webhooks, "webhooks");
+    routes.post("/admin/webhooks", handlers.webhook_post, "webhook_post");
+    routes.get("/admin/webhooks/:id", handlers.webhook, "webhook");
+    routes.delete("/admin/webhooks/:id", handlers.webhook_delete, "webhook_delete");
+    routes.get(
+        "/admin/webhooks/:id/deliveries",
+        handlers.webhook_deliveries,
+        "webhook_deliveries",
+    );
+    routes.get("/metrics", MetricsHandler::new(metrics), "metrics");
+    routes.get("/openapi.json", OpenApiHandler::new(), "openapi");
+    routes
+}
+
+/// The whole application: the router with every route behind the
+/// middleware, in the order it must run.
+pub fn chain(config: &Config, database: Arc<RwLock<Database>>, auth: Arc<Auth>) -> Chain {
+    let metrics = Arc::new(Metrics::new());
+    let handlers = Handlers::new(database.clone(), auth.clone(), config.max_streams());
+    let mut routes = routes(handlers, metrics.clone());
+    let cors = Arc::new(Cors::new(config.cors_origins.clone(), routes.table()));
+    routes.options("/*", PreflightHandler::new(cors.clone()), "preflight");
+    let request_logger = Arc::new(RequestLogger::new(routes.table(), metrics));
+    let body_limit = BodyLimit::new(config.limits.max_body_bytes, routes.table())
+        .with_route("admin_import", config.limits.max_import_bytes);
+
+    let mut chain = Chain::new(routes.into_router());
+    chain.link_before(request_logger.clone());
+    // Authentication comes first so the rate limiter can charge users; the
+    // limiter charges requests it refuses to their address.
+    chain.link_before(AuthMiddleware::new(auth, database));
+    chain.link_before(RateLimiter::new(config.limits.reads, config.limits.writes));
+    chain.link_before(body_limit);
+    chain.link_before(ConditionalBeforeMiddleware);
+    chain.link_after(ErrorAfterMiddleware);
+    chain.link_after(JsonAfterMiddleware);
+    chain.link_after(cors);
+    chain.link_after(ConditionalAfterMiddleware);
+    chain.link_after(request_logger);
+    chain
+}
@@ -0,0 +1,218 @@
+use crate::database::Database;
+use crate::error::ApiError;
+use crate::models::{Post, Role, User};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use iron::headers::{Authorization, Bearer};
+use iron::typemap::Key;
+use iron::{status, BeforeMiddleware, IronError, IronResult, Request};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::{Arc, PoisonError, RwLock};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// PBKDF2 rounds for new password hashes. Stored with every hash, so it can
+/// be raised without invalidating existing accounts.
+const PASSWORD_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+
+/// Hashes a password with a fresh random salt, as
+/// `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let hash = derive(password, &salt, PASSWORD_ITERATIONS);
+    format!(
+        "{}${}${}${}",
+        HASH_SCHEME,
+        PASSWORD_ITERATIONS,
+        base64::encode_config(salt, base64::STANDARD_NO_PAD),
+        base64::encode_config(hash, base64::STANDARD_NO_PAD)
+    )
+}
+
+/// Checks a password against a hash produced by `hash_password`.
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let (iterations, salt, hash) = match parts.as_slice() {
+        [HASH_SCHEME, iterations, salt, hash] => (iterations, salt, hash),
+        _ => return false,
+    };
+    let iterations = match iterations.parse() {
+        Ok(iterations) => iterations,
+        Err(_) => return false,
+    };
+    let salt = base64::decode_config(salt, base64::STANDARD_NO_PAD).unwrap_or_default();
+    let hash = base64::decode_config(hash, base64::STANDARD_NO_PAD).unwrap_or_default();
+    constant_time_eq(&derive(password, &salt, iterations), &hash)
+}
+
+fn derive(password: &str, salt: &[u8], iterations: u32) -> [u8; HASH_LEN] {
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+    hash
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What a bearer token asserts about its holder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// Username of the holder.
+    pub sub: String,
+    /// Role at the time the token was issued. `AuthMiddleware` replaces it
+    /// with the role the account has now.
+    pub role: Role,
+    /// Expiry, in seconds since the Unix epoch.
+    pub exp: i64,
+}
+
+impl Claims {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Only a post's author or an admin may change or delete it.
+    pub fn may_edit(&self, post: &Post) -> bool {
+        self.is_admin() || post.author() == self.sub
+    }
+
+    /// Whether the holder may publish under `author`.
+    pub fn may_write_as(&self, author: &str) -> bool {
+        self.is_admin() || author == self.sub
+    }
+}
+
+/// `Request::extensions` key under which `AuthMiddleware` leaves the claims
+/// of a valid bearer token.
+pub struct CurrentUser;
+
+impl Key for CurrentUser {
+    type Value = Claims;
+}
+
+/// Issues and checks HMAC-SHA256 signed bearer tokens of the form
+/// `<base64url claims>.<base64url signature>`.
+pub struct Auth {
+    secret: Vec<u8>,
+    token_ttl: Duration,
+}
+
+impl Auth {
+    pub fn new(secret: Vec<u8>, token_ttl: Duration) -> Auth {
+        Auth { secret, token_ttl }
+    }
+
+    /// A random signing secret. Tokens signed with it stop being valid when
+    /// the process exits.
+    pub fn random_secret() -> Vec<u8> {
+        let mut secret = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        secret
+    }
+
+    /// Signs a token for `user`, with the role the account has, and returns
+    /// it with its expiry.
+    pub fn issue(&self, user: &User) -> (String, DateTime<Utc>) {
+        let expires_at = Utc::now() + self.token_ttl;
+        let claims = Claims {
+            sub: user.username().to_string(),
+            role: user.role(),
+            exp: expires_at.timestamp(),
+        };
+        let claims = serde_json::to_vec(&claims).expect("claims serialize");
+        let claims = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+        let signature = base64::encode_config(self.sign(claims.as_bytes()), base64::URL_SAFE_NO_PAD);
+        (format!("{}.{}", claims, signature), expires_at)
+    }
+
+    /// Returns the claims of a token that carries a valid signature and has
+    /// not expired.
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let (claims, signature) = token.split_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        let mut mac = self.mac();
+        mac.update(claims.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+
+        let claims = base64::decode_config(claims, base64::URL_SAFE_NO_PAD).ok()?;
+        let claims: Claims = serde_json::from_slice(&claims).ok()?;
+        let expires_at = Utc.timestamp_opt(claims.exp, 0).single()?;
+        if expires_at <= Utc::now() {
+            return None;
+        }
+        Some(claims)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// Checks the `Authorization: Bearer` header, if any, and attaches the
+/// token's claims to the request as `CurrentUser`. Requests without the
+/// header pass through anonymously; requests with a bad token are refused.
+///
+/// The role comes from the account as stored now, not from the token, so
+/// that an admin taken off the list loses the role at once. Tokens of
+/// accounts that do not exist are refused.
+pub struct AuthMiddleware {
+    auth: Arc<Auth>,
+    database: Arc<RwLock<Database>>,
+}
+
+impl AuthMiddleware {
+    pub fn new(auth: Arc<Auth>, database: Arc<RwLock<Database>>) -> AuthMiddleware {
+        AuthMiddleware { auth, database }
+    }
+
+    fn current_claims(&self, token: &str) -> Option<Claims> {
+        let mut claims = self.auth.verify(token)?;
+        let database = self.database.read().unwrap_or_else(PoisonError::into_inner);
+        claims.role = database.user(&claims.sub)?.role();
+        Some(claims)
+    }
+}
+
+impl BeforeMiddleware for AuthMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        if req.headers.get_raw("Authorization").is_none() {
+            return Ok(());
+        }
+        let claims = req
+            .headers
+            .get::<Authorization<Bearer>>()
+            .and_then(|header| self.current_claims(&header.token));
+        match claims {
+            Some(claims) => {
+                req.extensions.insert::<CurrentUser>(claims);
+                Ok(())
+            }
+            None => {
+                let mut err: IronError = ApiError::new(
+                    status::Unauthorized,
+                    "unauthorized",
+                    "invalid or expired bearer token",
+                )
+                .into();
+                err.response
+                    .headers
+                    .set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::models::Comment;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+const DEFAULT_DEPTH: usize = 3;
+const MAX_DEPTH: usize = 10;
+const DEFAULT_REPLIES: usize = 10;
+
+/// Position after the last comment of the previous page.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    datetime: DateTime<Utc>,
+    uuid: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(value: &str) -> Option<Cursor> {
+        let json = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// A comment with the replies below it, down to the requested depth.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CommentNode<'a> {
+    #[serde(flatten)]
+    pub comment: &'a Comment,
+    /// Number of direct replies, including any cut off by the depth or
+    /// replies limit. The rest are paged through with `parent`.
+    pub reply_count: usize,
+    pub replies: Vec<CommentNode<'a>>,
+}
+
+/// One page of top-level comments, or of the replies to one comment, with
+/// their reply trees.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CommentPage<'a> {
+    pub comments: Vec<CommentNode<'a>>,
+    pub next_cursor: Option<String>,
+}
+
+/// Query parameters of `GET /post/:id/comments`.
+#[derive(Debug)]
+pub struct CommentQuery {
+    limit: usize,
+    /// Levels of replies below each comment on the page.
+    depth: usize,
+    /// Replies shown below any one comment, oldest first.
+    replies: usize,
+    /// Comment whose replies are paged instead of the top-level comments.
+    parent: Option<Uuid>,
+    cursor: Option<Cursor>,
+}
+
+impl CommentQuery {
+    /// Parses the query string pairs. Returns the name of the first parameter
+    /// that has an invalid value.
+    pub fn parse<I>(pairs: I) -> Result<CommentQuery, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut query = CommentQuery {
+            limit: DEFAULT_LIMIT,
+            depth: DEFAULT_DEPTH,
+            replies: DEFAULT_REPLIES,
+            parent: None,
+            cursor: None,
+        };
+        for (key, value) in pairs {
+            let valid = match key.as_str() {
+                "limit" => value
+                    .parse()
+                    .ok()
+                    .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                    .map(|limit| query.limit = limit)
+                    .is_some(),
+                "depth" => value
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth <= MAX_DEPTH)
+                    .map(|depth| query.depth = depth)
+                    .is_some(),
+                "replies" => value
+                    .parse()
+                    .ok()
+                    .filter(|replies| (1..=MAX_LIMIT).contains(replies))
+                    .map(|replies| query.replies = replies)
+                    .is_some(),
+                "parent" => value
+                    .parse()
+                    .ok()
+                    .map(|parent| query.parent = Some(parent))
+                    .is_some(),
+                "cursor" => Cursor::decode(&value)
+                    .map(|cursor| query.cursor = Some(cursor))
+                    .is_some(),
+                _ => true,
+            };
+            if !valid {
+                return Err(key);
+            }
+        }
+        Ok(query)
+    }
+
+    /// Arranges the comments of one post into reply trees, oldest first, and
+    /// cuts a page of top-level comments, or of the replies to `parent`, out
+    /// of them.
+    pub fn page<'a, I>(&self, comments: I) -> CommentPage<'a>
+    where
+        I: IntoIterator<Item = &'a Comment>,
+    {
+        let mut children: HashMap<Option<Uuid>, Vec<&Comment>> = HashMap::new();
+        for comment in comments {
+            children
+                .entry(comment.parent().cloned())
+                .or_default()
+                .push(comment);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by_key(|c| (*c.datetime(), *c.uuid()));
+        }
+
+        let mut listed: Vec<&Comment> = children
+            .get(&self.parent)
+            .map(|top| {
+                top.iter()
+                    .filter(|c| {
+                        self.cursor.as_ref().is_none_or(|cursor| {
+                            (*c.datetime(), *c.uuid()) > (cursor.datetime, cursor.uuid)
+                        })
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let next_cursor = if listed.len() > self.limit {
+            listed.truncate(self.limit);
+            listed.last().map(|c| {
+                Cursor {
+                    datetime: *c.datetime(),
+                    uuid: *c.uuid(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+
+        CommentPage {
+            comments: listed
+                .into_iter()
+                .map(|c| node(c, &children, self.depth, self.replies))
+                .collect(),
+            next_cursor,
+        }
+    }
+}
+
+fn node<'a>(
+    comment: &'a Comment,
+    children: &HashMap<Option<Uuid>, Vec<&'a Comment>>,
+    depth: usize,
+    max_replies: usize,
+) -> CommentNode<'a> {
+    let replies = children
+        .get(&Some(*comment.uuid()))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    CommentNode {
+        comment,
+        reply_count: replies.len(),
+        replies: if depth == 0 {
+            Vec::new()
+        } else {
+            replies
+                .iter()
+                .take(max_replies)
+                .map(|reply| node(reply, children, depth - 1, max_replies))
+                .collect()
+        },
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::ApiError;
+use crate::models::Post;
+
+use chrono::{DateTime, Utc};
+use iron::headers::{
+    ContentLength, ETag, EntityTag, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use iron::method::Method;
+use iron::typemap::Key;
+use iron::{status, AfterMiddleware, BeforeMiddleware, IronError, IronResult, Request, Response};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Strong entity tag of a representation: a hash of its bytes.
+pub fn etag_for(bytes: &[u8]) -> EntityTag {
+    let digest = Sha256::digest(bytes);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    EntityTag::strong(hex)
+}
+
+/// Entity tag of a post's JSON representation, equal to the `ETag` that
+/// `GET /post/:id` sends for it.
+pub fn post_etag(post: &Post) -> EntityTag {
+    etag_for(&serde_json::to_vec(post).expect("posts serialize"))
+}
+
+/// Weak entity tag of whatever a `GET` reads from the database as it was at
+/// `modified`. The URL and the `Accept` and `Authorization` headers go into
+/// it too, since they choose what part of that state is shown and how.
+pub fn state_etag(req: &Request, modified: &DateTime<Utc>) -> EntityTag {
+    let mut hasher = Sha256::new();
+    hasher.update(modified.to_rfc3339().as_bytes());
+    hasher.update(req.url.as_ref().as_str().as_bytes());
+    for name in ["Accept", "Authorization"] {
+        for value in req.headers.get_raw(name).unwrap_or_default() {
+            hasher.update([0]);
+            hasher.update(value);
+        }
+    }
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    EntityTag::weak(hex)
+}
+
+/// Sets `ETag` and `Last-Modified` on a response read from the database as it
+/// was at `modified`.
+pub fn set_state_validators(req: &Request, res: &mut Response, modified: &DateTime<Utc>) {
+    res.headers.set(ETag(state_etag(req, modified)));
+    set_last_modified(res, modified);
+}
+
+/// Sets `Last-Modified`, at the one-second precision HTTP dates have.
+pub fn set_last_modified(res: &mut Response, datetime: &DateTime<Utc>) {
+    let formatted = datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    if let Ok(date) = formatted.parse::<HttpDate>() {
+        res.headers.set(LastModified(date));
+    }
+}
+
+fn is_unsafe(method: &Method) -> bool {
+    matches!(method, Method::Put | Method::Patch | Method::Delete)
+}
+
+/// `Request::extensions` key holding the `If-Match` header of an update or
+/// delete, which the handler checks against the current entity tag.
+pub struct Precondition;
+
+impl Key for Precondition {
+    type Value = IfMatch;
+}
+
+/// Whether the `If-Match` a request was made with still matches `current`.
+/// Must be called with the resource locked, so that nobody changes it between
+/// the check and the write.
+pub fn precondition_holds(req: &Request, current: &EntityTag) -> bool {
+    match req.extensions.get::<Precondition>() {
+        Some(IfMatch::Any) => true,
+        Some(IfMatch::Items(tags)) => tags.iter().any(|tag| tag.strong_eq(current)),
+        None => false,
+    }
+}
+
+/// Error for an update or delete whose `If-Match` no longer matches.
+pub fn precondition_failed(current: &EntityTag) -> IronError {
+    let mut err: IronError = ApiError::new(
+        status::PreconditionFailed,
+        "precondition_failed",
+        "the resource changed since it was read; fetch it again and retry",
+    )
+    .with_details(json!({ "current_etag": current.to_string() }))
+    .into();
+    err.response.headers.set(ETag(current.clone()));
+    err
+}
+
+/// Refuses updates and deletes that do not say which version of the resource
+/// they were based on, and passes `If-Match` on to the handler.
+pub struct ConditionalBeforeMiddleware;
+
+impl BeforeMiddleware for ConditionalBeforeMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        if !is_unsafe(&req.method) {
+            return Ok(());
+        }
+        match req.headers.get::<IfMatch>().cloned() {
+            Some(if_match) => {
+                req.extensions.insert::<Precondition>(if_match);
+                Ok(())
+            }
+            None => Err(ApiError::new(
+                status::PreconditionRequired,
+                "precondition_required",
+                "updates and deletes need an If-Match header",
+            )
+            .into()),
+        }
+    }
+}
+
+/// Answers `If-None-Match` and `If-Modified-Since` on a successful `GET` with
+/// 304 Not Modified. Handlers set the `ETag` and `Last-Modified` to compare
+/// against from what they read, so bodies are never buffered or hashed here;
+/// responses without them, like exports and event streams, pass through.
+///
+/// `Last-Modified` only has one-second precision, so changes made within the
+/// same second would go unnoticed by `If-Modified-Since`. It is only
+/// consulted for responses without an `ETag`, which changes with every write.
+pub struct ConditionalAfterMiddleware;
+
+impl AfterMiddleware for ConditionalAfterMiddleware {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        if req.method != Method::Get || res.status != Some(status::Ok) {
+            return Ok(res);
+        }
+        let not_modified = match (req.headers.get::<IfNoneMatch>(), res.headers.get::<ETag>()) {
+            (Some(IfNoneMatch::Any), _) => true,
+            (Some(IfNoneMatch::Items(tags)), Some(ETag(etag))) => {
+                tags.iter().any(|tag| tag.weak_eq(etag))
+            }
+            (Some(_), None) | (None, Some(_)) => false,
+            (None, None) => match (
+                req.headers.get::<IfModifiedSince>(),
+                res.headers.get::<LastModified>(),
+            ) {
+                (Some(IfModifiedSince(since)), Some(LastModified(modified))) => modified <= since,
+                _ => false,
+            },
+        };
+
+        if not_modified {
+            res.status = Some(status::NotModified);
+            res.headers.remove::<ContentLength>();
+            res.body = None;
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,263 @@
+use crate::database::Database;
+use crate::limits::{BodyLimit, RateLimit};
+use crate::models::NewPost;
+use crate::storage::FileStorage;
+
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+pub const USAGE: &str = "usage: web_api [--config FILE] [--bind ADDR] [--threads N] \
+[--log-level FILTER] [--storage memory|file] [--data-dir DIR] [--fixtures FILE] \
+[--admin USERNAME:PASSWORD_HASH]... [--cors-origin ORIGIN]... [--read-burst N] [--read-rate N] \
+[--write-burst N] [--write-rate N] [--max-body-bytes N] [--max-import-bytes N] \
+[--max-streams N] [--trash-retention-days N]
+       web_api --hash-password < PASSWORD";
+
+/// Server settings. Read from the TOML file given with `--config`, if any,
+/// with the remaining command-line flags applied on top.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address to listen on.
+    pub bind: String,
+    /// Worker threads; 8 per CPU when unset, as iron picks.
+    pub threads: Option<usize>,
+    /// `env_logger` filter, used unless `RUST_LOG` is set.
+    pub log_level: String,
+    pub storage: StorageConfig,
+    /// Accounts with the admin role, created or updated at startup. No one
+    /// can become an admin by registering.
+    pub admins: Vec<AdminAccount>,
+    /// JSON array of posts to fill an empty database with.
+    pub fixtures: Option<PathBuf>,
+    /// Origins allowed to call the API from a browser.
+    pub cors_origins: Vec<String>,
+    pub limits: LimitsConfig,
+    /// How long a deleted post stays in the trash before it is purged.
+    pub trash_retention_days: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: String::from("localhost:8000"),
+            threads: None,
+            log_level: String::from("info"),
+            storage: StorageConfig::default(),
+            admins: Vec::new(),
+            fixtures: None,
+            cors_origins: Vec::new(),
+            limits: LimitsConfig::default(),
+            trash_retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminAccount {
+    pub username: String,
+    /// As printed by `web_api --hash-password`.
+    pub password_hash: String,
+}
+
+impl FromStr for AdminAccount {
+    type Err = ();
+
+    /// Parses `USERNAME:PASSWORD_HASH`.
+    fn from_str(s: &str) -> Result<AdminAccount, ()> {
+        let (username, password_hash) = s.split_once(':').ok_or(())?;
+        if username.is_empty() || password_hash.is_empty() {
+            return Err(());
+        }
+        Ok(AdminAccount {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    /// Where the file backend keeps its snapshot and change log.
+    pub data_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            backend: Backend::Memory,
+            data_dir: PathBuf::from("data"),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn open_database(&self) -> io::Result<Database> {
+        match self.backend {
+            Backend::Memory => Ok(Database::new()),
+            Backend::File => Database::open(Box::new(FileStorage::new(&self.data_dir)?)),
+        }
+    }
+}
+
+/// Which storage backend to run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Memory,
+    File,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub reads: RateLimit,
+    pub writes: RateLimit,
+    pub max_body_bytes: u64,
+    /// Cap on `POST /admin/import` bodies, which are read as they stream in.
+    pub max_import_bytes: u64,
+    /// Event streams open at once, each holding a worker thread; half the
+    /// worker threads when unset.
+    pub max_streams: Option<usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            reads: RateLimit::DEFAULT_READS,
+            writes: RateLimit::DEFAULT_WRITES,
+            max_body_bytes: BodyLimit::DEFAULT_MAX_BYTES,
+            max_import_bytes: BodyLimit::DEFAULT_MAX_IMPORT_BYTES,
+            max_streams: None,
+        }
+    }
+}
+
+impl Config {
+    /// Number of worker threads to serve requests with.
+    pub fn worker_threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            8 * thread::available_parallelism().map_or(1, |cpus| cpus.get())
+        })
+    }
+
+    /// Number of event streams that may be open at once.
+    pub fn max_streams(&self) -> usize {
+        self.limits
+            .max_streams
+            .unwrap_or_else(|| (self.worker_threads() / 2).max(1))
+    }
+
+    /// Reads a TOML config file; settings it leaves out keep their defaults.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Builds the configuration from command-line arguments: the file named
+    /// by `--config` first, then every other flag overriding it.
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Config, String> {
+        let args: Vec<String> = args.collect();
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or("--config needs a value")?;
+                Config::load(Path::new(path))?
+            }
+            None => Config::default(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--config" => {
+                    value()?;
+                }
+                "--bind" => config.bind = value()?,
+                "--threads" => config.threads = Some(parse(&arg, &value()?)?),
+                "--log-level" => config.log_level = value()?,
+                "--storage" => {
+                    config.storage.backend = match value()?.as_str() {
+                        "memory" => Backend::Memory,
+                        "file" => Backend::File,
+                        other => return Err(format!("unknown storage backend `{}`", other)),
+                    }
+                }
+                "--data-dir" => config.storage.data_dir = value()?.into(),
+                "--fixtures" => config.fixtures = Some(value()?.into()),
+                "--admin" => config.admins.push(parse(&arg, &value()?)?),
+                "--cors-origin" => config.cors_origins.push(value()?),
+                "--read-burst" => config.limits.reads.burst = parse(&arg, &value()?)?,
+                "--read-rate" => config.limits.reads.per_second = parse(&arg, &value()?)?,
+                "--write-burst" => config.limits.writes.burst = parse(&arg, &value()?)?,
+                "--write-rate" => config.limits.writes.per_second = parse(&arg, &value()?)?,
+                "--max-body-bytes" => config.limits.max_body_bytes = parse(&arg, &value()?)?,
+                "--max-import-bytes" => {
+                    config.limits.max_import_bytes = parse(&arg, &value()?)?
+                }
+                "--max-streams" => config.limits.max_streams = Some(parse(&arg, &value()?)?),
+                "--trash-retention-days" => {
+                    config.trash_retention_days = parse(&arg, &value()?)?
+                }
+                other => return Err(format!("unknown argument `{}`", other)),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.threads == Some(0) {
+            return Err(String::from("threads must be at least 1"));
+        }
+        for (name, limit) in [("reads", &self.limits.reads), ("writes", &self.limits.writes)] {
+            if !(limit.burst >= 1.0 && limit.per_second > 0.0) {
+                return Err(format!(
+                    "{} limit needs a burst of at least 1 and a positive rate",
+                    name
+                ));
+            }
+        }
+        if let Some(max_streams) = self.limits.max_streams {
+            if max_streams == 0 || max_streams >= self.worker_threads() {
+                return Err(String::from(
+                    "max_streams must be at least 1 and fewer than the worker threads",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for {}", value, flag))
+}
+
+/// Reads a fixtures file: a JSON array of posts in the shape `POST /post`
+/// accepts, each with an author.
+pub fn load_fixtures(path: &Path) -> Result<Vec<NewPost>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let posts: Vec<NewPost> =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for (i, post) in posts.iter().enumerate() {
+        let invalid = post.invalid_fields();
+        if !invalid.is_empty() {
+            return Err(format!(
+                "{}: post {} has invalid fields: {}",
+                path.display(),
+                i,
+                invalid.join(", ")
+            ));
+        }
+    }
+    Ok(posts)
+}
@@ -0,0 +1,149 @@
+use crate::error::ApiError;
+use crate::negotiation::add_vary;
+use crate::routes::RouteTable;
+
+use iron::headers::{
+    AccessControlAllowMethods, AccessControlAllowOrigin, AccessControlMaxAge,
+    AccessControlRequestMethod, Allow,
+};
+use iron::method::Method;
+use iron::{status, AfterMiddleware, Handler, IronError, IronResult, Request, Response};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Request headers a browser may send cross-origin.
+const ALLOWED_HEADERS: &str = "Authorization, Content-Type, If-Match, If-None-Match, \
+If-Modified-Since, X-Request-Id";
+
+/// Response headers a cross-origin script may read.
+const EXPOSED_HEADERS: &str = "ETag, Last-Modified, Location, Retry-After, WWW-Authenticate, \
+X-Request-Id";
+
+/// How long, in seconds, a browser may cache a preflight answer.
+const PREFLIGHT_MAX_AGE: u32 = 600;
+
+/// Cross-origin access for the origins on an allow-list; `*` on the list
+/// allows every origin, answering `Access-Control-Allow-Origin: *`. As an
+/// `AfterMiddleware` it adds the `Access-Control-*` headers to responses,
+/// errors included, for requests from an allowed origin.
+#[derive(Debug)]
+pub struct Cors {
+    origins: Vec<String>,
+    table: RouteTable,
+}
+
+impl Cors {
+    pub fn new(origins: Vec<String>, table: RouteTable) -> Cors {
+        Cors { origins, table }
+    }
+
+    /// The `Origin` of a request, if it is on the allow-list.
+    fn allowed_origin(&self, req: &Request) -> Option<String> {
+        let origin = origin(req)?;
+        (self.allows_any() || self.origins.contains(&origin)).then_some(origin)
+    }
+
+    /// Methods there is a route for at `path`.
+    fn methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = Vec::new();
+        for route in self.table.routes() {
+            if route.method != Method::Options
+                && !methods.contains(&route.method)
+                && self.table.recognize(&route.method, path).is_some()
+            {
+                methods.push(route.method.clone());
+            }
+        }
+        if methods.contains(&Method::Get) {
+            methods.push(Method::Head);
+        }
+        methods.push(Method::Options);
+        methods
+    }
+
+    fn allows_any(&self) -> bool {
+        self.origins.iter().any(|allowed| allowed == "*")
+    }
+
+    fn set_headers(&self, req: &Request, res: &mut Response) {
+        // Every origin gets the same literal `*`, whether it sent `Origin` or
+        // not, so the response does not vary by origin.
+        if self.allows_any() {
+            res.headers.set(AccessControlAllowOrigin::Any);
+            res.headers
+                .set_raw("Access-Control-Expose-Headers", vec![EXPOSED_HEADERS.into()]);
+            return;
+        }
+        if let Some(origin) = self.allowed_origin(req) {
+            res.headers.set(AccessControlAllowOrigin::Value(origin));
+            res.headers
+                .set_raw("Access-Control-Expose-Headers", vec![EXPOSED_HEADERS.into()]);
+        }
+        // Whether the headers are set depends on the origin, so caches must
+        // keep responses for different origins apart.
+        add_vary(res, "Origin");
+    }
+}
+
+impl AfterMiddleware for Cors {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.set_headers(req, &mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        self.set_headers(req, &mut err.response);
+        Err(err)
+    }
+}
+
+fn origin(req: &Request) -> Option<String> {
+    let raw = req.headers.get_raw("Origin")?;
+    let value = raw.first()?;
+    String::from_utf8(value.clone()).ok()
+}
+
+/// Answers `OPTIONS` for every path a route is registered on, including CORS
+/// preflight requests from allowed origins.
+pub struct PreflightHandler {
+    cors: Arc<Cors>,
+}
+
+impl PreflightHandler {
+    pub fn new(cors: Arc<Cors>) -> PreflightHandler {
+        PreflightHandler { cors }
+    }
+}
+
+impl Handler for PreflightHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let path = format!("/{}", req.url.path().join("/"));
+        let methods = self.cors.methods(&path);
+        if methods.len() == 1 {
+            return Err(
+                ApiError::new(status::NotFound, "not_found", "no route matches this path")
+                    .with_details(json!(path))
+                    .into(),
+            );
+        }
+
+        let mut res = Response::with(status::NoContent);
+        if origin(req).is_some() && req.headers.has::<AccessControlRequestMethod>() {
+            if self.cors.allowed_origin(req).is_none() {
+                return Err(ApiError::new(
+                    status::Forbidden,
+                    "origin_not_allowed",
+                    "this origin may not call the API",
+                )
+                .with_details(json!(origin(req)))
+                .into());
+            }
+            res.headers.set(AccessControlAllowMethods(methods.clone()));
+            res.headers
+                .set_raw("Access-Control-Allow-Headers", vec![ALLOWED_HEADERS.into()]);
+            res.headers.set(AccessControlMaxAge(PREFLIGHT_MAX_AGE));
+        }
+        res.headers.set(Allow(methods));
+        Ok(res)
+    }
+}
@@ -1,22 +1,551 @@
-use crate::models::Post;
+use crate::config::AdminAccount;
+use crate::events::{EventKind, EventLog};
+use crate::models::{
+    self, Comment, Edit, NewPost, Post, PostStatus, Revision, Role, User, Webhook,
+};
+use crate::search::{Query, SearchIndex};
+use crate::storage::{Change, MemoryStorage, State, Storage};
+use crate::tags::{TagCount, TagIndex};
+use crate::webhooks::{Delivery, DeliveryStatus, StoredDelivery};
 
-#[derive(Debug,Clone)]
+use chrono::{DateTime, Utc};
+use log::error;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Debug)]
 pub struct Database{
-    posts: Vec<Post>,
+    state: State,
+    storage: Box<dyn Storage>,
+    /// Position of every post in `state.posts`.
+    positions: HashMap<Uuid, usize>,
+    /// Search and tag indexes over the published posts only.
+    index: SearchIndex,
+    tags: TagIndex,
+    /// When the last change was committed, or when the database was opened.
+    last_modified: DateTime<Utc>,
+    /// Changes to posts committed since the database was opened.
+    events: Arc<EventLog>,
+}
+
+/// Why a mutation was refused.
+#[derive(Debug)]
+pub enum DatabaseError {
+    NotFound,
+    /// The post exists but has no revision with this number.
+    NoSuchRevision,
+    NoSuchWebhook,
+    /// The post does not exist or is not in the trash.
+    NotInTrash,
+    /// Something with the same key is already stored.
+    AlreadyExists,
+    /// The named fields are missing, blank, of the wrong type or read-only.
+    InvalidFields(Vec<String>),
+    /// The storage backend failed to record the change.
+    Storage(io::Error),
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::new()
+    }
 }
 
 impl Database {
+    /// An empty database that is not persisted anywhere.
     pub fn new() -> Database{
         Database{
-            posts: Vec::new(),
+            state: State::default(),
+            storage: Box::new(MemoryStorage),
+            positions: HashMap::new(),
+            index: SearchIndex::new(),
+            tags: TagIndex::new(),
+            last_modified: Utc::now(),
+            events: Arc::new(EventLog::default()),
+        }
+    }
+
+    /// Opens a database on top of a storage backend, loading what it holds.
+    pub fn open(mut storage: Box<dyn Storage>) -> io::Result<Database> {
+        let mut state = storage.load()?;
+        for post in &mut state.posts {
+            post.render_body();
+        }
+        let mut index = SearchIndex::new();
+        let mut tags = TagIndex::new();
+        for post in state.posts.iter().filter(|post| is_listed(post)) {
+            index.insert(post);
+            tags.insert(post);
+        }
+        let mut database = Database {
+            state,
+            storage,
+            positions: HashMap::new(),
+            index,
+            tags,
+            last_modified: Utc::now(),
+            events: Arc::new(EventLog::default()),
+        };
+        database.reindex_positions();
+        Ok(database)
+    }
+
+    /// Stores a post as the work of its author.
+    pub fn add_post(&mut self, post: Post) -> Result<(), DatabaseError> {
+        let edit = Edit::new(post.author(), None);
+        self.save_post(post, edit)
+    }
+
+    /// Stores a new post, or replaces the one with the same uuid, recording
+    /// a revision made by `edit` unless only the status changed.
+    pub fn save_post(&mut self, post: Post, edit: Edit) -> Result<(), DatabaseError> {
+        let mut revisions = Vec::new();
+        if self.revisions(post.uuid()).is_empty() {
+            // A post stored before revisions were kept gets its current
+            // version recorded first, so that it is not lost.
+            if let Ok(current) = self.find_any(post.uuid()) {
+                let baseline = Edit::new(current.author(), None);
+                revisions.push(Revision::new(1, current, baseline));
+            }
+        }
+        let previous = revisions.last().or(self.revisions(post.uuid()).last());
+        if !previous.is_some_and(|revision| revision.records(&post)) {
+            let number = previous.map_or(0, Revision::number) + 1;
+            revisions.push(Revision::new(number, &post, edit));
+        }
+        self.commit(Change::Put { post, revisions })
+    }
+
+    /// Every post that is not in the trash, in the order they were stored.
+    pub fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.state.posts.iter().filter(|post| !post.is_trashed())
+    }
+
+    /// When anything in the database last changed, as far as this process
+    /// knows.
+    pub fn last_modified(&self) -> &DateTime<Utc> {
+        &self.last_modified
+    }
+
+    /// Hands the full state to storage so nothing is left only in the
+    /// change log.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.storage.flush(&self.state)
+    }
+
+    /// The log every committed change to a post is published to.
+    pub fn events(&self) -> Arc<EventLog> {
+        self.events.clone()
+    }
+
+
+    pub fn post(&self, uuid: &Uuid) -> Option<&Post> {
+        self.find(uuid).ok()
+    }
+
+    /// Replaces the title, body and author of a post.
+    pub fn update_post(
+        &mut self,
+        uuid: &Uuid,
+        new_post: NewPost,
+        editor: &str,
+    ) -> Result<Post, DatabaseError> {
+        let invalid = new_post.invalid_fields();
+        if !invalid.is_empty() {
+            return Err(DatabaseError::InvalidFields(
+                invalid.into_iter().map(String::from).collect(),
+            ));
+        }
+
+        let mut post = self.find(uuid)?.clone();
+        let edit = Edit::new(editor, new_post.note.clone());
+        post.replace(new_post);
+        self.save_post(post.clone(), edit)?;
+        Ok(post)
+    }
+
+    /// Applies a JSON merge patch to a post.
+    pub fn patch_post(
+        &mut self,
+        uuid: &Uuid,
+        patch: &Value,
+        editor: &str,
+    ) -> Result<Post, DatabaseError> {
+        let post = self.find(uuid)?;
+        let merged = models::merge_patch(post, patch).map_err(DatabaseError::InvalidFields)?;
+        let new_post: NewPost = serde_json::from_value(merged)
+            .map_err(|e| DatabaseError::InvalidFields(vec![e.to_string()]))?;
+        self.update_post(uuid, new_post, editor)
+    }
+
+    /// Every revision of a post, oldest first.
+    pub fn revisions(&self, uuid: &Uuid) -> &[Revision] {
+        self.state.revisions.get(uuid).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn revision(&self, uuid: &Uuid, number: u32) -> Result<&Revision, DatabaseError> {
+        self.find(uuid)?;
+        let revisions = self.revisions(uuid);
+        revisions
+            .binary_search_by_key(&number, Revision::number)
+            .map(|index| &revisions[index])
+            .map_err(|_| DatabaseError::NoSuchRevision)
+    }
+
+    /// Brings a post back to how revision `number` left it, as a new
+    /// revision.
+    pub fn restore_revision(
+        &mut self,
+        uuid: &Uuid,
+        number: u32,
+        edit: Edit,
+    ) -> Result<Post, DatabaseError> {
+        let mut new_post = self.revision(uuid, number)?.editable();
+        new_post.note = edit.note.or_else(|| Some(format!("Restored revision {}", number)));
+        self.update_post(uuid, new_post, &edit.editor)
+    }
+
+    /// Publishes every scheduled post whose time has come, as `now`, and
+    /// returns their uuids.
+    pub fn publish_due(&mut self, now: DateTime<Utc>) -> Result<Vec<Uuid>, DatabaseError> {
+        let due: Vec<Post> = self
+            .state
+            .posts
+            .iter()
+            .filter(|post| post.is_due(&now))
+            .cloned()
+            .collect();
+        let mut published = Vec::with_capacity(due.len());
+        for mut post in due {
+            post.publish(now);
+            // Only the status changes, so no revision is recorded.
+            let edit = Edit::new(post.author(), None);
+            published.push(*post.uuid());
+            self.save_post(post, edit)?;
+        }
+        Ok(published)
+    }
+
+    /// The earliest time a scheduled post is due to be published.
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.state
+            .posts
+            .iter()
+            .filter(|post| post.status() == PostStatus::Scheduled && !post.is_trashed())
+            .filter_map(|post| post.publish_at().copied())
+            .min()
+    }
+
+    /// Moves a post to the trash, where it is hidden from everything but
+    /// `trash` and `restore_post` until it is purged. Returns the post as
+    /// it was.
+    pub fn delete_post(&mut self, uuid: &Uuid) -> Result<Post, DatabaseError> {
+        let post = self.find(uuid)?.clone();
+        let mut trashed = post.clone();
+        trashed.move_to_trash(Utc::now());
+        // Moving in and out of the trash does not change the content, so
+        // it is not recorded as a revision.
+        self.commit(Change::Put { post: trashed, revisions: Vec::new() })?;
+        Ok(post)
+    }
+
+    /// Every post in the trash, most recently deleted first.
+    pub fn trash(&self) -> Vec<&Post> {
+        let mut trashed: Vec<&Post> =
+            self.state.posts.iter().filter(|post| post.is_trashed()).collect();
+        trashed.sort_by(|a, b| b.deleted_at().cmp(&a.deleted_at()));
+        trashed
+    }
+
+    pub fn trashed(&self, uuid: &Uuid) -> Result<&Post, DatabaseError> {
+        self.find_any(uuid)
+            .ok()
+            .filter(|post| post.is_trashed())
+            .ok_or(DatabaseError::NotInTrash)
+    }
+
+    /// Takes a post out of the trash, back in the state it was deleted in.
+    pub fn restore_post(&mut self, uuid: &Uuid) -> Result<Post, DatabaseError> {
+        let mut post = self.trashed(uuid)?.clone();
+        post.restore_from_trash();
+        self.commit(Change::Put { post: post.clone(), revisions: Vec::new() })?;
+        Ok(post)
+    }
+
+    /// Removes a post in the trash for good, with its comments and
+    /// revisions.
+    pub fn purge_post(&mut self, uuid: &Uuid) -> Result<Post, DatabaseError> {
+        let post = self.trashed(uuid)?.clone();
+        self.commit(Change::Delete { uuid: *uuid })?;
+        Ok(post)
+    }
+
+    /// Purges every post that went in the trash at or before `cutoff`, in
+    /// a single change, and returns their uuids.
+    pub fn purge_trash(&mut self, cutoff: DateTime<Utc>) -> Result<Vec<Uuid>, DatabaseError> {
+        let expired: Vec<Uuid> = self
+            .state
+            .posts
+            .iter()
+            .filter(|post| post.deleted_at().is_some_and(|at| *at <= cutoff))
+            .map(|post| *post.uuid())
+            .collect();
+        if !expired.is_empty() {
+            self.commit(Change::Purge { uuids: expired.clone() })?;
+        }
+        Ok(expired)
+    }
+
+    /// When the post that has been in the trash the longest was deleted.
+    pub fn oldest_trashed(&self) -> Option<DateTime<Utc>> {
+        self.state.posts.iter().filter_map(|post| post.deleted_at().copied()).min()
+    }
+
+    /// Full-text search over the titles and bodies of published posts, best
+    /// match first.
+    pub fn search(&self, query: &Query) -> Vec<(&Post, f64)> {
+        self.index
+            .search(query)
+            .into_iter()
+            .filter_map(|(uuid, score)| self.find(&uuid).ok().map(|post| (post, score)))
+            .collect()
+    }
+
+    /// Stores a new user; usernames are unique.
+    pub fn add_user(&mut self, user: User) -> Result<(), DatabaseError> {
+        if self.user(user.username()).is_some() {
+            return Err(DatabaseError::AlreadyExists);
+        }
+        self.commit(Change::PutUser { user })
+    }
+
+    /// Makes the configured accounts admins, creating them if need be and
+    /// setting their passwords, and takes the admin role away from every
+    /// other account.
+    pub fn set_admins(&mut self, admins: &[AdminAccount]) -> Result<(), DatabaseError> {
+        let demoted: Vec<User> = self
+            .state
+            .users
+            .iter()
+            .filter(|user| user.role() == Role::Admin)
+            .filter(|user| admins.iter().all(|admin| admin.username != user.username()))
+            .cloned()
+            .collect();
+        for mut user in demoted {
+            user.set_role(Role::User);
+            self.commit(Change::PutUser { user })?;
         }
+        for admin in admins {
+            let current = self.user(&admin.username);
+            let unchanged = current.is_some_and(|user| {
+                user.role() == Role::Admin && user.password_hash() == admin.password_hash
+            });
+            if unchanged {
+                continue;
+            }
+            let mut user = current.cloned().unwrap_or_else(|| {
+                User::new(&admin.username, admin.password_hash.clone(), Role::Admin)
+            });
+            user.set_role(Role::Admin);
+            user.set_password_hash(admin.password_hash.clone());
+            self.commit(Change::PutUser { user })?;
+        }
+        Ok(())
+    }
+
+    pub fn user(&self, username: &str) -> Option<&User> {
+        self.state.users.iter().find(|user| user.username() == username)
+    }
+
+    /// Stores a comment on an existing post. A reply must answer a comment on
+    /// the same post.
+    pub fn add_comment(&mut self, comment: Comment) -> Result<(), DatabaseError> {
+        self.find(comment.post_uuid())?;
+        if comment.body().trim().is_empty() {
+            return Err(DatabaseError::InvalidFields(vec!["body".to_string()]));
+        }
+        if let Some(parent) = comment.parent() {
+            let parent_on_post = self
+                .comments(comment.post_uuid())
+                .iter()
+                .any(|c| c.uuid() == parent);
+            if !parent_on_post {
+                return Err(DatabaseError::InvalidFields(vec!["parent".to_string()]));
+            }
+        }
+        self.commit(Change::PutComment { comment })
+    }
+
+    pub fn add_webhook(&mut self, webhook: Webhook) -> Result<(), DatabaseError> {
+        self.commit(Change::PutWebhook { webhook })
+    }
+
+    /// Every webhook, in the order they were registered.
+    pub fn webhooks(&self) -> &[Webhook] {
+        &self.state.webhooks
+    }
+
+    pub fn webhook(&self, id: &Uuid) -> Result<&Webhook, DatabaseError> {
+        self.state
+            .webhooks
+            .iter()
+            .find(|webhook| webhook.id() == id)
+            .ok_or(DatabaseError::NoSuchWebhook)
     }
 
-    pub fn add_post(&mut self, post: Post){
-        self.posts.push(post);
+    /// Removes a webhook and forgets its deliveries; pending ones are not
+    /// sent.
+    pub fn delete_webhook(&mut self, id: &Uuid) -> Result<Webhook, DatabaseError> {
+        let webhook = self.webhook(id)?.clone();
+        self.commit(Change::DeleteWebhook { id: *id })?;
+        Ok(webhook)
     }
 
-    pub fn posts(&self) -> &Vec<Post>{
-        &self.posts
+    /// The recent deliveries to a webhook, newest first.
+    pub fn deliveries(&self, webhook_id: &Uuid) -> Vec<Delivery> {
+        self.state
+            .deliveries
+            .iter()
+            .rev()
+            .filter(|stored| stored.webhook_id == *webhook_id)
+            .map(|stored| stored.delivery.clone())
+            .collect()
     }
-}
\ No newline at end of file
+
+    pub fn delivery(&self, webhook_id: &Uuid, id: &Uuid) -> Option<&StoredDelivery> {
+        self.state
+            .deliveries
+            .iter()
+            .find(|stored| stored.webhook_id == *webhook_id && stored.delivery.id == *id)
+    }
+
+    /// Deliveries still to be attempted, oldest first.
+    pub fn pending_deliveries(&self) -> impl Iterator<Item = &StoredDelivery> {
+        self.state
+            .deliveries
+            .iter()
+            .filter(|stored| stored.delivery.status == DeliveryStatus::Pending)
+    }
+
+    /// Stores a new delivery or how the latest attempt at one went.
+    pub fn record_delivery(&mut self, delivery: StoredDelivery) -> Result<(), DatabaseError> {
+        self.commit(Change::PutDelivery { delivery })
+    }
+
+    /// Every comment on a post, replies included, in insertion order.
+    pub fn comments(&self, post_uuid: &Uuid) -> &[Comment] {
+        self.state.comments.get(post_uuid).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every tag on a published post with the number of published posts
+    /// carrying it.
+    pub fn tag_counts(&self) -> Vec<TagCount<'_>> {
+        self.tags.counts()
+    }
+
+    /// Published posts matching a tag expression; see
+    /// `TagIndex::matching`.
+    pub fn tagged(&self, clauses: &[Vec<String>]) -> Vec<&Post> {
+        self.tags
+            .matching(clauses)
+            .iter()
+            .filter_map(|uuid| self.find(uuid).ok())
+            .collect()
+    }
+
+    /// The post with this uuid, unless it is in the trash.
+    fn find(&self, uuid: &Uuid) -> Result<&Post, DatabaseError> {
+        self.find_any(uuid)
+            .ok()
+            .filter(|post| !post.is_trashed())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    fn find_any(&self, uuid: &Uuid) -> Result<&Post, DatabaseError> {
+        self.positions
+            .get(uuid)
+            .map(|&position| &self.state.posts[position])
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    fn reindex_positions(&mut self) {
+        self.positions = self
+            .state
+            .posts
+            .iter()
+            .enumerate()
+            .map(|(position, post)| (*post.uuid(), position))
+            .collect();
+    }
+
+    /// Records a change with the storage backend and, once it is durable,
+    /// applies it in memory and publishes it; fails only if recording does.
+    /// Events follow the feed: a post is created in it when it gets published
+    /// or restored from the trash, and deleted from it when it goes back to
+    /// being a draft, is archived or is moved to the trash.
+    fn commit(&mut self, change: Change) -> Result<(), DatabaseError> {
+        self.storage.record(&change).map_err(DatabaseError::Storage)?;
+        let event = match &change {
+            Change::Put { post, .. } => {
+                let was_listed = self.find_any(post.uuid()).is_ok_and(is_listed);
+                if is_listed(post) {
+                    self.index.insert(post);
+                    self.tags.insert(post);
+                } else {
+                    self.index.remove(post.uuid());
+                    self.tags.remove(post.uuid());
+                }
+                // A new post is appended to the list.
+                let next = self.state.posts.len();
+                self.positions.entry(*post.uuid()).or_insert(next);
+                let kind = match (was_listed, is_listed(post)) {
+                    (false, true) => Some(EventKind::Created),
+                    (true, true) => Some(EventKind::Updated),
+                    (true, false) => Some(EventKind::Deleted),
+                    (false, false) => None,
+                };
+                kind.map(|kind| (kind, post.clone()))
+            }
+            Change::Delete { uuid } => {
+                self.index.remove(uuid);
+                self.tags.remove(uuid);
+                self.find_any(uuid)
+                    .ok()
+                    .filter(|post| is_listed(post))
+                    .map(|post| (EventKind::Deleted, post.clone()))
+            }
+            // Only posts in the trash are purged, and those are neither
+            // indexed nor listed.
+            Change::Purge { .. } => None,
+            Change::PutUser { .. }
+            | Change::PutComment { .. }
+            | Change::PutWebhook { .. }
+            | Change::DeleteWebhook { .. }
+            | Change::PutDelivery { .. } => None,
+        };
+        let deleted = matches!(change, Change::Delete { .. } | Change::Purge { .. });
+        change.apply(&mut self.state);
+        if deleted {
+            self.reindex_positions();
+        }
+        if let Some((kind, post)) = event {
+            self.events.publish(kind, &post);
+        }
+        self.last_modified = Utc::now();
+        // The change is durable once recorded, so a failed compaction does
+        // not fail it. The backend still holds everything it needs and tries
+        // again after the next change, or on `flush`.
+        if let Err(e) = self.storage.compact(&self.state) {
+            error!("unable to compact storage: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Whether a post shows up in the feed, by tag and in search.
+fn is_listed(post: &Post) -> bool {
+    post.status() == PostStatus::Published && !post.is_trashed()
+}
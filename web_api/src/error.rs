@@ -0,0 +1,231 @@
+use crate::database::DatabaseError;
+use crate::logging::RequestInfo;
+
+use iron::headers::{ContentLength, ContentType};
+use iron::typemap::Key;
+use iron::{status, AfterMiddleware, IronError, IronResult, Request, Response};
+use log::error;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// An error answered to the client. Handlers and middleware return it, or
+/// turn it into a `Response` or `IronError`; `ErrorAfterMiddleware` renders
+/// the body, once the request id is known.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: status::Status,
+    code: &'static str,
+    message: Cow<'static, str>,
+    details: Value,
+    /// What actually went wrong, for the log only.
+    cause: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(
+        status: status::Status,
+        code: &'static str,
+        message: impl Into<Cow<'static, str>>,
+    ) -> ApiError {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            details: Value::Null,
+            cause: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> ApiError {
+        self.details = details;
+        self
+    }
+
+    /// A 500 that tells the client nothing about `cause`, which is logged.
+    pub fn internal(cause: impl fmt::Display) -> ApiError {
+        ApiError {
+            cause: Some(cause.to_string()),
+            ..ApiError::new(
+                status::InternalServerError,
+                "internal_error",
+                "the server failed to handle this request",
+            )
+        }
+    }
+
+    /// A generic error for a status that reached the client without a body,
+    /// such as the router's 404.
+    pub fn from_status(status: status::Status) -> ApiError {
+        let reason = status.canonical_reason().unwrap_or("error");
+        let code = match status {
+            status::NotFound => "not_found",
+            status::MethodNotAllowed => "method_not_allowed",
+            _ if status.is_server_error() => return ApiError::internal(reason),
+            _ => "error",
+        };
+        ApiError::new(status, code, reason.to_lowercase())
+    }
+
+    pub fn status(&self) -> status::Status {
+        self.status
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    fn body(&self, request_id: Option<&str>) -> String {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+                details: self.details.clone(),
+                request_id,
+            },
+        };
+        serde_json::to_string(&body).expect("error bodies serialize")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl Error for ApiError {}
+
+/// `Response::extensions` key of the error a response is yet to be
+/// rendered from.
+impl Key for ApiError {
+    type Value = ApiError;
+}
+
+impl From<ApiError> for Response {
+    fn from(error: ApiError) -> Response {
+        let mut res = Response::with(error.status);
+        res.extensions.insert::<ApiError>(error);
+        res
+    }
+}
+
+impl From<ApiError> for IronError {
+    fn from(error: ApiError) -> IronError {
+        IronError {
+            error: Box::new(error.clone()),
+            response: error.into(),
+        }
+    }
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(error: DatabaseError) -> ApiError {
+        match error {
+            DatabaseError::NotFound => {
+                ApiError::new(status::NotFound, "not_found", "no post with this id")
+            }
+            DatabaseError::NoSuchRevision => ApiError::new(
+                status::NotFound,
+                "not_found",
+                "the post has no revision with this number",
+            ),
+            DatabaseError::NoSuchWebhook => {
+                ApiError::new(status::NotFound, "not_found", "no webhook with this id")
+            }
+            DatabaseError::NotInTrash => {
+                ApiError::new(status::NotFound, "not_found", "no post with this id in the trash")
+            }
+            DatabaseError::AlreadyExists => ApiError::new(
+                status::Conflict,
+                "already_exists",
+                "this name is already taken",
+            ),
+            DatabaseError::InvalidFields(fields) => ApiError::new(
+                status::UnprocessableEntity,
+                "invalid_fields",
+                "the listed fields are missing, blank, of the wrong type or read-only",
+            )
+            .with_details(json!(fields)),
+            DatabaseError::Storage(e) => ApiError {
+                code: "storage_error",
+                message: Cow::Borrowed("the change could not be stored"),
+                ..ApiError::internal(e)
+            },
+        }
+    }
+}
+
+impl From<DatabaseError> for IronError {
+    fn from(error: DatabaseError) -> IronError {
+        ApiError::from(error).into()
+    }
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody<'a> {
+    pub error: ErrorDetail<'a>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorDetail<'a> {
+    /// Stable, machine-readable identifier of the error.
+    pub code: &'a str,
+    /// Human-readable explanation.
+    pub message: &'a str,
+    /// Error-specific context, e.g. the names of invalid fields.
+    pub details: Value,
+    /// The `X-Request-Id` of the request, to quote when reporting a problem.
+    pub request_id: Option<&'a str>,
+}
+
+/// Renders the body of every error response from its `ApiError`, and gives
+/// the bare ones (like the router's 404) a body of the same shape. Link it
+/// before anything that needs the final body.
+pub struct ErrorAfterMiddleware;
+
+impl ErrorAfterMiddleware {
+    fn render(req: &Request, res: &mut Response) {
+        let error = match res.extensions.remove::<ApiError>() {
+            Some(error) => error,
+            None => match res.status {
+                Some(status)
+                    if (status.is_client_error() || status.is_server_error())
+                        && res.body.is_none() =>
+                {
+                    ApiError::from_status(status)
+                }
+                _ => return,
+            },
+        };
+        let request_id = req.extensions.get::<RequestInfo>().map(|info| info.id.as_str());
+        if let Some(cause) = &error.cause {
+            error!(
+                target: "web_api::errors",
+                "{}",
+                json!({ "request_id": request_id, "code": error.code, "cause": cause })
+            );
+        }
+        res.status = Some(error.status);
+        let body = error.body(request_id);
+        res.headers.set(ContentType::json());
+        res.headers.set(ContentLength(body.len() as u64));
+        res.body = Some(Box::new(body));
+    }
+}
+
+impl AfterMiddleware for ErrorAfterMiddleware {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        ErrorAfterMiddleware::render(req, &mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        ErrorAfterMiddleware::render(req, &mut err.response);
+        Err(err)
+    }
+}
@@ -0,0 +1,267 @@
+use crate::models::Post;
+
+use iron::response::WriteBody;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Number of past events kept for clients that reconnect.
+pub const EVENT_LOG_CAPACITY: usize = 1000;
+
+/// How long a stream stays silent before it sends a comment, which keeps
+/// proxies from timing it out and notices clients that went away.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Milliseconds an `EventSource` waits before reconnecting.
+pub const RETRY_MS: u64 = 3000;
+
+/// What happened to a post.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 3] = [EventKind::Created, EventKind::Updated, EventKind::Deleted];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Created => "created",
+            EventKind::Updated => "updated",
+            EventKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// One change to the posts, numbered in the order it was committed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub id: u64,
+    pub kind: EventKind,
+    /// The post as JSON, or only its uuid once it is deleted.
+    pub data: String,
+}
+
+impl Event {
+    /// Writes the event in the `text/event-stream` format.
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id,
+            self.kind.name(),
+            self.data
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    events: VecDeque<Event>,
+    /// Id of the newest event, 0 before the first.
+    last_id: u64,
+}
+
+impl Log {
+    /// Events after `last_id`, or `None` when some of them were already
+    /// dropped or `last_id` was never handed out by this process.
+    fn since(&self, last_id: u64) -> Option<Vec<Event>> {
+        if last_id > self.last_id {
+            return None;
+        }
+        let oldest = self.events.front().map_or(self.last_id + 1, |event| event.id);
+        if last_id + 1 < oldest {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .filter(|event| event.id > last_id)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// The most recent changes to the posts, for streaming to clients. Keeps at
+/// most `capacity` of them, so that reconnecting clients can catch up.
+#[derive(Debug)]
+pub struct EventLog {
+    log: Mutex<Log>,
+    published: Condvar,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> EventLog {
+        EventLog {
+            log: Mutex::new(Log::default()),
+            published: Condvar::new(),
+            capacity,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn publish(&self, kind: EventKind, post: &Post) {
+        let data = match kind {
+            EventKind::Deleted => json!({ "uuid": post.uuid() }).to_string(),
+            _ => serde_json::to_string(post).expect("posts serialize"),
+        };
+        let mut log = self.lock();
+        log.last_id += 1;
+        let id = log.last_id;
+        log.events.push_back(Event { id, kind, data });
+        while log.events.len() > self.capacity {
+            log.events.pop_front();
+        }
+        drop(log);
+        self.published.notify_all();
+    }
+
+    /// Id of the newest event, 0 before the first.
+    pub fn last_id(&self) -> u64 {
+        self.lock().last_id
+    }
+
+    /// Events after `last_id`; see `wait`.
+    pub fn since(&self, last_id: u64) -> Option<Vec<Event>> {
+        self.lock().since(last_id)
+    }
+
+    /// Waits up to `timeout` for events after `last_id` and returns them,
+    /// possibly none. `None` means some were missed, because they no longer
+    /// fit in the log or `last_id` comes from another process.
+    pub fn wait(&self, last_id: u64, timeout: Duration) -> Option<Vec<Event>> {
+        let log = self.lock();
+        let (log, _) = self
+            .published
+            .wait_timeout_while(log, timeout, |log| log.last_id == last_id)
+            .unwrap_or_else(PoisonError::into_inner);
+        log.since(last_id)
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        EventLog::new(EVENT_LOG_CAPACITY)
+    }
+}
+
+/// Caps the number of event streams open at once. Each one holds on to a
+/// worker thread for as long as its client stays connected, so without a cap
+/// idle subscribers could leave none for other requests.
+#[derive(Debug)]
+pub struct StreamSlots {
+    open: AtomicUsize,
+    max: usize,
+}
+
+impl StreamSlots {
+    pub fn new(max: usize) -> StreamSlots {
+        StreamSlots {
+            open: AtomicUsize::new(0),
+            max,
+        }
+    }
+
+    /// Takes a slot for a new stream, or `None` if `max` are open already.
+    pub fn acquire(self: &Arc<StreamSlots>) -> Option<StreamSlot> {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| StreamSlot(self.clone()))
+    }
+
+    /// Number of streams open now.
+    pub fn open(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+/// A place among the open streams, given back when dropped.
+#[derive(Debug)]
+pub struct StreamSlot(Arc<StreamSlots>);
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Body of a `text/event-stream` response. Sends the events after
+/// `last_id`, then every new one as it is published, until the client goes
+/// away. A client that missed events gets a `reset` event instead, telling
+/// it to reload the feed.
+///
+/// The stream holds on to one of the server's worker threads for as long as
+/// the client stays connected, and to its `StreamSlot` until it is dropped.
+pub struct EventStream {
+    log: Arc<EventLog>,
+    last_id: u64,
+    _slot: StreamSlot,
+}
+
+impl EventStream {
+    /// A stream of the events after `last_id`, or of events from now on.
+    pub fn new(log: Arc<EventLog>, last_id: Option<u64>, slot: StreamSlot) -> EventStream {
+        let last_id = last_id.unwrap_or_else(|| log.last_id());
+        EventStream {
+            log,
+            last_id,
+            _slot: slot,
+        }
+    }
+
+    fn pump(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "retry: {}\n\n", RETRY_MS)?;
+        let mut events = self.log.since(self.last_id);
+        loop {
+            match events {
+                Some(events) if events.is_empty() => out.write_all(b": keep-alive\n\n")?,
+                Some(events) => {
+                    for event in events {
+                        event.write_to(out)?;
+                        self.last_id = event.id;
+                    }
+                }
+                None => {
+                    self.last_id = self.log.last_id();
+                    write!(out, "id: {}\nevent: reset\ndata: {{}}\n\n", self.last_id)?;
+                }
+            }
+            out.flush()?;
+            events = self.log.wait(self.last_id, KEEP_ALIVE);
+        }
+    }
+}
+
+impl WriteBody for EventStream {
+    fn write_body(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        match self.pump(out) {
+            Err(e) if client_gone(&e) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn client_gone(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
@@ -0,0 +1,303 @@
+use crate::models::{normalize_tag, Post, PostStatus};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+/// Order of the posts in a feed page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sort {
+    #[serde(rename = "datetime")]
+    DatetimeAsc,
+    #[serde(rename = "-datetime")]
+    DatetimeDesc,
+    #[serde(rename = "title")]
+    Title,
+}
+
+impl Sort {
+    fn parse(value: &str) -> Option<Sort> {
+        match value {
+            "datetime" => Some(Sort::DatetimeAsc),
+            "-datetime" => Some(Sort::DatetimeDesc),
+            "title" => Some(Sort::Title),
+            _ => None,
+        }
+    }
+
+    /// Total order over posts; the uuid breaks ties so that every post has a
+    /// unique position a cursor can point at.
+    fn compare(self, a: Key, b: Key) -> Ordering {
+        match self {
+            Sort::DatetimeAsc => (a.datetime, a.uuid).cmp(&(b.datetime, b.uuid)),
+            Sort::DatetimeDesc => (b.datetime, b.uuid).cmp(&(a.datetime, a.uuid)),
+            Sort::Title => (a.title, a.uuid).cmp(&(b.title, b.uuid)),
+        }
+    }
+}
+
+/// The fields of a post that any `Sort` orders by.
+#[derive(Clone, Copy)]
+struct Key<'a> {
+    datetime: &'a DateTime<Utc>,
+    title: &'a str,
+    uuid: &'a Uuid,
+}
+
+impl<'a> From<&'a Post> for Key<'a> {
+    fn from(post: &'a Post) -> Key<'a> {
+        Key {
+            datetime: post.datetime(),
+            title: post.title(),
+            uuid: post.uuid(),
+        }
+    }
+}
+
+/// Position after which the next page starts: the sort key and uuid of the
+/// last post of the previous page. Because it names a position in the order
+/// rather than an offset, posts inserted between requests do not shift pages.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: Sort,
+    datetime: DateTime<Utc>,
+    title: String,
+    uuid: Uuid,
+}
+
+impl Cursor {
+    fn after(sort: Sort, post: &Post) -> Cursor {
+        Cursor {
+            sort,
+            datetime: *post.datetime(),
+            title: post.title().to_string(),
+            uuid: *post.uuid(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(value: &str) -> Option<Cursor> {
+        let json = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    fn key(&self) -> Key<'_> {
+        Key {
+            datetime: &self.datetime,
+            title: &self.title,
+            uuid: &self.uuid,
+        }
+    }
+
+    /// Whether `post` comes strictly after the cursor position.
+    fn precedes(&self, post: &Post) -> bool {
+        self.sort.compare(self.key(), post.into()) == Ordering::Less
+    }
+}
+
+/// Query parameters of `GET /post_feed`.
+#[derive(Debug)]
+pub struct FeedQuery {
+    limit: usize,
+    cursor: Option<Cursor>,
+    sort: Sort,
+    author: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    title: Option<String>,
+    /// Tag expression: every clause must match, and a clause matches a post
+    /// carrying any of its tags.
+    tags: Vec<Vec<String>>,
+    /// Posts in any of these states are listed; only published ones unless
+    /// the query asks for others.
+    statuses: Vec<PostStatus>,
+}
+
+/// One page of the feed, as returned to the client.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FeedPage<'a> {
+    pub posts: Vec<&'a Post>,
+    pub next_cursor: Option<String>,
+}
+
+impl FeedQuery {
+    /// Parses the query string pairs. Returns the name of the first parameter
+    /// that has an invalid value.
+    pub fn parse<I>(pairs: I) -> Result<FeedQuery, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut query = FeedQuery {
+            limit: DEFAULT_LIMIT,
+            cursor: None,
+            sort: Sort::DatetimeAsc,
+            author: None,
+            since: None,
+            until: None,
+            title: None,
+            tags: Vec::new(),
+            statuses: Vec::new(),
+        };
+        let mut sort = None;
+
+        for (key, value) in pairs {
+            let valid = match key.as_str() {
+                "limit" => value
+                    .parse()
+                    .ok()
+                    .filter(|limit| (1..=MAX_LIMIT).contains(limit))
+                    .map(|limit| query.limit = limit)
+                    .is_some(),
+                "cursor" => Cursor::decode(&value)
+                    .map(|cursor| query.cursor = Some(cursor))
+                    .is_some(),
+                "sort" => Sort::parse(&value).map(|s| sort = Some(s)).is_some(),
+                "author" => {
+                    query.author = Some(value);
+                    true
+                }
+                "since" => parse_datetime(&value)
+                    .map(|since| query.since = Some(since))
+                    .is_some(),
+                "until" => parse_datetime(&value)
+                    .map(|until| query.until = Some(until))
+                    .is_some(),
+                "title" => {
+                    query.title = Some(value.to_lowercase());
+                    true
+                }
+                // `tag=a|b&tag=c` selects posts tagged (a or b) and c.
+                "tag" => value
+                    .split('|')
+                    .map(normalize_tag)
+                    .collect::<Option<Vec<String>>>()
+                    .map(|clause| query.tags.push(clause))
+                    .is_some(),
+                // `status=draft|scheduled` lists posts in either state.
+                "status" => value
+                    .split('|')
+                    .map(PostStatus::parse)
+                    .collect::<Option<Vec<PostStatus>>>()
+                    .map(|statuses| query.statuses.extend(statuses))
+                    .is_some(),
+                _ => true,
+            };
+            if !valid {
+                return Err(key);
+            }
+        }
+
+        if query.statuses.is_empty() {
+            query.statuses.push(PostStatus::Published);
+        }
+
+        // A cursor carries the order it was issued for; asking for another
+        // order with it would skip or repeat posts.
+        query.sort = match (sort, &query.cursor) {
+            (Some(sort), Some(cursor)) if sort != cursor.sort => return Err("cursor".to_string()),
+            (_, Some(cursor)) => cursor.sort,
+            (Some(sort), None) => sort,
+            (None, None) => Sort::DatetimeAsc,
+        };
+        Ok(query)
+    }
+
+    /// Restricts the query to posts carrying `tag`, on top of any `tag=`
+    /// parameters.
+    pub fn require_tag(&mut self, tag: String) {
+        self.tags.push(vec![tag]);
+    }
+
+    /// The tag expression, for looking candidates up in the tag index.
+    /// Empty when the query does not filter by tag.
+    pub fn tag_clauses(&self) -> &[Vec<String>] {
+        &self.tags
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    /// Restricts the query to posts by `author`.
+    pub fn set_author(&mut self, author: String) {
+        self.author = Some(author);
+    }
+
+    /// Whether the query lists only published posts, which are all that the
+    /// tag index holds.
+    pub fn only_published(&self) -> bool {
+        self.statuses.iter().all(|&status| status == PostStatus::Published)
+    }
+
+    /// Whether the query asks for posts that only their authors may see.
+    pub fn wants_private(&self) -> bool {
+        self.statuses.iter().any(|status| !status.is_public())
+    }
+
+    fn matches(&self, post: &Post) -> bool {
+        self.statuses.contains(&post.status())
+            && self
+                .tags
+                .iter()
+                .all(|clause| clause.iter().any(|tag| post.tags().contains(tag)))
+            && self
+                .author
+                .as_ref()
+                .is_none_or(|author| post.author() == author)
+            && self.since.is_none_or(|since| *post.datetime() >= since)
+            && self.until.is_none_or(|until| *post.datetime() < until)
+            && self
+                .title
+                .as_ref()
+                .is_none_or(|title| post.title().to_lowercase().contains(title))
+            && self
+                .cursor
+                .as_ref()
+                .is_none_or(|cursor| cursor.precedes(post))
+    }
+
+    /// Filters, sorts and cuts one page out of `posts`.
+    pub fn page<'a, I>(&self, posts: I) -> FeedPage<'a>
+    where
+        I: IntoIterator<Item = &'a Post>,
+    {
+        let mut selected: Vec<&Post> = posts
+            .into_iter()
+            .filter(|post| self.matches(post))
+            .collect();
+        selected.sort_by(|a, b| self.sort.compare((*a).into(), (*b).into()));
+
+        let next_cursor = if selected.len() > self.limit {
+            selected.truncate(self.limit);
+            selected
+                .last()
+                .map(|post| Cursor::after(self.sort, post).encode())
+        } else {
+            None
+        };
+        FeedPage {
+            posts: selected,
+            next_cursor,
+        }
+    }
+}
+
+/// Accepts either a full RFC 3339 timestamp or a plain `YYYY-MM-DD` date.
+fn parse_datetime(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| Utc.from_utc_datetime(&datetime))
+}
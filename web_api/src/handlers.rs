@@ -1,32 +1,68 @@
-use crate::database::Database;
-use crate::models::Post;
+use crate::auth::{self, Auth, Claims, CurrentUser};
+use crate::comments::CommentQuery;
+use crate::conditional;
+use crate::database::{Database, DatabaseError};
+use crate::error::ApiError;
+use crate::events::{self, EventKind, EventLog, EventStream, StreamSlots};
+use crate::feed::FeedQuery;
+use crate::limits::{payload_too_large, LimitedReader, MaxBodyBytes};
+use crate::models::{
+    normalize_tag, Comment, Credentials, Edit, NewComment, NewPost, NewWebhook, Post, Role, User,
+    Webhook,
+};
+use crate::negotiation::{add_vary, negotiate, Format};
+use crate::render::{self, FeedMeta};
+use crate::revisions::{self, RestoreRequest, RevisionList, RevisionSummary};
+use crate::search::{self, Query};
+use crate::tags::TagCount;
+use crate::transfer::{self, Export, Import, Line, IMPORT_CHUNK};
+use crate::trash::TrashList;
+use crate::webhooks::{self, DeliveryList, RegisteredWebhook, WebhookInfo, WebhookList};
 
-use iron::headers::ContentType;
-use iron::{status, AfterMiddleware, Handler, IronResult, Request, Response};
+use chrono::{DateTime, Utc};
+use iron::headers::{CacheControl, CacheDirective, ContentType, ETag, Location};
+use iron::{status, AfterMiddleware, Handler, IronError, IronResult, Request, Response};
 use router::Router;
-// use std::error::Error;
-// use std::io::Read;
-use std::sync::{Arc, Mutex};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{self, BufReader, Read};
+use std::mem;
+use std::sync::{Arc, PoisonError, RwLock};
 use uuid::Uuid;
 
+/// Unwraps a `Result`. An error becomes a 500 whose cause is logged but not
+/// sent, or, given one, the `ApiError` to answer with instead.
 macro_rules! try_handler {
     ($e:expr) => {
         match $e {
             Ok(x) => x,
-            Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string())))
+            Err(e) => return Err(ApiError::internal(e).into()),
         }
     };
     ($e:expr, $error:expr) => {
         match $e {
             Ok(x) => x,
-            Err(e) => return Ok(Response::with(($error, e.to_string())))
+            Err(_) => return Err($error.into()),
         }
     };
 }
 
-macro_rules! lock {
+/// Takes the shared database for reading. Handlers change the database only
+/// through its methods, so one that panicked while holding the lock cannot
+/// have left it half-modified; the poison is ignored instead of failing every
+/// later request.
+macro_rules! read_db {
     ($e:expr) => {
-        $e.lock().unwrap()
+        $e.read().unwrap_or_else(PoisonError::into_inner)
+    };
+}
+
+/// Takes the shared database for writing, recovering from poison like
+/// `read_db!`.
+macro_rules! write_db {
+    ($e:expr) => {
+        $e.write().unwrap_or_else(PoisonError::into_inner)
     };
 }
 
@@ -35,100 +71,1334 @@ macro_rules! get_http_param {
         match $r.extensions.get::<Router>() {
             Some(router) => match router.find($e) {
                 Some(val) => val,
-                None => return Ok(Response::with((status::BadRequest))),
+                None => return Err(ApiError::internal(format!("no `{}` in the route", $e)).into()),
             },
-            None => return Ok(Response::with((status::InternalServerError))),
+            None => return Err(ApiError::internal("the request was not routed").into()),
+        }
+    };
+}
+
+/// Claims of the bearer token the request was made with, or a 401 error.
+macro_rules! require_user {
+    ($r:expr) => {
+        match $r.extensions.get::<CurrentUser>() {
+            Some(claims) => claims.clone(),
+            None => {
+                return Err(ApiError::new(
+                    status::Unauthorized,
+                    "unauthorized",
+                    "this endpoint needs an `Authorization: Bearer` token",
+                )
+                .into())
+            }
         }
     };
 }
 
+fn invalid_query(param: &str) -> ApiError {
+    ApiError::new(
+        status::BadRequest,
+        "invalid_query",
+        "a query parameter has an invalid value",
+    )
+    .with_details(json!(param))
+}
+
+/// A path parameter that should have been a UUID but isn't.
+fn invalid_id(param: &str) -> ApiError {
+    ApiError::new(status::BadRequest, "invalid_id", "the id is not a valid UUID")
+        .with_details(json!(param))
+}
+
+/// The revision number in the path, which counts from 1.
+fn revision_number(value: &str) -> Result<u32, ApiError> {
+    value.parse().ok().filter(|&number| number > 0).ok_or_else(|| {
+        ApiError::new(
+            status::BadRequest,
+            "invalid_revision",
+            "revision numbers are whole numbers from 1",
+        )
+        .with_details(json!("n"))
+    })
+}
+
+fn admins_only() -> ApiError {
+    ApiError::new(status::Forbidden, "forbidden", "only admins may do this")
+}
+
+fn forbidden() -> ApiError {
+    ApiError::new(
+        status::Forbidden,
+        "forbidden",
+        "only the author of a post or an admin may do this",
+    )
+}
+
+/// Whether the request may see `post`. Drafts and scheduled posts are only
+/// shown to their author and admins; to anyone else they do not exist.
+fn may_see(req: &Request, post: &Post) -> bool {
+    post.status().is_public()
+        || req
+            .extensions
+            .get::<CurrentUser>()
+            .is_some_and(|user| user.may_edit(post))
+}
+
+/// The post with this id, if the request may see it.
+fn visible_post<'a>(
+    req: &Request,
+    database: &'a Database,
+    id: &Uuid,
+) -> Result<&'a Post, DatabaseError> {
+    database
+        .post(id)
+        .filter(|post| may_see(req, post))
+        .ok_or(DatabaseError::NotFound)
+}
+
+/// Checks, with the database locked, that `user` may change `post` and that
+/// the request's `If-Match` still matches it. A missing post passes, so that
+/// the database reports it as not found.
+fn check_edit(req: &Request, user: &Claims, post: Option<&Post>) -> IronResult<()> {
+    let post = match post {
+        Some(post) => post,
+        None => return Ok(()),
+    };
+    if !user.may_edit(post) {
+        if !post.status().is_public() {
+            return Err(DatabaseError::NotFound.into());
+        }
+        return Err(forbidden().into());
+    }
+    let current = conditional::post_etag(post);
+    if !conditional::precondition_holds(req, &current) {
+        return Err(conditional::precondition_failed(&current));
+    }
+    Ok(())
+}
+
+/// A 200 or 201 response carrying a post, with its `ETag`.
+fn post_response(status: status::Status, post: &Post) -> IronResult<Response> {
+    let payload = try_handler!(serde_json::to_string(post));
+    let mut res = Response::with((status, payload));
+    res.headers.set(ETag(conditional::post_etag(post)));
+    conditional::set_last_modified(&mut res, post.updated_at());
+    Ok(res)
+}
+
+/// A 200 response carrying something read from `database`, tagged with the
+/// state of the database it was read at.
+fn state_response(req: &Request, database: &Database, payload: String) -> Response {
+    let mut res = Response::with((status::Ok, payload));
+    conditional::set_state_validators(req, &mut res, database.last_modified());
+    res
+}
+
+/// Reads the request body, up to the size `BodyLimit` allows.
+fn read_body(req: &mut Request) -> Result<String, ApiError> {
+    let max_bytes = req.extensions.get::<MaxBodyBytes>().copied();
+    let mut body = String::new();
+    let read = match max_bytes {
+        Some(max) => req.body.by_ref().take(max.saturating_add(1)).read_to_string(&mut body),
+        None => req.body.read_to_string(&mut body),
+    };
+    if let Some(max) = max_bytes {
+        if body.len() as u64 > max {
+            return Err(payload_too_large(max));
+        }
+    }
+    if let Err(e) = read {
+        return Err(ApiError::new(
+            status::BadRequest,
+            "unreadable_body",
+            "request body could not be read",
+        )
+        .with_details(json!(e.to_string())));
+    }
+    Ok(body)
+}
+
+/// Reads the request body and deserializes it as JSON. Syntax errors map to
+/// 400, well-formed JSON of the wrong shape maps to 422.
+fn read_json<T: serde::de::DeserializeOwned>(req: &mut Request) -> Result<T, ApiError> {
+    let body = read_body(req)?;
+    serde_json::from_str(&body).map_err(|e| {
+        if e.is_data() {
+            ApiError::new(
+                status::UnprocessableEntity,
+                "invalid_body",
+                "request body has the wrong shape",
+            )
+            .with_details(json!(e.to_string()))
+        } else {
+            ApiError::new(
+                status::BadRequest,
+                "invalid_json",
+                "request body is not valid JSON",
+            )
+            .with_details(json!(e.to_string()))
+        }
+    })
+}
+
 pub struct Handlers {
     pub post_feed: PostFeedHandler,
+    pub post_stream: PostStreamHandler,
     pub post_post: PostPostHandler,
     pub post: PostHandler,
+    pub post_put: PostPutHandler,
+    pub post_patch: PostPatchHandler,
+    pub post_delete: PostDeleteHandler,
+    pub trash: TrashHandler,
+    pub trash_restore: TrashRestoreHandler,
+    pub search: SearchHandler,
+    pub register: RegisterHandler,
+    pub login: LoginHandler,
+    pub comments: CommentsHandler,
+    pub comment_post: CommentPostHandler,
+    pub tags: TagsHandler,
+    pub tag_posts: TagPostsHandler,
+    pub revisions: RevisionsHandler,
+    pub revision: RevisionHandler,
+    pub revision_diff: RevisionDiffHandler,
+    pub revision_restore: RevisionRestoreHandler,
+    pub admin_import: AdminImportHandler,
+    pub admin_export: AdminExportHandler,
+    pub webhooks: WebhooksHandler,
+    pub webhook_post: WebhookPostHandler,
+    pub webhook: WebhookHandler,
+    pub webhook_delete: WebhookDeleteHandler,
+    pub webhook_deliveries: WebhookDeliveriesHandler,
 }
 
 impl Handlers {
-    pub fn new(db: Database) -> Handlers {
-        let db = Arc::new(Mutex::new(db));
+    pub fn new(db: Arc<RwLock<Database>>, auth: Arc<Auth>, max_streams: usize) -> Handlers {
         Handlers {
             post_feed: PostFeedHandler::new(db.clone()),
+            post_stream: PostStreamHandler::new(read_db!(db).events(), max_streams),
             post_post: PostPostHandler::new(db.clone()),
             post: PostHandler::new(db.clone()),
+            post_put: PostPutHandler::new(db.clone()),
+            post_patch: PostPatchHandler::new(db.clone()),
+            post_delete: PostDeleteHandler::new(db.clone()),
+            trash: TrashHandler::new(db.clone()),
+            trash_restore: TrashRestoreHandler::new(db.clone()),
+            search: SearchHandler::new(db.clone()),
+            register: RegisterHandler::new(db.clone()),
+            login: LoginHandler::new(db.clone(), auth.clone()),
+            comments: CommentsHandler::new(db.clone()),
+            comment_post: CommentPostHandler::new(db.clone()),
+            tags: TagsHandler::new(db.clone()),
+            tag_posts: TagPostsHandler::new(db.clone()),
+            revisions: RevisionsHandler::new(db.clone()),
+            revision: RevisionHandler::new(db.clone()),
+            revision_diff: RevisionDiffHandler::new(db.clone()),
+            revision_restore: RevisionRestoreHandler::new(db.clone()),
+            admin_import: AdminImportHandler::new(db.clone()),
+            admin_export: AdminExportHandler::new(db.clone()),
+            webhooks: WebhooksHandler::new(db.clone()),
+            webhook_post: WebhookPostHandler::new(db.clone()),
+            webhook: WebhookHandler::new(db.clone()),
+            webhook_delete: WebhookDeleteHandler::new(db.clone()),
+            webhook_deliveries: WebhookDeliveriesHandler::new(db.clone()),
         }
     }
 }
 
 pub struct PostFeedHandler {
-    database: Arc<Mutex<Database>>,
+    database: Arc<RwLock<Database>>,
 }
 
 impl PostFeedHandler {
-    pub fn new(database: Arc<Mutex<Database>>) -> PostFeedHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> PostFeedHandler {
         PostFeedHandler { database }
     }
 }
 
 impl Handler for PostFeedHandler {
-    fn handle(&self, _: &mut Request) -> IronResult<Response> {
-        let payload = try_handler!(serde_json::to_string(lock!(self.database).posts()));
-        Ok(Response::with((status::Ok, payload)))
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut query = FeedQuery::parse(req.url.as_ref().query_pairs().into_owned())
+            .map_err(|param| invalid_query(&param))?;
+        restrict_private(req, &mut query)?;
+
+        feed_response(req, &read_db!(self.database), &query, "Posts")
+    }
+}
+
+/// Lets a feed query ask for drafts and scheduled posts only on behalf of
+/// their author, who it defaults to, or of an admin.
+fn restrict_private(req: &Request, query: &mut FeedQuery) -> IronResult<()> {
+    if !query.wants_private() {
+        return Ok(());
+    }
+    let user = require_user!(req);
+    match query.author() {
+        _ if user.is_admin() => Ok(()),
+        Some(author) if author != user.sub => Err(ApiError::new(
+            status::Forbidden,
+            "forbidden",
+            "only admins may list drafts by someone else",
+        )
+        .with_details(json!("author"))
+        .into()),
+        Some(_) => Ok(()),
+        None => {
+            query.set_author(user.sub);
+            Ok(())
+        }
+    }
+}
+
+/// Renders one page of the feed, looking tag filters up in the tag index
+/// instead of scanning every post when the query lists only published posts.
+/// Answers with JSON or, by `Accept`, an Atom or RSS feed.
+fn feed_response(
+    req: &Request,
+    database: &Database,
+    query: &FeedQuery,
+    title: &str,
+) -> IronResult<Response> {
+    let format = negotiate(req, &[Format::Json, Format::Atom, Format::Rss])?;
+    let page = if query.tag_clauses().is_empty() || !query.only_published() {
+        query.page(database.posts())
+    } else {
+        query.page(database.tagged(query.tag_clauses()))
+    };
+    let payload = match format {
+        Format::Json => try_handler!(serde_json::to_string(&page)),
+        _ => {
+            let url = req.url.as_ref();
+            let site = url.origin().ascii_serialization();
+            let id = format!("{}{}", site, url.path());
+            let next_url = page.next_cursor.as_ref().map(|cursor| {
+                let mut next = url.clone();
+                let pairs: Vec<(String, String)> = url
+                    .query_pairs()
+                    .into_owned()
+                    .filter(|(name, _)| name != "cursor")
+                    .collect();
+                next.query_pairs_mut()
+                    .clear()
+                    .extend_pairs(pairs)
+                    .append_pair("cursor", cursor);
+                next.to_string()
+            });
+            let meta = FeedMeta {
+                title,
+                site: &site,
+                id: &id,
+                self_url: url.as_str(),
+                next_url: next_url.as_deref(),
+                updated: database.last_modified(),
+            };
+            if format == Format::Atom {
+                render::atom(&meta, &page.posts)
+            } else {
+                render::rss(&meta, &page.posts)
+            }
+        }
+    };
+    let mut res = Response::with((status::Ok, payload));
+    res.headers.set(format.content_type());
+    add_vary(&mut res, "Accept");
+    if query.wants_private() {
+        add_vary(&mut res, "Authorization");
+        res.headers.set(CacheControl(vec![CacheDirective::Private]));
+    }
+    conditional::set_state_validators(req, &mut res, database.last_modified());
+    Ok(res)
+}
+
+/// Streams changes to posts as server-sent events, resuming after the
+/// `Last-Event-ID` a reconnecting client sends. Answers 503 while
+/// `max_streams` streams are open already.
+pub struct PostStreamHandler {
+    events: Arc<EventLog>,
+    slots: Arc<StreamSlots>,
+}
+
+impl PostStreamHandler {
+    pub fn new(events: Arc<EventLog>, max_streams: usize) -> PostStreamHandler {
+        PostStreamHandler {
+            events,
+            slots: Arc::new(StreamSlots::new(max_streams)),
+        }
+    }
+}
+
+impl Handler for PostStreamHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let last_id = match req.headers.get_raw("Last-Event-ID") {
+            Some(values) => {
+                let value = String::from_utf8_lossy(&values[0]);
+                let id = try_handler!(
+                    value.trim().parse::<u64>(),
+                    ApiError::new(
+                        status::BadRequest,
+                        "invalid_header",
+                        "Last-Event-ID is not an event id",
+                    )
+                    .with_details(json!("Last-Event-ID"))
+                );
+                Some(id)
+            }
+            None => None,
+        };
+        let slot = match self.slots.acquire() {
+            Some(slot) => slot,
+            None => {
+                let mut err: IronError = ApiError::new(
+                    status::ServiceUnavailable,
+                    "too_many_streams",
+                    "too many event streams are open; try again later",
+                )
+                .into();
+                let retry_after = (events::RETRY_MS / 1000).to_string();
+                err.response.headers.set_raw("Retry-After", vec![retry_after.into_bytes()]);
+                return Err(err);
+            }
+        };
+
+        let mut res = Response::with(status::Ok);
+        res.headers
+            .set(ContentType("text/event-stream".parse().expect("valid media type")));
+        res.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        // Stops nginx from holding events back in its buffer.
+        res.headers.set_raw("X-Accel-Buffering", vec![b"no".to_vec()]);
+        res.body = Some(Box::new(EventStream::new(self.events.clone(), last_id, slot)));
+        Ok(res)
     }
 }
 
 pub struct PostPostHandler {
-    database: Arc<Mutex<Database>>,
+    database: Arc<RwLock<Database>>,
 }
 
 impl PostPostHandler {
-    pub fn new(database: Arc<Mutex<Database>>) -> PostPostHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> PostPostHandler {
         PostPostHandler { database }
     }
 }
 
 impl Handler for PostPostHandler {
-    fn handle(&self, _: &mut Request) -> IronResult<Response> {
-        let payload = try_handler!(serde_json::to_string(lock!(self.database).posts()));
-        Ok(Response::with((status::Ok, payload)))
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        let mut new_post: NewPost = read_json(req)?;
+        let author = new_post.author.get_or_insert_with(|| user.sub.clone());
+        if !user.may_write_as(author) {
+            return Err(forbidden().into());
+        }
+
+        let invalid = new_post.invalid_fields();
+        if !invalid.is_empty() {
+            return Err(ApiError::new(
+                status::UnprocessableEntity,
+                "invalid_fields",
+                "title and body must not be empty; tags are up to 20 letters, digits, `-` or `_` \
+                words; scheduled posts need publish_at",
+            )
+            .with_details(json!(invalid))
+            .into());
+        }
+
+        let edit = Edit::new(&user.sub, new_post.note.take());
+        let post = new_post.into_post();
+        write_db!(self.database).save_post(post.clone(), edit)?;
+
+        let mut res = post_response(status::Created, &post)?;
+        res.headers.set(Location(format!("/post/{}", post.uuid())));
+        Ok(res)
     }
 }
 
 pub struct PostHandler {
-    database: Arc<Mutex<Database>>,
+    database: Arc<RwLock<Database>>,
 }
 
 impl PostHandler {
-    fn new(database: Arc<Mutex<Database>>) -> PostHandler {
+    fn new(database: Arc<RwLock<Database>>) -> PostHandler {
         PostHandler { database }
     }
 
     fn find_post(&self, uuid: &Uuid) -> Option<Post> {
-        let locked = lock!(self.database);
-        let mut iterator = locked.posts().iter();
-        iterator
-            .find(|post| post.uuid() == uuid)
-            .map(|post| post.clone())
+        read_db!(self.database).post(uuid).cloned()
     }
 }
 
 impl Handler for PostHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        let ref post_id = get_http_param!(req, "id");
-        let id = try_handler!(Uuid::parse_str(post_id), status::BadRequest);
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+
+        let format = negotiate(req, &[Format::Json, Format::Html])?;
+        if let Some(post) = self.find_post(&id).filter(|post| may_see(req, post)) {
+            let mut res = match format {
+                Format::Html => {
+                    let mut res = Response::with((status::Ok, render::post_html(&post)));
+                    res.headers.set(format.content_type());
+                    conditional::set_state_validators(req, &mut res, post.updated_at());
+                    res
+                }
+                _ => post_response(status::Ok, &post)?,
+            };
+            add_vary(&mut res, "Accept");
+            if !post.status().is_public() {
+                add_vary(&mut res, "Authorization");
+                res.headers.set(CacheControl(vec![CacheDirective::Private]));
+            }
+            Ok(res)
+        } else {
+            Err(DatabaseError::NotFound.into())
+        }
+    }
+}
+pub struct PostPutHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl PostPutHandler {
+    fn new(database: Arc<RwLock<Database>>) -> PostPutHandler {
+        PostPutHandler { database }
+    }
+}
+
+impl Handler for PostPutHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let user = require_user!(req);
+        let mut new_post: NewPost = read_json(req)?;
+        let author = new_post.author.get_or_insert_with(|| user.sub.clone());
+        if !user.may_write_as(author) {
+            return Err(forbidden().into());
+        }
+
+        let mut database = write_db!(self.database);
+        check_edit(req, &user, database.post(&id))?;
+        let post = database.update_post(&id, new_post, &user.sub)?;
+        post_response(status::Ok, &post)
+    }
+}
+
+pub struct PostPatchHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl PostPatchHandler {
+    fn new(database: Arc<RwLock<Database>>) -> PostPatchHandler {
+        PostPatchHandler { database }
+    }
+}
+
+impl Handler for PostPatchHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let user = require_user!(req);
+        let patch: Value = read_json(req)?;
+        if let Some(author) = patch.get("author").and_then(Value::as_str) {
+            if !user.may_write_as(author) {
+                return Err(forbidden().into());
+            }
+        }
+
+        let mut database = write_db!(self.database);
+        check_edit(req, &user, database.post(&id))?;
+        let post = database.patch_post(&id, &patch, &user.sub)?;
+        post_response(status::Ok, &post)
+    }
+}
+
+/// Moves a post to the trash; see `TrashHandler`.
+pub struct PostDeleteHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl PostDeleteHandler {
+    fn new(database: Arc<RwLock<Database>>) -> PostDeleteHandler {
+        PostDeleteHandler { database }
+    }
+}
+
+impl Handler for PostDeleteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+
+        let user = require_user!(req);
+
+        let mut database = write_db!(self.database);
+        check_edit(req, &user, database.post(&id))?;
+        database.delete_post(&id)?;
+        Ok(Response::with(status::NoContent))
+    }
+}
+
+/// Lists the posts in the trash: every one to admins, and their own to
+/// everyone else.
+pub struct TrashHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl TrashHandler {
+    fn new(database: Arc<RwLock<Database>>) -> TrashHandler {
+        TrashHandler { database }
+    }
+}
+
+impl Handler for TrashHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        let database = read_db!(self.database);
+        let list = TrashList {
+            posts: database.trash().into_iter().filter(|post| user.may_edit(post)).collect(),
+        };
+        let payload = try_handler!(serde_json::to_string(&list));
+        let mut res = state_response(req, &database, payload);
+        res.headers.set(CacheControl(vec![CacheDirective::Private]));
+        Ok(res)
+    }
+}
+
+/// Takes a post out of the trash. Someone else's post in the trash is
+/// reported as not there, as it is hidden from them.
+pub struct TrashRestoreHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl TrashRestoreHandler {
+    fn new(database: Arc<RwLock<Database>>) -> TrashRestoreHandler {
+        TrashRestoreHandler { database }
+    }
+}
+
+impl Handler for TrashRestoreHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let user = require_user!(req);
+
+        let mut database = write_db!(self.database);
+        if !user.may_edit(database.trashed(&id)?) {
+            return Err(DatabaseError::NotInTrash.into());
+        }
+        let post = database.restore_post(&id)?;
+        post_response(status::Ok, &post)
+    }
+}
+
+pub struct SearchHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl SearchHandler {
+    fn new(database: Arc<RwLock<Database>>) -> SearchHandler {
+        SearchHandler { database }
+    }
+}
+
+/// Body of a `GET /search` response, best match first.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SearchResults<'a> {
+    pub results: Vec<SearchHit<'a>>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SearchHit<'a> {
+    pub post: &'a Post,
+    /// BM25 relevance; only meaningful relative to other hits.
+    pub score: f64,
+    /// HTML-escaped excerpt with the matched terms in `<mark>`.
+    pub snippet: Option<String>,
+}
+
+/// Largest number of results `GET /search` returns.
+const SEARCH_LIMIT: usize = 50;
+
+impl Handler for SearchHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let q = req
+            .url
+            .as_ref()
+            .query_pairs()
+            .find(|(key, _)| key == "q")
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default();
+        let query = Query::parse(&q);
+        if query.is_empty() {
+            return Err(ApiError::new(
+                status::BadRequest,
+                "invalid_query",
+                "q must contain at least one term or phrase to look for",
+            )
+            .with_details(json!("q"))
+            .into());
+        }
+
+        let database = read_db!(self.database);
+        let results = database
+            .search(&query)
+            .into_iter()
+            .take(SEARCH_LIMIT)
+            .map(|(post, score)| {
+                let snippet = search::snippet(post.body(), &query)
+                    .or_else(|| search::snippet(post.title(), &query));
+                SearchHit {
+                    post,
+                    score,
+                    snippet,
+                }
+            })
+            .collect();
+        let payload = try_handler!(serde_json::to_string(&SearchResults { results }));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+/// Body of a `POST /register` response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Account<'a> {
+    pub username: &'a str,
+    pub role: Role,
+}
+
+/// Shortest password `POST /register` accepts.
+const MIN_PASSWORD_LEN: usize = 8;
+
+fn valid_username(username: &str) -> bool {
+    (3..=32).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub struct RegisterHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl RegisterHandler {
+    fn new(database: Arc<RwLock<Database>>) -> RegisterHandler {
+        RegisterHandler { database }
+    }
+}
+
+impl Handler for RegisterHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let credentials: Credentials = read_json(req)?;
+
+        let mut invalid = Vec::new();
+        if !valid_username(&credentials.username) {
+            invalid.push("username");
+        }
+        if credentials.password.chars().count() < MIN_PASSWORD_LEN {
+            invalid.push("password");
+        }
+        if !invalid.is_empty() {
+            return Err(ApiError::new(
+                status::UnprocessableEntity,
+                "invalid_fields",
+                "usernames are 3 to 32 letters, digits, `_` or `-`; passwords at least 8 characters",
+            )
+            .with_details(json!(invalid))
+            .into());
+        }
+
+        // Admin accounts come only from the configuration, so registering
+        // under an admin's name fails as the name is taken.
+        let role = Role::User;
+        // Hash before taking the lock; it is deliberately slow.
+        let user = User::new(
+            &credentials.username,
+            auth::hash_password(&credentials.password),
+            role,
+        );
+        write_db!(self.database).add_user(user)?;
+
+        let payload = try_handler!(serde_json::to_string(&Account {
+            username: &credentials.username,
+            role,
+        }));
+        Ok(Response::with((status::Created, payload)))
+    }
+}
+
+/// Body of a `POST /login` response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Token {
+    /// Bearer token for the `Authorization` header.
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct LoginHandler {
+    database: Arc<RwLock<Database>>,
+    auth: Arc<Auth>,
+}
+
+impl LoginHandler {
+    fn new(database: Arc<RwLock<Database>>, auth: Arc<Auth>) -> LoginHandler {
+        LoginHandler { database, auth }
+    }
+}
+
+impl Handler for LoginHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let credentials: Credentials = read_json(req)?;
+
+        let user = read_db!(self.database).user(&credentials.username).cloned();
+        let verified = match &user {
+            Some(user) => auth::verify_password(&credentials.password, user.password_hash()),
+            None => {
+                // Spend as long as a real check would, so response times do
+                // not reveal which usernames exist.
+                auth::hash_password(&credentials.password);
+                false
+            }
+        };
+        let user = match user {
+            Some(user) if verified => user,
+            _ => {
+                return Err(ApiError::new(
+                    status::Unauthorized,
+                    "invalid_credentials",
+                    "unknown username or wrong password",
+                )
+                .into());
+            }
+        };
+
+        let (token, expires_at) = self.auth.issue(&user);
+        let payload = try_handler!(serde_json::to_string(&Token { token, expires_at }));
+        Ok(Response::with((status::Ok, payload)))
+    }
+}
+
+pub struct CommentsHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl CommentsHandler {
+    fn new(database: Arc<RwLock<Database>>) -> CommentsHandler {
+        CommentsHandler { database }
+    }
+}
+
+impl Handler for CommentsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let query = CommentQuery::parse(req.url.as_ref().query_pairs().into_owned())
+            .map_err(|param| invalid_query(&param))?;
+
+        let database = read_db!(self.database);
+        visible_post(req, &database, &id)?;
+        let page = query.page(database.comments(&id));
+        let payload = try_handler!(serde_json::to_string(&page));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+pub struct CommentPostHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl CommentPostHandler {
+    fn new(database: Arc<RwLock<Database>>) -> CommentPostHandler {
+        CommentPostHandler { database }
+    }
+}
+
+impl Handler for CommentPostHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let user = require_user!(req);
+        let new_comment: NewComment = read_json(req)?;
+
+        let comment = Comment::new(
+            id,
+            new_comment.parent,
+            &user.sub,
+            new_comment.body.as_deref().unwrap_or_default(),
+        );
+        let mut database = write_db!(self.database);
+        visible_post(req, &database, &id)?;
+        database.add_comment(comment.clone())?;
+        let payload = try_handler!(serde_json::to_string(&comment));
+        Ok(Response::with((status::Created, payload)))
+    }
+}
+
+/// Body of a `GET /tags` response, in tag order.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TagList<'a> {
+    pub tags: Vec<TagCount<'a>>,
+}
+
+pub struct TagsHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl TagsHandler {
+    fn new(database: Arc<RwLock<Database>>) -> TagsHandler {
+        TagsHandler { database }
+    }
+}
+
+impl Handler for TagsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let database = read_db!(self.database);
+        let payload = try_handler!(serde_json::to_string(&TagList {
+            tags: database.tag_counts()
+        }));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+pub struct TagPostsHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl TagPostsHandler {
+    fn new(database: Arc<RwLock<Database>>) -> TagPostsHandler {
+        TagPostsHandler { database }
+    }
+}
+
+impl Handler for TagPostsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let tag = match normalize_tag(get_http_param!(req, "tag")) {
+            Some(tag) => tag,
+            None => return Err(invalid_query("tag").into()),
+        };
+        let mut query = FeedQuery::parse(req.url.as_ref().query_pairs().into_owned())
+            .map_err(|param| invalid_query(&param))?;
+        restrict_private(req, &mut query)?;
+        let title = format!("Posts tagged {}", tag);
+        query.require_tag(tag);
+
+        feed_response(req, &read_db!(self.database), &query, &title)
+    }
+}
+
+pub struct RevisionsHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl RevisionsHandler {
+    fn new(database: Arc<RwLock<Database>>) -> RevisionsHandler {
+        RevisionsHandler { database }
+    }
+}
+
+impl Handler for RevisionsHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+
+        let database = read_db!(self.database);
+        visible_post(req, &database, &id)?;
+        let list = RevisionList {
+            revisions: database
+                .revisions(&id)
+                .iter()
+                .map(RevisionSummary::from)
+                .collect(),
+        };
+        let payload = try_handler!(serde_json::to_string(&list));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+pub struct RevisionHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl RevisionHandler {
+    fn new(database: Arc<RwLock<Database>>) -> RevisionHandler {
+        RevisionHandler { database }
+    }
+}
+
+impl Handler for RevisionHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let number = revision_number(get_http_param!(req, "n"))?;
+
+        let database = read_db!(self.database);
+        visible_post(req, &database, &id)?;
+        let revision = database.revision(&id, number)?;
+        let payload = try_handler!(serde_json::to_string(revision));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+/// Diffs a revision against the one before it or, given `?from=m`,
+/// revision `m`; `from=0` diffs against nothing.
+pub struct RevisionDiffHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl RevisionDiffHandler {
+    fn new(database: Arc<RwLock<Database>>) -> RevisionDiffHandler {
+        RevisionDiffHandler { database }
+    }
+}
+
+impl Handler for RevisionDiffHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let number = revision_number(get_http_param!(req, "n"))?;
+        let mut from = number - 1;
+        for (name, value) in req.url.as_ref().query_pairs() {
+            if name == "from" {
+                from = try_handler!(value.parse::<u32>(), invalid_query("from"));
+            }
+        }
+
+        let database = read_db!(self.database);
+        visible_post(req, &database, &id)?;
+        let to = database.revision(&id, number)?;
+        let from = match from {
+            0 => None,
+            from => Some(database.revision(&id, from)?),
+        };
+        let payload = try_handler!(serde_json::to_string(&revisions::diff(from, to)));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+/// Rolls a post back to an earlier revision, which makes a new revision.
+/// Only the author or an admin may.
+pub struct RevisionRestoreHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl RevisionRestoreHandler {
+    fn new(database: Arc<RwLock<Database>>) -> RevisionRestoreHandler {
+        RevisionRestoreHandler { database }
+    }
+}
+
+impl Handler for RevisionRestoreHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let post_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(post_id), invalid_id("id"));
+        let number = revision_number(get_http_param!(req, "n"))?;
+        let user = require_user!(req);
+        let body = read_body(req)?;
+        let request: RestoreRequest = if body.trim().is_empty() {
+            RestoreRequest::default()
+        } else {
+            try_handler!(
+                serde_json::from_str(&body),
+                ApiError::new(
+                    status::BadRequest,
+                    "invalid_json",
+                    "request body is not valid JSON",
+                )
+            )
+        };
+
+        let mut database = write_db!(self.database);
+        if !user.may_edit(visible_post(req, &database, &id)?) {
+            return Err(forbidden().into());
+        }
+        let post = database.restore_revision(&id, number, Edit::new(&user.sub, request.note))?;
+        post_response(status::Ok, &post)
+    }
+}
+
+/// Loads posts from JSON Lines or, with `Content-Type: text/csv`, CSV, as
+/// the body streams in, storing them `IMPORT_CHUNK` at a time. Reports the
+/// lines it skipped instead of failing the batch; with `?dry_run=true` it
+/// only reports.
+pub struct AdminImportHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl AdminImportHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> AdminImportHandler {
+        AdminImportHandler { database }
+    }
+}
+
+impl Handler for AdminImportHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let mut dry_run = false;
+        for (name, value) in req.url.as_ref().query_pairs() {
+            if name == "dry_run" {
+                dry_run = try_handler!(value.parse::<bool>(), invalid_query("dry_run"));
+            }
+        }
+        let csv = match req.headers.get::<ContentType>() {
+            Some(ContentType(mime)) => match (mime.0.as_str(), mime.1.as_str()) {
+                ("text", "csv") => true,
+                ("application", "x-ndjson" | "jsonl" | "json") => false,
+                _ => {
+                    return Err(ApiError::new(
+                        status::UnsupportedMediaType,
+                        "unsupported_media_type",
+                        "imports are JSON Lines or CSV",
+                    )
+                    .with_details(json!(mime.to_string()))
+                    .into())
+                }
+            },
+            None => false,
+        };
 
-        if let Some(post) = self.find_post(&id) {
-            let payload = try_handler!(serde_json::to_string(&post));
-            Ok(Response::with((status::Ok, payload)))
+        let max_bytes = req.extensions.get::<MaxBodyBytes>().copied().unwrap_or(u64::MAX);
+        let body = BufReader::new(LimitedReader::new(&mut req.body, max_bytes));
+        let lines: Box<dyn Iterator<Item = io::Result<Line>>> = if csv {
+            Box::new(transfer::csv_lines(body))
         } else {
-            Ok(Response::with(status::NotFound))
+            Box::new(transfer::json_lines(body))
+        };
+
+        let mut import = Import::new(&user.sub, dry_run);
+        let mut chunk = Vec::with_capacity(IMPORT_CHUNK);
+        let mut unread = None;
+        for line in lines {
+            match line {
+                Ok(line) => chunk.push(line),
+                Err(e) => {
+                    unread = Some(e);
+                    break;
+                }
+            }
+            if chunk.len() == IMPORT_CHUNK {
+                import.add(&mut write_db!(self.database), mem::take(&mut chunk))?;
+            }
         }
+        import.add(&mut write_db!(self.database), chunk)?;
+        if let Some(e) = unread {
+            import.cut_off(&e);
+        }
+        let payload = try_handler!(serde_json::to_string(&import.finish()));
+        Ok(Response::with((status::Ok, payload)))
     }
 }
+
+/// Dumps every post as JSON Lines or, if `Accept` prefers it, CSV, streamed
+/// `transfer::EXPORT_CHUNK` posts at a time. It has no `ETag`, which would take
+/// buffering the whole dump to compute.
+pub struct AdminExportHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl AdminExportHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> AdminExportHandler {
+        AdminExportHandler { database }
+    }
+}
+
+impl Handler for AdminExportHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let format = negotiate(req, &[Format::JsonLines, Format::Csv])?;
+
+        let mut res = Response::with(status::Ok);
+        res.headers.set(format.content_type());
+        add_vary(&mut res, "Accept");
+        res.body = Some(Box::new(Export::new(self.database.clone(), format)));
+        Ok(res)
+    }
+}
+
+pub struct WebhooksHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl WebhooksHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> WebhooksHandler {
+        WebhooksHandler { database }
+    }
+}
+
+impl Handler for WebhooksHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let database = read_db!(self.database);
+        let list = WebhookList {
+            webhooks: database.webhooks().iter().map(WebhookInfo::from).collect(),
+        };
+        let payload = try_handler!(serde_json::to_string(&list));
+        Ok(state_response(req, &database, payload))
+    }
+}
+
+/// Registers a URL to be sent changes to posts, with a new secret that is
+/// shown only in the response.
+pub struct WebhookPostHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl WebhookPostHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> WebhookPostHandler {
+        WebhookPostHandler { database }
+    }
+}
+
+impl Handler for WebhookPostHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let new_webhook: NewWebhook = read_json(req)?;
+        let invalid = new_webhook.invalid_fields();
+        if !invalid.is_empty() {
+            return Err(ApiError::new(
+                status::UnprocessableEntity,
+                "invalid_fields",
+                "url must be an http or https URL; events, if given, must not be empty",
+            )
+            .with_details(json!(invalid))
+            .into());
+        }
+
+        let wanted = new_webhook.events.unwrap_or_else(|| EventKind::ALL.to_vec());
+        let events = EventKind::ALL
+            .into_iter()
+            .filter(|kind| wanted.contains(kind))
+            .collect();
+        let webhook = Webhook::new(
+            new_webhook.url.as_deref().unwrap_or_default(),
+            webhooks::random_secret(),
+            events,
+            &user.sub,
+        );
+        write_db!(self.database).add_webhook(webhook.clone())?;
+
+        let payload = try_handler!(serde_json::to_string(&RegisteredWebhook {
+            webhook: WebhookInfo::from(&webhook),
+            secret: webhook.secret(),
+        }));
+        let mut res = Response::with((status::Created, payload));
+        res.headers.set(Location(format!("/admin/webhooks/{}", webhook.id())));
+        Ok(res)
+    }
+}
+
+pub struct WebhookHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl WebhookHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> WebhookHandler {
+        WebhookHandler { database }
+    }
+}
+
+impl Handler for WebhookHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let webhook_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(webhook_id), invalid_id("id"));
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let database = read_db!(self.database);
+        let payload = try_handler!(serde_json::to_string(&WebhookInfo::from(
+            database.webhook(&id)?
+        )));
+        let etag = conditional::etag_for(payload.as_bytes());
+        let mut res = Response::with((status::Ok, payload));
+        res.headers.set(ETag(etag));
+        Ok(res)
+    }
+}
+
+/// Unregisters a webhook. Like other deletes it needs the `ETag` of the
+/// webhook as last read in `If-Match`.
+pub struct WebhookDeleteHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl WebhookDeleteHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> WebhookDeleteHandler {
+        WebhookDeleteHandler { database }
+    }
+}
+
+impl Handler for WebhookDeleteHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let webhook_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(webhook_id), invalid_id("id"));
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+
+        let mut database = write_db!(self.database);
+        let current = try_handler!(serde_json::to_vec(&WebhookInfo::from(
+            database.webhook(&id)?
+        )));
+        let current = conditional::etag_for(&current);
+        if !conditional::precondition_holds(req, &current) {
+            return Err(conditional::precondition_failed(&current));
+        }
+        database.delete_webhook(&id)?;
+        Ok(Response::with(status::NoContent))
+    }
+}
+
+/// The most recent deliveries to a webhook, newest first.
+pub struct WebhookDeliveriesHandler {
+    database: Arc<RwLock<Database>>,
+}
+
+impl WebhookDeliveriesHandler {
+    pub fn new(database: Arc<RwLock<Database>>) -> WebhookDeliveriesHandler {
+        WebhookDeliveriesHandler { database }
+    }
+}
+
+impl Handler for WebhookDeliveriesHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let webhook_id = get_http_param!(req, "id");
+        let id = try_handler!(Uuid::parse_str(webhook_id), invalid_id("id"));
+        let user = require_user!(req);
+        if !user.is_admin() {
+            return Err(admins_only().into());
+        }
+        let database = read_db!(self.database);
+        database.webhook(&id)?;
+        let list = DeliveryList {
+            deliveries: database.deliveries(&id),
+        };
+        let payload = try_handler!(serde_json::to_string(&list));
+        Ok(Response::with((status::Ok, payload)))
+    }
+}
+
 pub struct JsonAfterMiddleware;
 
 impl AfterMiddleware for JsonAfterMiddleware {
     fn after(&self, _: &mut Request, mut res: Response) -> IronResult<Response> {
-        res.headers.set(ContentType::json());
+        if !res.headers.has::<ContentType>() {
+            res.headers.set(ContentType::json());
+        }
         Ok(res)
     }
+
+    fn catch(&self, _: &mut Request, mut err: IronError) -> IronResult<Response> {
+        if !err.response.headers.has::<ContentType>() {
+            err.response.headers.set(ContentType::json());
+        }
+        Err(err)
+    }
 }
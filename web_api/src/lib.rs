@@ -0,0 +1,29 @@
+pub mod app;
+pub mod auth;
+pub mod comments;
+pub mod conditional;
+pub mod config;
+pub mod cors;
+pub mod database;
+pub mod error;
+pub mod events;
+pub mod feed;
+pub mod handlers;
+pub mod limits;
+pub mod logging;
+pub mod markdown;
+pub mod metrics;
+pub mod models;
+pub mod negotiation;
+pub mod openapi;
+pub mod periodic;
+pub mod render;
+pub mod revisions;
+pub mod routes;
+pub mod scheduler;
+pub mod search;
+pub mod storage;
+pub mod tags;
+pub mod transfer;
+pub mod trash;
+pub mod webhooks;
@@ -0,0 +1,270 @@
+use crate::auth::CurrentUser;
+use crate::error::ApiError;
+use crate::routes::RouteTable;
+
+use iron::headers::ContentLength;
+use iron::method::Method;
+use iron::typemap::Key;
+use iron::{status, BeforeMiddleware, IronError, IronResult, Request};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+/// Number of buckets kept before full ones are swept out.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Budget of one token bucket: up to `burst` requests at once, refilled at
+/// `per_second` requests per second.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub const DEFAULT_READS: RateLimit = RateLimit {
+        burst: 100.0,
+        per_second: 20.0,
+    };
+    pub const DEFAULT_WRITES: RateLimit = RateLimit {
+        burst: 20.0,
+        per_second: 2.0,
+    };
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &RateLimit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: limit.burst,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.refilled_at = now;
+    }
+
+    /// Takes one token, or returns how many seconds until one is available.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), u64> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / limit.per_second).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Read,
+    Write,
+}
+
+/// Who a request is charged to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    User(String),
+    Address(std::net::IpAddr),
+}
+
+/// Token-bucket rate limiting, with separate budgets for reads (`GET`,
+/// `HEAD`, `OPTIONS`) and writes. Requests are charged to the authenticated
+/// user when there is one, so link this after `AuthMiddleware`, and to the
+/// client address otherwise. Requests refused before they reach the limiter,
+/// such as those with a bad token, are charged to their address too, so that
+/// guessing tokens is limited like anything else.
+pub struct RateLimiter {
+    reads: RateLimit,
+    writes: RateLimit,
+    buckets: Mutex<HashMap<(Client, Kind), TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(reads: RateLimit, writes: RateLimit) -> RateLimiter {
+        RateLimiter {
+            reads,
+            writes,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit(&self, kind: Kind) -> &RateLimit {
+        match kind {
+            Kind::Read => &self.reads,
+            Kind::Write => &self.writes,
+        }
+    }
+
+    /// Takes a token from the client's bucket for this kind of request, or
+    /// refuses the request.
+    fn charge(&self, req: &Request, client: Client) -> IronResult<()> {
+        let kind = match req.method {
+            Method::Get | Method::Head | Method::Options => Kind::Read,
+            _ => Kind::Write,
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= SWEEP_THRESHOLD {
+            // A bucket that has refilled completely is the same as no bucket.
+            buckets.retain(|(_, kind), bucket| {
+                let limit = self.limit(*kind);
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst
+            });
+        }
+        let limit = self.limit(kind);
+        let taken = buckets
+            .entry((client, kind))
+            .or_insert_with(|| TokenBucket::full(limit, now))
+            .take(limit, now);
+        drop(buckets);
+
+        taken.map_err(|retry_after| {
+            let mut err: IronError = ApiError::new(
+                status::TooManyRequests,
+                "rate_limited",
+                "too many requests; retry later",
+            )
+            .with_details(json!({ "retry_after": retry_after }))
+            .into();
+            err.response
+                .headers
+                .set_raw("Retry-After", vec![retry_after.to_string().into_bytes()]);
+            err
+        })
+    }
+}
+
+impl BeforeMiddleware for RateLimiter {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let client = match req.extensions.get::<CurrentUser>() {
+            Some(claims) => Client::User(claims.sub.clone()),
+            None => Client::Address(req.remote_addr.ip()),
+        };
+        self.charge(req, client)
+    }
+
+    fn catch(&self, req: &mut Request, err: IronError) -> IronResult<()> {
+        self.charge(req, Client::Address(req.remote_addr.ip()))?;
+        Err(err)
+    }
+}
+
+/// `Request::extensions` key holding the largest body, in bytes, handlers
+/// may read.
+pub struct MaxBodyBytes;
+
+impl Key for MaxBodyBytes {
+    type Value = u64;
+}
+
+/// Error for a body over `max_bytes`.
+pub fn payload_too_large(max_bytes: u64) -> ApiError {
+    ApiError::new(
+        status::PayloadTooLarge,
+        "payload_too_large",
+        "request body is too large",
+    )
+    .with_details(json!({ "max_bytes": max_bytes }))
+}
+
+/// Refuses bodies whose `Content-Length` is over the cap, and records the cap
+/// in `MaxBodyBytes` so that bodies sent without a length are cut off while
+/// being read. Some routes, like imports, may be given a cap of their own.
+pub struct BodyLimit {
+    max_bytes: u64,
+    routes: RouteTable,
+    /// Caps that replace `max_bytes`, by route name.
+    route_max_bytes: Vec<(&'static str, u64)>,
+}
+
+impl BodyLimit {
+    pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+    pub const DEFAULT_MAX_IMPORT_BYTES: u64 = 64 * 1024 * 1024;
+
+    pub fn new(max_bytes: u64, routes: RouteTable) -> BodyLimit {
+        BodyLimit {
+            max_bytes,
+            routes,
+            route_max_bytes: Vec::new(),
+        }
+    }
+
+    /// Caps bodies sent to the route named `name` at `max_bytes` instead.
+    pub fn with_route(mut self, name: &'static str, max_bytes: u64) -> BodyLimit {
+        self.route_max_bytes.push((name, max_bytes));
+        self
+    }
+
+    fn max_bytes_for(&self, req: &Request) -> u64 {
+        let path = format!("/{}", req.url.path().join("/"));
+        self.routes
+            .recognize(&req.method, &path)
+            .and_then(|route| self.route_max_bytes.iter().find(|(name, _)| *name == route.name))
+            .map_or(self.max_bytes, |(_, max_bytes)| *max_bytes)
+    }
+}
+
+impl BeforeMiddleware for BodyLimit {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let max_bytes = self.max_bytes_for(req);
+        if let Some(ContentLength(length)) = req.headers.get::<ContentLength>() {
+            if *length > max_bytes {
+                return Err(payload_too_large(max_bytes).into());
+            }
+        }
+        req.extensions.insert::<MaxBodyBytes>(max_bytes);
+        Ok(())
+    }
+}
+
+/// Reads a body a little at a time, failing once it goes over `max_bytes`
+/// instead of quietly stopping there.
+pub struct LimitedReader<R> {
+    inner: R,
+    max_bytes: u64,
+    read: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, max_bytes: u64) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            max_bytes,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read < self.max_bytes || buf.is_empty() {
+            let allowed = (self.max_bytes - self.read).min(buf.len() as u64) as usize;
+            let n = self.inner.read(&mut buf[..allowed])?;
+            self.read += n as u64;
+            return Ok(n);
+        }
+        // At the cap: one more byte is enough to tell that there is more.
+        if self.inner.read(&mut [0])? == 0 {
+            return Ok(0);
+        }
+        Err(io::Error::other(format!(
+            "request body is larger than {} bytes",
+            self.max_bytes
+        )))
+    }
+}
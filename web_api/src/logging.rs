@@ -0,0 +1,113 @@
+use crate::metrics::Metrics;
+use crate::routes::RouteTable;
+
+use iron::headers::ContentLength;
+use iron::typemap::Key;
+use iron::{status, AfterMiddleware, BeforeMiddleware, IronError, IronResult, Request, Response};
+use log::info;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+use uuid::Uuid;
+
+/// Header carrying the id a request is logged under.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied request id that is reused instead of replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Route name used for requests no registered route matches.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Per-request data `RequestLogger` keeps between `before` and `after`.
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub id: String,
+    started: Instant,
+}
+
+impl Key for RequestInfo {
+    type Value = RequestInfo;
+}
+
+/// Logs one JSON line per request through the `log` facade, tags every
+/// response with `X-Request-Id`, and records per-route metrics.
+///
+/// Link it before every other `BeforeMiddleware` and after every other
+/// `AfterMiddleware`, so that the latency covers the whole chain.
+pub struct RequestLogger {
+    routes: RouteTable,
+    metrics: Arc<Metrics>,
+}
+
+impl RequestLogger {
+    pub fn new(routes: RouteTable, metrics: Arc<Metrics>) -> RequestLogger {
+        RequestLogger { routes, metrics }
+    }
+
+    fn finish(&self, req: &Request, res: &mut Response) {
+        let info = match req.extensions.get::<RequestInfo>() {
+            Some(info) => info.clone(),
+            None => return,
+        };
+        let latency = info.started.elapsed();
+        let path = format!("/{}", req.url.path().join("/"));
+        let route = self
+            .routes
+            .recognize(&req.method, &path)
+            .map_or(UNMATCHED_ROUTE, |route| route.name);
+        let status = res.status.unwrap_or(status::NotFound).to_u16();
+        let bytes = res.headers.get::<ContentLength>().map(|length| length.0);
+
+        res.headers
+            .set_raw(REQUEST_ID_HEADER, vec![info.id.clone().into_bytes()]);
+        self.metrics
+            .record(route, req.method.as_ref(), status, latency);
+        info!(
+            target: "web_api::access",
+            "{}",
+            json!({
+                "request_id": info.id,
+                "method": req.method.as_ref(),
+                "route": route,
+                "path": path,
+                "status": status,
+                "latency_ms": latency.as_secs_f64() * 1000.0,
+                "bytes": bytes,
+            })
+        );
+    }
+}
+
+impl BeforeMiddleware for RequestLogger {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let id = req
+            .headers
+            .get_raw(REQUEST_ID_HEADER)
+            .and_then(|values| values.first())
+            .and_then(|value| String::from_utf8(value.clone()).ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id.chars().all(|c| c.is_ascii_graphic())
+            })
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.extensions.insert::<RequestInfo>(RequestInfo {
+            id,
+            started: Instant::now(),
+        });
+        Ok(())
+    }
+}
+
+impl AfterMiddleware for RequestLogger {
+    fn after(&self, req: &mut Request, mut res: Response) -> IronResult<Response> {
+        self.finish(req, &mut res);
+        Ok(res)
+    }
+
+    fn catch(&self, req: &mut Request, mut err: IronError) -> IronResult<Response> {
+        self.finish(req, &mut err.response);
+        Err(err)
+    }
+}
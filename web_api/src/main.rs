@@ -1,89 +1,121 @@
 extern crate chrono;
 extern crate env_logger;
 extern crate iron;
-extern crate logger;
 extern crate router;
 extern crate uuid;
 extern crate serde_json;
+#[macro_use]
+extern crate log;
 
+use web_api::app;
+use web_api::auth::{self, Auth};
+use web_api::config::{load_fixtures, Config, USAGE};
+use web_api::database::Database;
+use web_api::scheduler::{self, SCHEDULER_INTERVAL};
+use web_api::trash::{self, PURGE_INTERVAL};
+use web_api::webhooks::{self, RETRY_BASE};
 
-mod models;
-mod database;
-mod handlers;
-
-use database::Database;
-use handlers::*;
-use models::*;
-
-use iron::prelude::Chain;
 use iron::Iron;
-use router::Router;
-use uuid::Uuid;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread;
 
-struct LoggerBefore{}
+/// Environment variable holding the secret bearer tokens are signed with.
+const TOKEN_SECRET_VAR: &str = "WEB_API_TOKEN_SECRET";
 
-impl LoggerBefore{
-    fn new() -> LoggerBefore{
-        LoggerBefore{}
-    }
-}
+/// How long a token issued by `POST /login` stays valid.
+const TOKEN_TTL_HOURS: i64 = 24;
 
-impl iron::middleware::BeforeMiddleware for LoggerBefore{
-    fn before(&self, req: &mut iron::Request) -> iron::IronResult<()>{
-        println!("----->logger_before: {:?}", req);
-        Ok(())
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some("--hash-password") {
+        print_password_hash();
+    }
+    let config = Config::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&config.log_level))
+        .init();
+
+    let mut db = config
+        .storage
+        .open_database()
+        .expect("Unable to load posts from storage");
+    db.set_admins(&config.admins).expect("Unable to store the admin accounts");
+    if let Some(fixtures) = &config.fixtures {
+        if db.posts().next().is_none() {
+            seed(&mut db, fixtures);
+        }
     }
+    let db = Arc::new(RwLock::new(db));
+    exit_on_signal(db.clone()).expect("Unable to install signal handlers");
+    scheduler::spawn(db.clone(), SCHEDULER_INTERVAL);
+    webhooks::spawn(db.clone(), RETRY_BASE);
+    let retention = chrono::Duration::days(config.trash_retention_days.into());
+    trash::spawn(db.clone(), PURGE_INTERVAL, retention);
+
+    let secret = match std::env::var(TOKEN_SECRET_VAR) {
+        Ok(secret) => secret.into_bytes(),
+        Err(_) => {
+            warn!("{} is not set; tokens will not survive a restart", TOKEN_SECRET_VAR);
+            Auth::random_secret()
+        }
+    };
+    let auth = Arc::new(Auth::new(secret, chrono::Duration::hours(TOKEN_TTL_HOURS)));
+
+    let chain = app::chain(&config, db, auth);
+
+    let mut iron = Iron::new(chain);
+    iron.threads = config.worker_threads();
+    info!("listening on {}", config.bind);
+    iron.http(config.bind.as_str()).expect("Unable to start server");
 }
 
-struct LoggerAfter{}
-
-impl LoggerAfter{
-    fn new() -> LoggerAfter{
-        LoggerAfter{}
+/// Reads a password from the first line of stdin, prints its hash for the
+/// `admins` setting and exits.
+fn print_password_hash() -> ! {
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("unable to read the password: {}", e);
+        process::exit(2);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("the password is empty");
+        process::exit(2);
     }
+    println!("{}", auth::hash_password(password));
+    process::exit(0);
 }
 
-impl iron::middleware::AfterMiddleware for LoggerAfter{
-    fn after(&self, _: &mut iron::Request, res: iron::Response) -> iron::IronResult<iron::Response>{
-        println!("------>logger_after: {:?}", res);
-        Ok(res)
+/// Fills an empty database with the posts in a fixtures file.
+fn seed(db: &mut Database, fixtures: &Path) {
+    let posts = load_fixtures(fixtures).unwrap_or_else(|e| {
+        error!("unable to load the fixtures: {}", e);
+        process::exit(2);
+    });
+    for post in posts {
+        db.add_post(post.into_post()).expect("Unable to store seed post");
     }
 }
 
-fn main() {
-    env_logger::init();
-
-    let mut db = Database::new();
-    let p = Post::new(
-        "The First Post",
-        "This is the first post in our API",
-        "Tensor",
-        chrono::offset::Utc::now(),
-        Uuid::new_v4(),
-    );
-    db.add_post(p);
-
-    let p2 = Post::new(
-        "The next post is better",
-        "Iron is really cool and Rust is awesome too!",
-        "Metalman",
-        chrono::offset::Utc::now(),
-        Uuid::new_v4(),
-    );
-    db.add_post(p2);
-
-    let handlers = Handlers::new(db);
-    let json_content_middleware = JsonAfterMiddleware;
-
-    let mut router = Router::new();
-    router.get("/post_feed", handlers.post_feed, "post_feed");
-    router.post("/post", handlers.post_post, "post_post");
-    router.get("/post/:id", handlers.post, "post");
-
-    let mut chain = Chain::new(router);
-    chain.link_before(LoggerBefore::new());
-    chain.link_after(json_content_middleware);
-    chain.link_after(LoggerAfter::new());
-
-    Iron::new(chain).http("localhost:8000").expect("Unable to start server");
+/// Flushes storage and exits on SIGTERM or SIGINT. The write lock is held
+/// until the process is gone, so no request is left half applied.
+fn exit_on_signal(db: Arc<RwLock<Database>>) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("received signal {}, shutting down", signal);
+            let mut db = db.write().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = db.flush() {
+                error!("unable to flush storage: {}", e);
+                process::exit(1);
+            }
+            process::exit(0);
+        }
+    });
+    Ok(())
 }
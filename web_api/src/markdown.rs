@@ -0,0 +1,23 @@
+use ammonia::Builder;
+use pulldown_cmark::{html, Options, Parser};
+use std::sync::OnceLock;
+
+/// Renders a post body, written in CommonMark with tables, as HTML that is
+/// safe to put in a page. Whatever raw HTML the body contains goes through
+/// an allowlist: scripts, styles, event-handler attributes and URLs with
+/// schemes such as `javascript:` are dropped.
+pub fn to_html(source: &str) -> String {
+    let mut unsafe_html = String::with_capacity(source.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(source, Options::ENABLE_TABLES));
+    sanitizer().clean(&unsafe_html).to_string()
+}
+
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::default();
+        // Fenced code keeps its `language-*` class for syntax highlighters.
+        builder.add_tag_attributes("code", &["class"]);
+        builder
+    })
+}
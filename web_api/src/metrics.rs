@@ -0,0 +1,130 @@
+use iron::headers::ContentType;
+use iron::{status, Handler, IronResult, Request, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; the last slot is `+Inf`.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Keyed by route name, method and status code.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// Keyed by route name and method.
+    latencies: BTreeMap<(String, String), Histogram>,
+}
+
+/// Per-route request counters and latency histograms.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record(&self, route: &str, method: &str, status: u16, latency: Duration) {
+        let mut registry = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        *registry
+            .requests
+            .entry((route.to_string(), method.to_string(), status))
+            .or_insert(0) += 1;
+        registry
+            .latencies
+            .entry((route.to_string(), method.to_string()))
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests handled, by route, method and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                route, method, status, count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Request latency, by route and method.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((route, method), histogram) in &registry.latencies {
+            let labels = format!("route=\"{}\",method=\"{}\"", route, method);
+            let mut cumulative = 0;
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let bound = BUCKETS
+                    .get(i)
+                    .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+        out
+    }
+}
+
+/// Serves `GET /metrics`.
+pub struct MetricsHandler {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsHandler {
+    pub fn new(metrics: Arc<Metrics>) -> MetricsHandler {
+        MetricsHandler { metrics }
+    }
+}
+
+impl Handler for MetricsHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut res = Response::with((status::Ok, self.metrics.render()));
+        res.headers.set(ContentType(
+            "text/plain; version=0.0.4; charset=utf-8".parse().unwrap(),
+        ));
+        Ok(res)
+    }
+}
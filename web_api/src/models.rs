@@ -1,15 +1,88 @@
+use crate::events::EventKind;
+use crate::markdown;
+
 use chrono::DateTime;
 use chrono::Utc;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Most tags a post may carry.
+pub const MAX_TAGS: usize = 20;
+/// Longest tag, in characters.
+const MAX_TAG_LEN: usize = 50;
+
+/// Lowercases a tag and checks it is 1 to 50 letters, digits, `-` or `_`.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LEN
+        && tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+/// Where a post is in its life. Only published posts are listed in the
+/// feed, by tag and in search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PostStatus {
+    /// Seen only by its author and admins.
+    Draft,
+    /// A draft that is published at `publish_at`.
+    Scheduled,
+    #[default]
+    Published,
+    /// No longer listed, but still readable by anyone who has the link.
+    Archived,
+}
+
+impl PostStatus {
+    pub fn parse(value: &str) -> Option<PostStatus> {
+        match value {
+            "draft" => Some(PostStatus::Draft),
+            "scheduled" => Some(PostStatus::Scheduled),
+            "published" => Some(PostStatus::Published),
+            "archived" => Some(PostStatus::Archived),
+            _ => None,
+        }
+    }
+
+    /// Whether anyone may read a post in this state, not only its author.
+    pub fn is_public(self) -> bool {
+        matches!(self, PostStatus::Published | PostStatus::Archived)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Post {
     title: String,
+    /// CommonMark, with tables.
     body: String,
+    /// `body` rendered and sanitized, ready to put in a page.
+    #[serde(default)]
+    body_html: String,
     author: String,
+    /// When the post was created or, for one that started as a draft, first
+    /// published.
     datetime: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
     uuid: Uuid,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    #[serde(default)]
+    status: PostStatus,
+    /// When a scheduled post is due to be published; unset otherwise.
+    #[serde(default)]
+    publish_at: Option<DateTime<Utc>>,
+    /// When the post was moved to the trash; unset while it is not in it.
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Post {
@@ -17,13 +90,570 @@ impl Post {
         Post {
             title: title.to_string(),
             body: body.to_string(),
+            body_html: markdown::to_html(body),
             author: author.to_string(),
             datetime,
+            updated_at: datetime,
             uuid,
+            tags: BTreeSet::new(),
+            status: PostStatus::Published,
+            publish_at: None,
+            deleted_at: None,
         }
     }
 
     pub fn uuid(&self) -> &Uuid {
         &self.uuid
     }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn body_html(&self) -> &str {
+        &self.body_html
+    }
+
+    /// Renders `body_html` again, for posts stored before it existed or
+    /// rendered under older sanitizing rules.
+    pub fn render_body(&mut self) {
+        self.body_html = markdown::to_html(&self.body);
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn datetime(&self) -> &DateTime<Utc> {
+        &self.datetime
+    }
+
+    pub fn updated_at(&self) -> &DateTime<Utc> {
+        &self.updated_at
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    pub fn status(&self) -> PostStatus {
+        self.status
+    }
+
+    pub fn publish_at(&self) -> Option<&DateTime<Utc>> {
+        self.publish_at.as_ref()
+    }
+
+    /// Whether the post is scheduled and its time has come. Posts in the
+    /// trash are never due.
+    pub fn is_due(&self, now: &DateTime<Utc>) -> bool {
+        self.status == PostStatus::Scheduled
+            && self.deleted_at.is_none()
+            && self.publish_at.is_some_and(|at| at <= *now)
+    }
+
+    pub fn deleted_at(&self) -> Option<&DateTime<Utc>> {
+        self.deleted_at.as_ref()
+    }
+
+    pub fn is_trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Moves the post to the trash as of `now`. Its status is kept, so that
+    /// restoring it puts it back where it was.
+    pub fn move_to_trash(&mut self, now: DateTime<Utc>) {
+        self.deleted_at = Some(now);
+    }
+
+    pub fn restore_from_trash(&mut self) {
+        self.deleted_at = None;
+    }
+
+    /// The client-editable fields of the post, as a `NewPost`.
+    pub fn editable(&self) -> NewPost {
+        NewPost {
+            title: Some(self.title.clone()),
+            body: Some(self.body.clone()),
+            author: Some(self.author.clone()),
+            tags: Some(self.tags.iter().cloned().collect()),
+            note: None,
+            status: Some(self.status),
+            publish_at: self.publish_at,
+        }
+    }
+
+    /// Replaces the editable fields with the ones from an already validated
+    /// `NewPost` and bumps `updated_at`. The status is kept if the `NewPost`
+    /// leaves it out.
+    pub fn replace(&mut self, new_post: NewPost) {
+        let now = Utc::now();
+        self.tags = new_post.normalized_tags();
+        if let Some(status) = new_post.status {
+            self.set_status(status, new_post.publish_at, now);
+        }
+        self.title = new_post.title.unwrap_or_default();
+        self.body = new_post.body.unwrap_or_default();
+        self.render_body();
+        self.author = new_post.author.unwrap_or_default();
+        self.updated_at = now;
+    }
+
+    /// Publishes the post, as of `now`.
+    pub fn publish(&mut self, now: DateTime<Utc>) {
+        self.set_status(PostStatus::Published, None, now);
+        self.updated_at = now;
+    }
+
+    /// A draft that gets published is dated as of then, so that it does not
+    /// turn up in the feed among posts from when it was started.
+    fn set_status(
+        &mut self,
+        status: PostStatus,
+        publish_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) {
+        if status == PostStatus::Published && !self.status.is_public() {
+            self.datetime = now;
+        }
+        self.status = status;
+        self.publish_at = publish_at.filter(|_| status == PostStatus::Scheduled);
+    }
+}
+
+/// Body of a `POST /post` request. Every field is optional here so that a
+/// missing field can be reported as a validation error instead of a parse error.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct NewPost {
+    pub title: Option<String>,
+    /// CommonMark, with tables. Raw HTML in it is sanitized when rendered.
+    pub body: Option<String>,
+    pub author: Option<String>,
+    /// Optional; a post without tags has an empty set.
+    pub tags: Option<Vec<String>>,
+    /// Why the change was made, kept with the revision it creates.
+    #[serde(default, skip_serializing)]
+    pub note: Option<String>,
+    /// `published` for a new post if left out; an update that leaves it out
+    /// keeps the current status.
+    #[serde(default)]
+    pub status: Option<PostStatus>,
+    /// Required with the `scheduled` status and ignored with any other.
+    #[serde(default)]
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+impl NewPost {
+    /// Returns the names of the fields that are missing, blank or invalid.
+    pub fn invalid_fields(&self) -> Vec<&'static str> {
+        let fields = [
+            ("title", &self.title),
+            ("body", &self.body),
+            ("author", &self.author),
+        ];
+        let mut invalid: Vec<&'static str> = fields
+            .iter()
+            .filter(|(_, value)| value.as_ref().is_none_or(|v| v.trim().is_empty()))
+            .map(|(name, _)| *name)
+            .collect();
+        let tags = self.tags.as_deref().unwrap_or_default();
+        if tags.len() > MAX_TAGS || tags.iter().any(|tag| normalize_tag(tag).is_none()) {
+            invalid.push("tags");
+        }
+        if self.status == Some(PostStatus::Scheduled) && self.publish_at.is_none() {
+            invalid.push("publish_at");
+        }
+        invalid
+    }
+
+    fn normalized_tags(&self) -> BTreeSet<String> {
+        self.tags
+            .iter()
+            .flatten()
+            .filter_map(|tag| normalize_tag(tag))
+            .collect()
+    }
+
+    /// Builds the stored `Post`, assigning the server-side uuid and timestamp.
+    pub fn into_post(self) -> Post {
+        let now = Utc::now();
+        self.into_post_at(Uuid::new_v4(), now, now)
+    }
+
+    /// Builds a `Post` whose uuid and timestamps come from elsewhere, such as
+    /// an import.
+    pub fn into_post_at(
+        self,
+        uuid: Uuid,
+        datetime: DateTime<Utc>,
+        updated_at: DateTime<Utc>,
+    ) -> Post {
+        let mut post = Post::new(
+            self.title.as_deref().unwrap_or_default(),
+            self.body.as_deref().unwrap_or_default(),
+            self.author.as_deref().unwrap_or_default(),
+            datetime,
+            uuid,
+        );
+        post.updated_at = updated_at;
+        post.tags = self.normalized_tags();
+        post.status = self.status.unwrap_or_default();
+        post.publish_at = self.publish_at.filter(|_| post.status == PostStatus::Scheduled);
+        post
+    }
+}
+
+/// Who changed a post, and why.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub editor: String,
+    pub note: Option<String>,
+}
+
+impl Edit {
+    pub fn new(editor: &str, note: Option<String>) -> Edit {
+        Edit {
+            editor: editor.to_string(),
+            note: note.filter(|note| !note.trim().is_empty()),
+        }
+    }
+}
+
+/// A post as one change left it. Revisions of a post are numbered from 1,
+/// for the version it was created with.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Revision {
+    post_uuid: Uuid,
+    number: u32,
+    editor: String,
+    datetime: DateTime<Utc>,
+    note: Option<String>,
+    title: String,
+    body: String,
+    author: String,
+    tags: BTreeSet<String>,
+}
+
+impl Revision {
+    /// Revision `number` of `post`, made by `edit` when the post was last
+    /// updated.
+    pub fn new(number: u32, post: &Post, edit: Edit) -> Revision {
+        Revision {
+            post_uuid: post.uuid,
+            number,
+            editor: edit.editor,
+            datetime: post.updated_at,
+            note: edit.note,
+            title: post.title.clone(),
+            body: post.body.clone(),
+            author: post.author.clone(),
+            tags: post.tags.clone(),
+        }
+    }
+
+    pub fn post_uuid(&self) -> &Uuid {
+        &self.post_uuid
+    }
+
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn editor(&self) -> &str {
+        &self.editor
+    }
+
+    pub fn datetime(&self) -> &DateTime<Utc> {
+        &self.datetime
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    /// Whether `post` has the content this revision records, whatever its
+    /// status.
+    pub fn records(&self, post: &Post) -> bool {
+        self.title == post.title
+            && self.body == post.body
+            && self.author == post.author
+            && self.tags == post.tags
+    }
+
+    /// The editable fields as they were in this revision. Revisions do not
+    /// record the status, so restoring one leaves it as it is.
+    pub fn editable(&self) -> NewPost {
+        NewPost {
+            title: Some(self.title.clone()),
+            body: Some(self.body.clone()),
+            author: Some(self.author.clone()),
+            tags: Some(self.tags.iter().cloned().collect()),
+            note: None,
+            status: None,
+            publish_at: None,
+        }
+    }
+}
+
+/// Fields of a `Post` that a JSON merge patch may touch, and the `note`
+/// that may come along with them.
+const EDITABLE_FIELDS: [&str; 7] = [
+    "title",
+    "body",
+    "author",
+    "tags",
+    "note",
+    "status",
+    "publish_at",
+];
+
+/// Applies a JSON merge patch (RFC 7396) to the editable fields of `post`.
+/// Returns the merged fields, or the names of the fields the patch is not
+/// allowed to change.
+pub fn merge_patch(post: &Post, patch: &Value) -> Result<Value, Vec<String>> {
+    let patch_fields = match patch.as_object() {
+        Some(fields) => fields,
+        None => return Err(vec!["<root>".to_string()]),
+    };
+
+    let forbidden: Vec<String> = patch_fields
+        .keys()
+        .filter(|key| !EDITABLE_FIELDS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    if !forbidden.is_empty() {
+        return Err(forbidden);
+    }
+
+    let mut target = json!(post.editable());
+    apply_merge_patch(&mut target, patch);
+    Ok(target)
+}
+
+fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    let patch_fields = match patch.as_object() {
+        Some(fields) => fields,
+        None => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = json!({});
+    }
+    let target_fields = target.as_object_mut().unwrap();
+    for (key, value) in patch_fields {
+        if value.is_null() {
+            target_fields.remove(key);
+        } else {
+            apply_merge_patch(target_fields.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// A comment on a post, or a reply to another comment when `parent` is set.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Comment {
+    uuid: Uuid,
+    post_uuid: Uuid,
+    parent: Option<Uuid>,
+    author: String,
+    body: String,
+    datetime: DateTime<Utc>,
+}
+
+impl Comment {
+    pub fn new(post_uuid: Uuid, parent: Option<Uuid>, author: &str, body: &str) -> Comment {
+        Comment {
+            uuid: Uuid::new_v4(),
+            post_uuid,
+            parent,
+            author: author.to_string(),
+            body: body.to_string(),
+            datetime: Utc::now(),
+        }
+    }
+
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    pub fn post_uuid(&self) -> &Uuid {
+        &self.post_uuid
+    }
+
+    pub fn parent(&self) -> Option<&Uuid> {
+        self.parent.as_ref()
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn datetime(&self) -> &DateTime<Utc> {
+        &self.datetime
+    }
+}
+
+/// Body of `POST /post/:id/comments`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct NewComment {
+    pub body: Option<String>,
+    /// The comment this one replies to, if any.
+    pub parent: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// A registered account. Only the salted password hash is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    username: String,
+    password_hash: String,
+    role: Role,
+    created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn new(username: &str, password_hash: String, role: Role) -> User {
+        User {
+            username: username.to_string(),
+            password_hash,
+            role,
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password_hash(&self) -> &str {
+        &self.password_hash
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn set_role(&mut self, role: Role) {
+        self.role = role;
+    }
+
+    pub fn set_password_hash(&mut self, password_hash: String) {
+        self.password_hash = password_hash;
+    }
+}
+
+/// A URL that is sent the changes to posts as they happen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    id: Uuid,
+    url: String,
+    /// Key of the HMAC-SHA256 signature every delivery carries.
+    secret: String,
+    events: Vec<EventKind>,
+    created_by: String,
+    created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, secret: String, events: Vec<EventKind>, created_by: &str) -> Webhook {
+        Webhook {
+            id: Uuid::new_v4(),
+            url: url.to_string(),
+            secret,
+            events,
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    pub fn events(&self) -> &[EventKind] {
+        &self.events
+    }
+
+    pub fn created_by(&self) -> &str {
+        &self.created_by
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    /// Whether the webhook is sent events of this kind.
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.events.contains(&kind)
+    }
+}
+
+/// Body of `POST /admin/webhooks`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct NewWebhook {
+    /// An `http` or `https` URL, which deliveries are POSTed to.
+    pub url: Option<String>,
+    /// Which events to send; all of them if left out.
+    pub events: Option<Vec<EventKind>>,
+}
+
+impl NewWebhook {
+    /// Returns the names of the fields that are missing or invalid.
+    pub fn invalid_fields(&self) -> Vec<&'static str> {
+        let mut invalid = Vec::new();
+        let valid_url = self.url.as_deref().is_some_and(|url| {
+            iron::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        });
+        if !valid_url {
+            invalid.push("url");
+        }
+        if self.events.as_ref().is_some_and(Vec::is_empty) {
+            invalid.push("events");
+        }
+        invalid
+    }
+}
+
+/// Body of `POST /register` and `POST /login`.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
 }
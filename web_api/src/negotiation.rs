@@ -0,0 +1,140 @@
+use crate::error::ApiError;
+
+use iron::headers::ContentType;
+use iron::mime::Mime;
+use iron::{status, IronError, Request, Response};
+use serde_json::json;
+
+/// A representation a handler can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Atom,
+    Rss,
+    Html,
+    /// One JSON document per line.
+    JsonLines,
+    Csv,
+}
+
+impl Format {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Atom => "application/atom+xml",
+            Format::Rss => "application/rss+xml",
+            Format::Html => "text/html",
+            Format::JsonLines => "application/x-ndjson",
+            Format::Csv => "text/csv",
+        }
+    }
+
+    pub fn content_type(self) -> ContentType {
+        let mime: Mime = format!("{}; charset=utf-8", self.media_type())
+            .parse()
+            .expect("media types parse");
+        ContentType(mime)
+    }
+}
+
+/// One media range of an `Accept` header, e.g. `text/*;q=0.5`.
+struct MediaRange {
+    kind: String,
+    subtype: String,
+    quality: f32,
+}
+
+impl MediaRange {
+    fn parse(range: &str) -> Option<MediaRange> {
+        let mut params = range.split(';');
+        let (kind, subtype) = params.next()?.trim().split_once('/')?;
+        let mut quality = 1.0;
+        for param in params {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse().ok()?;
+                }
+            }
+        }
+        Some(MediaRange {
+            kind: kind.trim().to_ascii_lowercase(),
+            subtype: subtype.trim().to_ascii_lowercase(),
+            quality,
+        })
+    }
+
+    /// How specifically this range names `media_type`: 0 if it doesn't
+    /// match, then `*/*`, `type/*` and `type/subtype` in increasing order.
+    fn specificity(&self, media_type: &str) -> u8 {
+        let (kind, subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+        if self.kind == "*" && self.subtype == "*" {
+            1
+        } else if self.kind == kind && self.subtype == "*" {
+            2
+        } else if self.kind == kind && self.subtype == subtype {
+            3
+        } else {
+            0
+        }
+    }
+}
+
+/// Picks the format to answer with from those a handler `offers`, by the
+/// request's `Accept` header. Each format takes the quality of the most
+/// specific range matching it; among equally acceptable formats the one
+/// offered first wins, and no `Accept` header at all accepts the first.
+/// Answers 406 when nothing offered is acceptable.
+pub fn negotiate(req: &Request, offers: &[Format]) -> Result<Format, IronError> {
+    let ranges: Vec<MediaRange> = match req.headers.get_raw("Accept") {
+        Some(values) => values
+            .iter()
+            .filter_map(|value| std::str::from_utf8(value).ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(MediaRange::parse)
+            .collect(),
+        None => Vec::new(),
+    };
+    if ranges.is_empty() {
+        return Ok(offers[0]);
+    }
+
+    let mut best: Option<(Format, f32)> = None;
+    for &format in offers {
+        let quality = ranges
+            .iter()
+            .map(|range| (range.specificity(format.media_type()), range.quality))
+            .filter(|(specificity, _)| *specificity > 0)
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, quality)| quality);
+        if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+            best = Some((format, quality));
+        }
+    }
+    best.map(|(format, _)| format).ok_or_else(|| {
+        let offered: Vec<&str> = offers.iter().map(|format| format.media_type()).collect();
+        let mut err: IronError = ApiError::new(
+            status::NotAcceptable,
+            "not_acceptable",
+            "none of the media types in Accept can be produced",
+        )
+        .with_details(json!(offered))
+        .into();
+        add_vary(&mut err.response, "Accept");
+        err
+    })
+}
+
+/// Adds `header` to the `Vary` of a response, keeping what is already there.
+pub fn add_vary(res: &mut Response, header: &str) {
+    let mut names: Vec<String> = res
+        .headers
+        .get_raw("Vary")
+        .and_then(|values| values.first())
+        .and_then(|value| std::str::from_utf8(value).ok())
+        .map(|value| value.split(',').map(|name| name.trim().to_string()).collect())
+        .unwrap_or_default();
+    if !names.iter().any(|name| name.eq_ignore_ascii_case(header)) {
+        names.push(header.to_string());
+    }
+    res.headers.set_raw("Vary", vec![names.join(", ").into_bytes()]);
+}
@@ -0,0 +1,636 @@
+use crate::comments::CommentPage;
+use crate::conditional;
+use crate::feed::FeedPage;
+use crate::error::ErrorBody;
+use crate::handlers::{Account, SearchResults, TagList, Token};
+use crate::models::{Comment, Credentials, NewComment, NewPost, NewWebhook, Post, Revision};
+use crate::revisions::{RestoreRequest, RevisionDiff, RevisionList};
+use crate::transfer::{ImportReport, PostRecord};
+use crate::trash::TrashList;
+use crate::webhooks::{DeliveryList, RegisteredWebhook, WebhookInfo, WebhookList};
+
+use iron::headers::{ETag, EntityTag};
+use iron::{status, Handler, IronResult, Request, Response};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+/// Turns a `Router` path such as `/post/:id` into an OpenAPI path template
+/// such as `/post/{id}`.
+pub fn path_template(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Collects the operations of the document, generating the schema of every
+/// body from the Rust type the handler (de)serializes.
+struct Spec {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Spec {
+    fn new() -> Spec {
+        Spec {
+            generator: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>()).expect("schemas serialize")
+    }
+
+    fn json<T: JsonSchema>(&mut self) -> Value {
+        json!({ "application/json": { "schema": self.schema::<T>() } })
+    }
+
+    fn body<T: JsonSchema>(&mut self) -> Value {
+        json!({ "required": true, "content": self.json::<T>() })
+    }
+
+    fn error(&mut self, description: &str) -> Value {
+        json!({ "description": description, "content": self.json::<ErrorBody>() })
+    }
+
+    /// Adds an operation. Every operation may also be refused by the rate
+    /// limiter, which is documented here once for all of them.
+    fn operation(&mut self, method: &str, path: &str, mut operation: Value) {
+        let too_many = self.error("Rate limit exceeded; see `Retry-After`.");
+        operation["responses"]["429"] = too_many;
+        let item = self
+            .paths
+            .entry(path_template(path))
+            .or_insert_with(|| json!({}));
+        item[method] = operation;
+    }
+
+    fn finish(mut self) -> Value {
+        let schemas = self.generator.take_definitions();
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "web_api",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "A small blog API. Every path also answers `OPTIONS`, \
+                    including CORS preflight requests.",
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer" },
+                },
+            },
+        })
+    }
+}
+
+fn query(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+/// A uuid in the path, as `:id`.
+fn path_id(description: &str) -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string", "format": "uuid" },
+    })
+}
+
+fn if_match(description: &str) -> Value {
+    json!({
+        "name": "If-Match",
+        "in": "header",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn feed_params() -> Vec<Value> {
+    vec![
+        query("limit", "Posts per page, 1 to 100; 20 by default."),
+        query("cursor", "`next_cursor` of the previous page."),
+        query("sort", "`datetime`, `-datetime` or `title`."),
+        query("author", "Only posts by this author."),
+        query("since", "Only posts from this RFC 3339 time or date on."),
+        query("until", "Only posts before this RFC 3339 time or date."),
+        query("title", "Only posts whose title contains this, ignoring case."),
+        query(
+            "tag",
+            "Only posts with one of these `|`-separated tags; repeat to require several.",
+        ),
+        query(
+            "status",
+            "Only posts in one of these `|`-separated states; `published` by default. \
+                `draft` and `scheduled` need a bearer token and list the holder's own posts.",
+        ),
+    ]
+}
+
+fn feed_operation(spec: &mut Spec, summary: &str, params: Vec<Value>) -> Value {
+    json!({
+        "summary": summary,
+        "parameters": params,
+        "responses": {
+            "200": {
+                "description": "One page of posts, as JSON or as an Atom or RSS feed.",
+                "content": {
+                    "application/json": { "schema": spec.schema::<FeedPage>() },
+                    "application/atom+xml": { "schema": { "type": "string" } },
+                    "application/rss+xml": { "schema": { "type": "string" } },
+                },
+            },
+            "304": { "description": "Not modified since `If-None-Match`/`If-Modified-Since`." },
+            "400": spec.error("A query parameter is invalid."),
+            "401": spec.error("Drafts were asked for without a valid bearer token."),
+            "403": spec.error("Only admins may list drafts by someone else."),
+            "406": spec.error("None of the accepted media types can be produced."),
+        },
+    })
+}
+
+/// The OpenAPI 3 document describing every route of the API.
+pub fn document() -> Value {
+    let mut spec = Spec::new();
+    let auth = json!([{ "bearer": [] }]);
+    let post_id = path_id("The post's uuid.");
+    let post_if_match = if_match("`ETag` of the post as last read.");
+    let webhook_id = path_id("The webhook's id, as given when it was registered.");
+    let webhook_if_match = if_match("`ETag` of the webhook as last read.");
+
+    let operation = feed_operation(&mut spec, "List posts", feed_params());
+    spec.operation("get", "/post_feed", operation);
+
+    let operation = json!({
+        "summary": "Stream changes to posts",
+        "description": "Server-sent events named `created`, `updated` and `deleted`, whose \
+            data is the post, or only its `uuid` once deleted. They follow the feed: a post \
+            is created when it gets published and deleted when it is archived or unpublished. \
+            A client that missed events the server no longer holds gets a `reset` event and \
+            should reload the feed.",
+        "parameters": [{
+            "name": "Last-Event-ID",
+            "in": "header",
+            "description": "Id of the last event received; the stream resumes after it.",
+            "schema": { "type": "integer", "minimum": 0 },
+        }],
+        "responses": {
+            "200": {
+                "description": "An event stream that stays open.",
+                "content": { "text/event-stream": { "schema": { "type": "string" } } },
+            },
+            "400": spec.error("`Last-Event-ID` is not an event id."),
+            "503": spec.error("Too many event streams are open; see `Retry-After`."),
+        },
+    });
+    spec.operation("get", "/post_feed/stream", operation);
+
+    let operation = json!({
+        "summary": "Create a post",
+        "security": auth,
+        "requestBody": spec.body::<NewPost>(),
+        "responses": {
+            "201": { "description": "The new post; `Location` names it.", "content": spec.json::<Post>() },
+            "400": spec.error("The body is not JSON."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may post as someone else."),
+            "413": spec.error("The body is too large."),
+            "422": spec.error("Fields are missing, blank or invalid."),
+        },
+    });
+    spec.operation("post", "/post", operation);
+
+    let operation = json!({
+        "summary": "Get a post",
+        "description": "Drafts and scheduled posts are found only by their author and admins.",
+        "parameters": [post_id],
+        "responses": {
+            "200": {
+                "description": "The post, as JSON or as an HTML page.",
+                "content": {
+                    "application/json": { "schema": spec.schema::<Post>() },
+                    "text/html": { "schema": { "type": "string" } },
+                },
+            },
+            "304": { "description": "Not modified since `If-None-Match`/`If-Modified-Since`." },
+            "400": spec.error("The id is not a UUID."),
+            "404": spec.error("No post with this id."),
+            "406": spec.error("None of the accepted media types can be produced."),
+        },
+    });
+    spec.operation("get", "/post/:id", operation);
+
+    for (method, summary, description) in [
+        ("put", "Replace a post", "All editable fields of the post."),
+        ("patch", "Update a post", "A JSON merge patch (RFC 7396) of the editable fields."),
+    ] {
+        let mut body = spec.body::<NewPost>();
+        body["description"] = json!(description);
+        let operation = json!({
+            "summary": summary,
+            "security": auth,
+            "parameters": [post_id, post_if_match],
+            "requestBody": body,
+            "responses": {
+                "200": { "description": "The updated post.", "content": spec.json::<Post>() },
+                "400": spec.error("The id is not a UUID or the body is not JSON."),
+                "401": spec.error("No valid bearer token."),
+                "403": spec.error("Only the author or an admin may edit the post."),
+                "404": spec.error("No post with this id."),
+                "412": spec.error("`If-Match` does not match the current post."),
+                "413": spec.error("The body is too large."),
+                "422": spec.error("Fields are missing, blank, invalid or read-only."),
+                "428": spec.error("`If-Match` is missing."),
+            },
+        });
+        spec.operation(method, "/post/:id", operation);
+    }
+
+    let operation = json!({
+        "summary": "Delete a post",
+        "description": "Moves the post to the trash, where it is hidden everywhere but \
+            `/trash`. It can be restored from there until the retention period is over, when it \
+            is purged for good with its comments and revisions.",
+        "security": auth,
+        "parameters": [post_id, post_if_match],
+        "responses": {
+            "204": { "description": "The post is in the trash." },
+            "400": spec.error("The id is not a UUID."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only the author or an admin may delete the post."),
+            "404": spec.error("No post with this id."),
+            "412": spec.error("`If-Match` does not match the current post."),
+            "428": spec.error("`If-Match` is missing."),
+        },
+    });
+    spec.operation("delete", "/post/:id", operation);
+
+    let operation = json!({
+        "summary": "List the posts in the trash",
+        "description": "Admins see every post in the trash, other users their own.",
+        "security": auth,
+        "responses": {
+            "200": { "description": "The posts in the trash, most recently deleted first.", "content": spec.json::<TrashList>() },
+            "401": spec.error("No valid bearer token."),
+        },
+    });
+    spec.operation("get", "/trash", operation);
+
+    let operation = json!({
+        "summary": "Restore a post from the trash",
+        "description": "Puts the post back as it was when it was deleted.",
+        "security": auth,
+        "parameters": [post_id],
+        "responses": {
+            "200": { "description": "The restored post.", "content": spec.json::<Post>() },
+            "400": spec.error("The id is not a UUID."),
+            "401": spec.error("No valid bearer token."),
+            "404": spec.error("None of the user's posts in the trash has this id."),
+        },
+    });
+    spec.operation("post", "/trash/:id/restore", operation);
+
+    let operation = json!({
+        "summary": "List the comments on a post",
+        "parameters": [
+            post_id,
+            query("limit", "Comments per page, 1 to 100; 20 by default."),
+            query("cursor", "`next_cursor` of the previous page."),
+            query("depth", "Levels of replies to include, 0 to 10; 3 by default."),
+            query("replies", "Replies shown below any one comment, 1 to 100; 10 by default."),
+            query("parent", "Page through the replies to this comment instead."),
+        ],
+        "responses": {
+            "200": { "description": "One page of comment threads, oldest first.", "content": spec.json::<CommentPage>() },
+            "400": spec.error("The id is not a UUID or a query parameter is invalid."),
+            "404": spec.error("No post with this id."),
+        },
+    });
+    spec.operation("get", "/post/:id/comments", operation);
+
+    let operation = json!({
+        "summary": "Comment on a post",
+        "security": auth,
+        "parameters": [post_id],
+        "requestBody": spec.body::<NewComment>(),
+        "responses": {
+            "201": { "description": "The new comment.", "content": spec.json::<Comment>() },
+            "400": spec.error("The id is not a UUID or the body is not JSON."),
+            "401": spec.error("No valid bearer token."),
+            "404": spec.error("No post with this id."),
+            "413": spec.error("The body is too large."),
+            "422": spec.error("The body is blank or the parent is not a comment on this post."),
+        },
+    });
+    spec.operation("post", "/post/:id/comments", operation);
+
+    let revision_number = json!({
+        "name": "n",
+        "in": "path",
+        "required": true,
+        "description": "Revision number; a post is created as revision 1.",
+        "schema": { "type": "integer", "minimum": 1 },
+    });
+
+    let operation = json!({
+        "summary": "List the revisions of a post",
+        "description": "Every edit of the title, body, author or tags is a revision; changing \
+            only the status is not.",
+        "parameters": [post_id],
+        "responses": {
+            "200": { "description": "Every revision, oldest first, without content.", "content": spec.json::<RevisionList>() },
+            "400": spec.error("The id is not a UUID."),
+            "404": spec.error("No post with this id."),
+        },
+    });
+    spec.operation("get", "/post/:id/revisions", operation);
+
+    let operation = json!({
+        "summary": "Get a revision of a post",
+        "parameters": [post_id, revision_number],
+        "responses": {
+            "200": { "description": "The post as this revision left it.", "content": spec.json::<Revision>() },
+            "400": spec.error("The id is not a UUID or `n` is not a revision number."),
+            "404": spec.error("No post with this id, or no such revision."),
+        },
+    });
+    spec.operation("get", "/post/:id/revisions/:n", operation);
+
+    let operation = json!({
+        "summary": "Diff two revisions of a post",
+        "parameters": [
+            post_id,
+            revision_number,
+            query("from", "Revision to compare with; the one before `n` by default, 0 for none."),
+        ],
+        "responses": {
+            "200": { "description": "The changed fields, line by line.", "content": spec.json::<RevisionDiff>() },
+            "400": spec.error("The id is not a UUID or a revision number is invalid."),
+            "404": spec.error("No post with this id, or no such revision."),
+        },
+    });
+    spec.operation("get", "/post/:id/revisions/:n/diff", operation);
+
+    let mut body = spec.body::<RestoreRequest>();
+    body["required"] = json!(false);
+    let operation = json!({
+        "summary": "Restore a revision of a post",
+        "description": "Makes the post what revision `n` left it as, recorded as a new revision.",
+        "security": auth,
+        "parameters": [post_id, revision_number],
+        "requestBody": body,
+        "responses": {
+            "200": { "description": "The restored post.", "content": spec.json::<Post>() },
+            "400": spec.error("The id is not a UUID, `n` is not a revision number or the body is not JSON."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only the author or an admin may restore the post."),
+            "404": spec.error("No post with this id, or no such revision."),
+            "413": spec.error("The body is too large."),
+        },
+    });
+    spec.operation("post", "/post/:id/revisions/:n/restore", operation);
+
+    let operation = json!({
+        "summary": "List tags",
+        "responses": {
+            "200": { "description": "Every tag in use, with how many posts carry it.", "content": spec.json::<TagList>() },
+        },
+    });
+    spec.operation("get", "/tags", operation);
+
+    let mut params = feed_params();
+    params.insert(
+        0,
+        json!({ "name": "tag", "in": "path", "required": true, "schema": { "type": "string" } }),
+    );
+    let operation = feed_operation(&mut spec, "List posts with a tag", params);
+    spec.operation("get", "/tags/:tag/posts", operation);
+
+    let operation = json!({
+        "summary": "Search posts",
+        "parameters": [query(
+            "q",
+            "Terms, \"quoted phrases\" and -excluded terms; every term must match.",
+        )],
+        "responses": {
+            "200": { "description": "Matching posts, best first.", "content": spec.json::<SearchResults>() },
+            "400": spec.error("`q` has nothing to look for."),
+        },
+    });
+    spec.operation("get", "/search", operation);
+
+    let operation = json!({
+        "summary": "Register an account",
+        "description": "Accounts made here always have the `user` role. Admin accounts are \
+            set up in the server configuration, and their names are taken.",
+        "requestBody": spec.body::<Credentials>(),
+        "responses": {
+            "201": { "description": "The new account.", "content": spec.json::<Account>() },
+            "400": spec.error("The body is not JSON."),
+            "409": spec.error("The username is taken."),
+            "422": spec.error("The username or password is invalid."),
+        },
+    });
+    spec.operation("post", "/register", operation);
+
+    let operation = json!({
+        "summary": "Log in",
+        "requestBody": spec.body::<Credentials>(),
+        "responses": {
+            "200": { "description": "A bearer token.", "content": spec.json::<Token>() },
+            "400": spec.error("The body is not JSON."),
+            "401": spec.error("Unknown username or wrong password."),
+        },
+    });
+    spec.operation("post", "/login", operation);
+
+    let operation = json!({
+        "summary": "Import posts",
+        "description": "One post per JSON line, or CSV with a header row naming the columns \
+            and tags separated by spaces. The body is read as it streams in and stored a \
+            chunk of posts at a time, up to `max_import_bytes`. Invalid lines are reported \
+            and skipped; a post whose `uuid` exists already is replaced, unless it is in the \
+            trash. A body that cannot be read to the end is reported as a last failed line, \
+            with the posts before it imported.",
+        "security": auth,
+        "parameters": [query("dry_run", "`true` to only report what the import would do.")],
+        "requestBody": {
+            "required": true,
+            "content": {
+                "application/x-ndjson": { "schema": spec.schema::<PostRecord>() },
+                "text/csv": { "schema": { "type": "string" } },
+            },
+        },
+        "responses": {
+            "200": { "description": "What was imported and which lines were skipped.", "content": spec.json::<ImportReport>() },
+            "400": spec.error("`dry_run` is not `true` or `false`."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may import."),
+            "413": spec.error("The body is too large."),
+            "415": spec.error("The body is neither JSON Lines nor CSV."),
+        },
+    });
+    spec.operation("post", "/admin/import", operation);
+
+    let operation = json!({
+        "summary": "Export posts",
+        "security": auth,
+        "responses": {
+            "200": {
+                "description": "Every post, as JSON Lines or as CSV in the import's columns.",
+                "content": {
+                    "application/x-ndjson": { "schema": spec.schema::<Post>() },
+                    "text/csv": { "schema": { "type": "string" } },
+                },
+            },
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may export."),
+            "406": spec.error("None of the accepted media types can be produced."),
+        },
+    });
+    spec.operation("get", "/admin/export", operation);
+
+    let operation = json!({
+        "summary": "List webhooks",
+        "security": auth,
+        "responses": {
+            "200": { "description": "Every webhook, without its secret.", "content": spec.json::<WebhookList>() },
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may manage webhooks."),
+        },
+    });
+    spec.operation("get", "/admin/webhooks", operation);
+
+    let operation = json!({
+        "summary": "Register a webhook",
+        "description": "The URL is POSTed a JSON payload with `delivery`, `webhook`, `event`, \
+            `event_id` and `data` fields whenever a post is created, updated or deleted, as on \
+            `/post_feed/stream`. `X-Webhook-Signature` carries `sha256=` and the hex \
+            HMAC-SHA256 of the body, keyed with the secret. Deliveries that fail or get no 2xx \
+            answer are retried with exponential backoff. Deliveries are stored, and those still \
+            pending when the server restarts are retried after it starts again.",
+        "security": auth,
+        "requestBody": spec.body::<NewWebhook>(),
+        "responses": {
+            "201": { "description": "The webhook with its secret, which is not shown again.", "content": spec.json::<RegisteredWebhook>() },
+            "400": spec.error("The body is not JSON."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may manage webhooks."),
+            "413": spec.error("The body is too large."),
+            "422": spec.error("The URL is missing or invalid, or no events are wanted."),
+        },
+    });
+    spec.operation("post", "/admin/webhooks", operation);
+
+    let operation = json!({
+        "summary": "Get a webhook",
+        "security": auth,
+        "parameters": [webhook_id],
+        "responses": {
+            "200": { "description": "The webhook, without its secret.", "content": spec.json::<WebhookInfo>() },
+            "400": spec.error("The id is not a UUID."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may manage webhooks."),
+            "404": spec.error("No webhook with this id."),
+        },
+    });
+    spec.operation("get", "/admin/webhooks/:id", operation);
+
+    let operation = json!({
+        "summary": "Delete a webhook",
+        "security": auth,
+        "parameters": [webhook_id, webhook_if_match],
+        "responses": {
+            "204": { "description": "The webhook is gone; pending deliveries are not sent." },
+            "400": spec.error("The id is not a UUID."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may manage webhooks."),
+            "404": spec.error("No webhook with this id."),
+            "412": spec.error("`If-Match` does not match the current webhook."),
+            "428": spec.error("`If-Match` is missing."),
+        },
+    });
+    spec.operation("delete", "/admin/webhooks/:id", operation);
+
+    let operation = json!({
+        "summary": "List the deliveries to a webhook",
+        "description": "The most recent deliveries, newest first, up to 100 of them.",
+        "security": auth,
+        "parameters": [webhook_id],
+        "responses": {
+            "200": { "description": "The deliveries and how they went.", "content": spec.json::<DeliveryList>() },
+            "400": spec.error("The id is not a UUID."),
+            "401": spec.error("No valid bearer token."),
+            "403": spec.error("Only admins may manage webhooks."),
+            "404": spec.error("No webhook with this id."),
+        },
+    });
+    spec.operation("get", "/admin/webhooks/:id/deliveries", operation);
+
+    let operation = json!({
+        "summary": "Prometheus metrics",
+        "responses": {
+            "200": {
+                "description": "Request counts and latencies in the Prometheus text format.",
+                "content": { "text/plain": { "schema": { "type": "string" } } },
+            },
+        },
+    });
+    spec.operation("get", "/metrics", operation);
+
+    let operation = json!({
+        "summary": "This document",
+        "responses": {
+            "200": {
+                "description": "The OpenAPI 3 description of the API.",
+                "content": { "application/json": { "schema": { "type": "object" } } },
+            },
+        },
+    });
+    spec.operation("get", "/openapi.json", operation);
+
+    spec.finish()
+}
+
+/// Serves the OpenAPI document, rendered once up front.
+pub struct OpenApiHandler {
+    document: String,
+    etag: EntityTag,
+}
+
+impl OpenApiHandler {
+    pub fn new() -> OpenApiHandler {
+        let document = document().to_string();
+        let etag = conditional::etag_for(document.as_bytes());
+        OpenApiHandler { document, etag }
+    }
+}
+
+impl Default for OpenApiHandler {
+    fn default() -> OpenApiHandler {
+        OpenApiHandler::new()
+    }
+}
+
+impl Handler for OpenApiHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut res = Response::with((status::Ok, self.document.clone()));
+        res.headers.set(ETag(self.etag.clone()));
+        Ok(res)
+    }
+}
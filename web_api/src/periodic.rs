@@ -0,0 +1,34 @@
+use crate::database::Database;
+
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Starts a thread named `name` that wakes every `interval` and, when `due`
+/// says there is work as of now, does it with `run`. Looking is cheap and
+/// takes only the read lock; the write lock is taken only when something is
+/// due, so the task does not hold up writers every tick.
+pub fn spawn<D, R>(
+    name: &str,
+    database: Arc<RwLock<Database>>,
+    interval: Duration,
+    due: D,
+    mut run: R,
+) -> JoinHandle<()>
+where
+    D: Fn(&Database, DateTime<Utc>) -> bool + Send + 'static,
+    R: FnMut(&mut Database, DateTime<Utc>) + Send + 'static,
+{
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let now = Utc::now();
+            if !due(&database.read().unwrap_or_else(PoisonError::into_inner), now) {
+                continue;
+            }
+            run(&mut database.write().unwrap_or_else(PoisonError::into_inner), now);
+        })
+        .unwrap_or_else(|e| panic!("Unable to start the {} thread: {}", name, e))
+}
@@ -0,0 +1,151 @@
+use crate::models::Post;
+use crate::search::push_escaped;
+
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+
+/// What a feed document says about itself, besides its posts.
+#[derive(Debug)]
+pub struct FeedMeta<'a> {
+    pub title: &'a str,
+    /// Scheme and authority the server was reached at, e.g.
+    /// `http://localhost:8000`; post links are made absolute with it.
+    pub site: &'a str,
+    /// The feed's URL without a page cursor, which identifies it.
+    pub id: &'a str,
+    /// The URL this page was requested at.
+    pub self_url: &'a str,
+    /// The URL of the next page, if there is one.
+    pub next_url: Option<&'a str>,
+    pub updated: &'a DateTime<Utc>,
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    push_escaped(&mut out, text);
+    out
+}
+
+fn post_url(site: &str, post: &Post) -> String {
+    format!("{}/post/{}", site, post.uuid())
+}
+
+/// Renders a page of posts as an Atom 1.0 feed (RFC 4287), linking the next
+/// page as RFC 5005 describes.
+pub fn atom(meta: &FeedMeta, posts: &[&Post]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(out, "  <id>{}</id>", escape(meta.id));
+    let _ = writeln!(out, "  <title>{}</title>", escape(meta.title));
+    let _ = writeln!(out, "  <updated>{}</updated>", meta.updated.to_rfc3339());
+    let _ = writeln!(out, "  <link rel=\"self\" href=\"{}\"/>", escape(meta.self_url));
+    if let Some(next) = meta.next_url {
+        let _ = writeln!(out, "  <link rel=\"next\" href=\"{}\"/>", escape(next));
+    }
+    for post in posts {
+        out.push_str("  <entry>\n");
+        let _ = writeln!(out, "    <id>urn:uuid:{}</id>", post.uuid());
+        let _ = writeln!(out, "    <title>{}</title>", escape(post.title()));
+        let _ = writeln!(out, "    <updated>{}</updated>", post.updated_at().to_rfc3339());
+        let _ = writeln!(out, "    <published>{}</published>", post.datetime().to_rfc3339());
+        let _ = writeln!(
+            out,
+            "    <author><name>{}</name></author>",
+            escape(post.author())
+        );
+        let _ = writeln!(
+            out,
+            "    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>",
+            escape(&post_url(meta.site, post))
+        );
+        for tag in post.tags() {
+            let _ = writeln!(out, "    <category term=\"{}\"/>", escape(tag));
+        }
+        let _ = writeln!(
+            out,
+            "    <content type=\"html\">{}</content>",
+            escape(post.body_html())
+        );
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+/// Renders a page of posts as an RSS 2.0 feed.
+pub fn rss(meta: &FeedMeta, posts: &[&Post]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str(
+        "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n",
+    );
+    out.push_str("  <channel>\n");
+    let _ = writeln!(out, "    <title>{}</title>", escape(meta.title));
+    let _ = writeln!(out, "    <link>{}</link>", escape(meta.id));
+    let _ = writeln!(out, "    <description>{}</description>", escape(meta.title));
+    let _ = writeln!(
+        out,
+        "    <lastBuildDate>{}</lastBuildDate>",
+        meta.updated.to_rfc2822()
+    );
+    let _ = writeln!(
+        out,
+        "    <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}\"/>",
+        escape(meta.self_url)
+    );
+    if let Some(next) = meta.next_url {
+        let _ = writeln!(
+            out,
+            "    <atom:link rel=\"next\" type=\"application/rss+xml\" href=\"{}\"/>",
+            escape(next)
+        );
+    }
+    for post in posts {
+        out.push_str("    <item>\n");
+        let _ = writeln!(out, "      <title>{}</title>", escape(post.title()));
+        let _ = writeln!(out, "      <link>{}</link>", escape(&post_url(meta.site, post)));
+        let _ = writeln!(
+            out,
+            "      <guid isPermaLink=\"false\">urn:uuid:{}</guid>",
+            post.uuid()
+        );
+        let _ = writeln!(out, "      <pubDate>{}</pubDate>", post.datetime().to_rfc2822());
+        let _ = writeln!(out, "      <dc:creator>{}</dc:creator>", escape(post.author()));
+        for tag in post.tags() {
+            let _ = writeln!(out, "      <category>{}</category>", escape(tag));
+        }
+        let _ = writeln!(
+            out,
+            "      <description>{}</description>",
+            escape(post.body_html())
+        );
+        out.push_str("    </item>\n");
+    }
+    out.push_str("  </channel>\n</rss>\n");
+    out
+}
+
+/// Renders a post as a standalone HTML page, with its body as rendered from
+/// Markdown.
+pub fn post_html(post: &Post) -> String {
+    let title = escape(post.title());
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n");
+    out.push_str("<meta charset=\"utf-8\">\n");
+    let _ = writeln!(out, "<title>{}</title>", title);
+    out.push_str("</head>\n<body>\n<article>\n");
+    let _ = writeln!(out, "<h1>{}</h1>", title);
+    let _ = writeln!(
+        out,
+        "<p>By {} on <time datetime=\"{}\">{}</time></p>",
+        escape(post.author()),
+        post.datetime().to_rfc3339(),
+        post.datetime().format("%B %-d, %Y")
+    );
+    out.push_str(post.body_html());
+    if !post.tags().is_empty() {
+        let tags: Vec<String> = post.tags().iter().map(|tag| escape(tag)).collect();
+        let _ = writeln!(out, "<p>Tags: {}</p>", tags.join(", "));
+    }
+    out.push_str("</article>\n</body>\n</html>\n");
+    out
+}
@@ -0,0 +1,183 @@
+use crate::models::Revision;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Largest number of line pairs compared when diffing; past it the changed
+/// lines are shown as wholly replaced.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Body of `POST /post/:id/revisions/:n/restore`, which may be left out.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct RestoreRequest {
+    /// Why the post is rolled back; says which revision it was rolled back
+    /// to by default.
+    pub note: Option<String>,
+}
+
+/// A revision without its content, as listed.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RevisionSummary<'a> {
+    pub number: u32,
+    pub editor: &'a str,
+    pub datetime: &'a DateTime<Utc>,
+    pub note: Option<&'a str>,
+}
+
+impl<'a> From<&'a Revision> for RevisionSummary<'a> {
+    fn from(revision: &'a Revision) -> RevisionSummary<'a> {
+        RevisionSummary {
+            number: revision.number(),
+            editor: revision.editor(),
+            datetime: revision.datetime(),
+            note: revision.note(),
+        }
+    }
+}
+
+/// Every revision of a post, oldest first.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RevisionList<'a> {
+    pub revisions: Vec<RevisionSummary<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LineChange {
+    Equal,
+    Delete,
+    Insert,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DiffLine<'a> {
+    pub change: LineChange,
+    pub text: &'a str,
+}
+
+/// The lines of one field, old and new, in order.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FieldDiff<'a> {
+    pub field: &'static str,
+    pub lines: Vec<DiffLine<'a>>,
+}
+
+/// What changed between two revisions of a post; fields that did not change
+/// are left out.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RevisionDiff<'a> {
+    /// 0 when diffing against the post not existing yet.
+    pub from: u32,
+    pub to: u32,
+    pub fields: Vec<FieldDiff<'a>>,
+}
+
+/// Compares `from`, or nothing, with `to`, line by line. Tags are compared
+/// one per line.
+pub fn diff<'a>(from: Option<&'a Revision>, to: &'a Revision) -> RevisionDiff<'a> {
+    let tags = |revision: &'a Revision| revision.tags().iter().map(String::as_str).collect();
+    let old_tags: Vec<&str> = from.map(tags).unwrap_or_default();
+    let fields = [
+        ("title", from.map_or("", Revision::title), to.title()),
+        ("author", from.map_or("", Revision::author), to.author()),
+        ("body", from.map_or("", Revision::body), to.body()),
+    ];
+    let mut diffs: Vec<FieldDiff> = fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|&(field, old, new)| {
+            let old: Vec<&str> = old.lines().collect();
+            let new: Vec<&str> = new.lines().collect();
+            FieldDiff {
+                field,
+                lines: diff_lines(&old, &new),
+            }
+        })
+        .collect();
+    let new_tags: Vec<&str> = tags(to);
+    if old_tags != new_tags {
+        diffs.push(FieldDiff {
+            field: "tags",
+            lines: diff_lines(&old_tags, &new_tags),
+        });
+    }
+    RevisionDiff {
+        from: from.map_or(0, Revision::number),
+        to: to.number(),
+        fields: diffs,
+    }
+}
+
+/// A shortest edit script turning `old` into `new`, from their longest
+/// common subsequence.
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let line = |change, text: &&'a str| DiffLine { change, text };
+    let mut lines: Vec<DiffLine> = old[..prefix]
+        .iter()
+        .map(|text| line(LineChange::Equal, text))
+        .collect();
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    if old_middle.len() * new_middle.len() > MAX_DIFF_CELLS {
+        lines.extend(old_middle.iter().map(|text| line(LineChange::Delete, text)));
+        lines.extend(new_middle.iter().map(|text| line(LineChange::Insert, text)));
+    } else {
+        lines.extend(diff_middle(old_middle, new_middle));
+    }
+
+    lines.extend(old[old.len() - suffix..].iter().map(|text| line(LineChange::Equal, text)));
+    lines
+}
+
+fn diff_middle<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut common = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if old[i] == new[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine {
+                change: LineChange::Equal,
+                text: old[i],
+            });
+            i += 1;
+            j += 1;
+        } else if j == new.len()
+            || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            lines.push(DiffLine {
+                change: LineChange::Delete,
+                text: old[i],
+            });
+            i += 1;
+        } else {
+            lines.push(DiffLine {
+                change: LineChange::Insert,
+                text: new[j],
+            });
+            j += 1;
+        }
+    }
+    lines
+}
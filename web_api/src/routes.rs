@@ -0,0 +1,159 @@
+use iron::method::Method;
+use iron::Handler;
+use router::Router;
+use std::sync::Arc;
+
+/// A route as registered on the `Router`.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+    pub name: &'static str,
+}
+
+impl Route {
+    /// Whether `path` has the shape of this route; `:param` segments match
+    /// any non-empty segment and a final `*` matches whatever is left.
+    fn matches(&self, path: &str) -> bool {
+        let mut pattern: Vec<&str> = self.path.trim_matches('/').split('/').collect();
+        let mut segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if pattern.last() == Some(&"*") {
+            pattern.pop();
+            segments.truncate(pattern.len());
+        }
+        pattern.len() == segments.len()
+            && pattern.iter().zip(&segments).all(|(p, s)| {
+                if p.starts_with(':') {
+                    !s.is_empty()
+                } else {
+                    p == s
+                }
+            })
+    }
+
+    fn static_segments(&self) -> usize {
+        self.path
+            .split('/')
+            .filter(|s| !s.starts_with(':') && *s != "*")
+            .count()
+    }
+}
+
+/// Every route the server answers, shared with the middleware that needs to
+/// know them.
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Arc<Vec<Route>>,
+}
+
+impl RouteTable {
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// The route a request would be dispatched to. Like the `Router`, prefers
+    /// literal segments over `:param` ones.
+    pub fn recognize(&self, method: &Method, path: &str) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| &route.method == method && route.matches(path))
+            .max_by_key(|route| route.static_segments())
+    }
+}
+
+/// Registers handlers on a `Router` while keeping a `RouteTable` of them.
+pub struct Routes {
+    router: Router,
+    table: Vec<Route>,
+}
+
+impl Default for Routes {
+    fn default() -> Routes {
+        Routes::new()
+    }
+}
+
+impl Routes {
+    pub fn new() -> Routes {
+        Routes {
+            router: Router::new(),
+            table: Vec::new(),
+        }
+    }
+
+    pub fn route<H: Handler>(
+        &mut self,
+        method: Method,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.router.route(method.clone(), path, handler, name);
+        self.table.push(Route { method, path, name });
+        self
+    }
+
+    pub fn get<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Get, path, handler, name)
+    }
+
+    pub fn post<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Post, path, handler, name)
+    }
+
+    pub fn put<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Put, path, handler, name)
+    }
+
+    pub fn patch<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Patch, path, handler, name)
+    }
+
+    pub fn delete<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Delete, path, handler, name)
+    }
+
+    pub fn options<H: Handler>(
+        &mut self,
+        path: &'static str,
+        handler: H,
+        name: &'static str,
+    ) -> &mut Routes {
+        self.route(Method::Options, path, handler, name)
+    }
+
+    pub fn table(&self) -> RouteTable {
+        RouteTable {
+            routes: Arc::new(self.table.clone()),
+        }
+    }
+
+    pub fn into_router(self) -> Router {
+        self.router
+    }
+}
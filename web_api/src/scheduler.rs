@@ -0,0 +1,33 @@
+use crate::database::Database;
+use crate::periodic;
+
+use log::{error, info};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the scheduler looks for posts that are due, and so how late
+/// one may be published.
+pub const SCHEDULER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts a thread that publishes every scheduled post once its `publish_at`
+/// has passed, looking every `interval`. Publishing goes through the
+/// database like any other change, so it is stored and streamed to clients
+/// as usual.
+pub fn spawn(database: Arc<RwLock<Database>>, interval: Duration) -> JoinHandle<()> {
+    periodic::spawn(
+        "scheduler",
+        database,
+        interval,
+        |database, now| database.next_due().is_some_and(|at| at <= now),
+        |database, now| match database.publish_due(now) {
+            Ok(uuids) => {
+                for uuid in uuids {
+                    info!("published scheduled post {}", uuid);
+                }
+            }
+            // Left scheduled, so the next tick tries again.
+            Err(e) => error!("unable to publish scheduled posts: {:?}", e),
+        },
+    )
+}
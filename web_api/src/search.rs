@@ -0,0 +1,275 @@
+use crate::models::Post;
+
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// BM25 term-frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document-length normalization.
+const B: f64 = 0.75;
+/// Number of words of context kept on each side of the first hit in a snippet.
+const SNIPPET_CONTEXT: usize = 12;
+
+/// Splits text into lowercase alphanumeric tokens, with their byte ranges in
+/// the original text.
+fn tokens(text: &str) -> Vec<(String, usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                tokens.push((text[s..i].to_lowercase(), s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((text[s..].to_lowercase(), s, text.len()));
+    }
+    tokens
+}
+
+/// A parsed `q=` string.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    /// Single terms that must all appear.
+    pub terms: Vec<String>,
+    /// Quoted phrases that must all appear as consecutive terms.
+    pub phrases: Vec<Vec<String>>,
+    /// Terms and phrases prefixed with `-` that must not appear.
+    pub excluded: Vec<Vec<String>>,
+}
+
+impl Query {
+    /// Parses terms, `"quoted phrases"` and `-excluded` terms or phrases.
+    pub fn parse(q: &str) -> Query {
+        let mut query = Query::default();
+        let mut rest = q.trim_start();
+        while !rest.is_empty() {
+            let negated = rest.starts_with('-');
+            if negated {
+                rest = &rest[1..];
+            }
+            let (chunk, quoted, remainder) = if let Some(inner) = rest.strip_prefix('"') {
+                match inner.find('"') {
+                    Some(end) => (&inner[..end], true, &inner[end + 1..]),
+                    None => (inner, true, ""),
+                }
+            } else {
+                match rest.find(char::is_whitespace) {
+                    Some(end) => (&rest[..end], false, &rest[end..]),
+                    None => (rest, false, ""),
+                }
+            };
+            rest = remainder.trim_start();
+
+            let words: Vec<String> = tokens(chunk).into_iter().map(|(t, _, _)| t).collect();
+            if words.is_empty() {
+                continue;
+            }
+            if negated {
+                query.excluded.push(words);
+            } else if quoted && words.len() > 1 {
+                query.phrases.push(words);
+            } else {
+                query.terms.extend(words);
+            }
+        }
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.phrases.is_empty()
+    }
+
+    /// Every positive term, including the ones inside phrases.
+    fn positive_terms(&self) -> HashSet<&str> {
+        self.terms
+            .iter()
+            .chain(self.phrases.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Inverted index over the titles and bodies of posts.
+///
+/// For every term it keeps the positions at which the term occurs in each
+/// post, so phrases can be matched without looking at the posts themselves.
+/// Title and body share one position space with a gap between them, so a
+/// phrase never matches across the two.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, HashMap<Uuid, Vec<usize>>>,
+    documents: HashMap<Uuid, Document>,
+    total_length: usize,
+}
+
+/// What the index remembers about one post, to score it and to unindex it.
+#[derive(Debug)]
+struct Document {
+    length: usize,
+    terms: HashSet<String>,
+}
+
+impl SearchIndex {
+    pub fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+
+    /// Indexes a post, replacing what was indexed for the same uuid before.
+    pub fn insert(&mut self, post: &Post) {
+        self.remove(post.uuid());
+
+        let title = tokens(post.title());
+        let body = tokens(post.body());
+        let positions = title.iter().enumerate().chain(
+            body.iter()
+                .enumerate()
+                .map(|(i, t)| (i + title.len() + 1, t)),
+        );
+        let mut document = Document {
+            length: 0,
+            terms: HashSet::new(),
+        };
+        for (position, (term, _, _)) in positions {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .entry(*post.uuid())
+                .or_default()
+                .push(position);
+            document.terms.insert(term.clone());
+            document.length += 1;
+        }
+        self.total_length += document.length;
+        self.documents.insert(*post.uuid(), document);
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        if let Some(document) = self.documents.remove(uuid) {
+            self.total_length -= document.length;
+            for term in document.terms {
+                if let Some(docs) = self.postings.get_mut(&term) {
+                    docs.remove(uuid);
+                    if docs.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the uuids of the matching posts with their BM25 scores, best
+    /// match first.
+    pub fn search(&self, query: &Query) -> Vec<(Uuid, f64)> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let terms = query.positive_terms();
+        let mut candidates: Option<HashSet<Uuid>> = None;
+        for term in &terms {
+            let docs: HashSet<Uuid> = self
+                .postings
+                .get(*term)
+                .map(|docs| docs.keys().cloned().collect())
+                .unwrap_or_default();
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&docs).cloned().collect(),
+                None => docs,
+            });
+        }
+
+        let mut results: Vec<(Uuid, f64)> = candidates
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|uuid| query.phrases.iter().all(|p| self.has_phrase(uuid, p)))
+            .filter(|uuid| !query.excluded.iter().any(|p| self.has_phrase(uuid, p)))
+            .map(|uuid| {
+                let score = terms.iter().map(|term| self.bm25(term, &uuid)).sum();
+                (uuid, score)
+            })
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+    }
+
+    /// Positions of `term` in one post, in increasing order.
+    fn positions_in(&self, term: &str, uuid: &Uuid) -> &[usize] {
+        self.postings
+            .get(term)
+            .and_then(|docs| docs.get(uuid))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    fn has_phrase(&self, uuid: &Uuid, phrase: &[String]) -> bool {
+        self.positions_in(&phrase[0], uuid).iter().any(|start| {
+            phrase.iter().enumerate().skip(1).all(|(offset, term)| {
+                self.positions_in(term, uuid)
+                    .binary_search(&(start + offset))
+                    .is_ok()
+            })
+        })
+    }
+
+    fn bm25(&self, term: &str, uuid: &Uuid) -> f64 {
+        let tf = self.positions_in(term, uuid).len() as f64;
+        let n = self.documents.len() as f64;
+        let df = self.postings.get(term).map_or(0, HashMap::len) as f64;
+        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+        let length = self.documents.get(uuid).map_or(0, |d| d.length) as f64;
+        let average = self.total_length as f64 / n.max(1.0);
+        idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average.max(1.0)))
+    }
+}
+
+/// Cuts a short excerpt around the first query hit in `text`, HTML-escaped,
+/// with every hit wrapped in `<mark>`. Returns `None` when nothing matches.
+pub fn snippet(text: &str, query: &Query) -> Option<String> {
+    let terms = query.positive_terms();
+    let words = tokens(text);
+    let first = words
+        .iter()
+        .position(|(t, _, _)| terms.contains(t.as_str()))?;
+
+    let from = first.saturating_sub(SNIPPET_CONTEXT);
+    let to = (first + SNIPPET_CONTEXT * 2).min(words.len() - 1);
+    let mut out = String::new();
+    if from > 0 {
+        out.push('…');
+    }
+    let mut cursor = words[from].1;
+    for (term, start, end) in &words[from..=to] {
+        push_escaped(&mut out, &text[cursor..*start]);
+        if terms.contains(term.as_str()) {
+            out.push_str("<mark>");
+            push_escaped(&mut out, &text[*start..*end]);
+            out.push_str("</mark>");
+        } else {
+            push_escaped(&mut out, &text[*start..*end]);
+        }
+        cursor = *end;
+    }
+    if to < words.len() - 1 {
+        out.push('…');
+    } else {
+        push_escaped(&mut out, &text[cursor..]);
+    }
+    Some(out)
+}
+
+/// Appends `text` with the characters HTML and XML treat specially escaped.
+pub(crate) fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
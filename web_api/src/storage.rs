@@ -0,0 +1,440 @@
+use crate::models::{Comment, Post, Revision, User, Webhook};
+use crate::webhooks::{StoredDelivery, DELIVERIES_KEPT};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// One mutation of the database, as handed to a storage backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    /// Inserts the post or replaces the one with the same uuid, and adds
+    /// the revisions that record it.
+    Put {
+        post: Post,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        revisions: Vec<Revision>,
+    },
+    /// Removes the post and every comment on and revision of it.
+    Delete {
+        uuid: Uuid,
+    },
+    /// Removes the posts and every comment on and revision of them, as one
+    /// entry however many there are.
+    Purge {
+        uuids: Vec<Uuid>,
+    },
+    /// Inserts the user or replaces the one with the same username.
+    PutUser {
+        user: User,
+    },
+    PutComment {
+        comment: Comment,
+    },
+    PutWebhook {
+        webhook: Webhook,
+    },
+    /// Removes the webhook and its deliveries.
+    DeleteWebhook {
+        id: Uuid,
+    },
+    /// Inserts the delivery or replaces the one with the same id, forgetting
+    /// the oldest of its webhook's beyond `DELIVERIES_KEPT`.
+    PutDelivery {
+        delivery: StoredDelivery,
+    },
+}
+
+/// Everything a storage backend persists.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct State {
+    pub posts: Vec<Post>,
+    #[serde(default)]
+    pub users: Vec<User>,
+    /// Comments on every post, in insertion order, by post. Stored as one
+    /// list.
+    #[serde(default, with = "by_post")]
+    pub comments: HashMap<Uuid, Vec<Comment>>,
+    /// Revisions of every post, oldest first, by post. Stored as one list.
+    #[serde(default, with = "by_post")]
+    pub revisions: HashMap<Uuid, Vec<Revision>>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    /// Recent deliveries to every webhook, oldest first.
+    #[serde(default)]
+    pub deliveries: Vec<StoredDelivery>,
+}
+
+impl Change {
+    /// Applies the change to the state. Replaying the same change twice
+    /// leaves the state as it was after the first time.
+    pub fn apply(self, state: &mut State) {
+        match self {
+            Change::Put { post, revisions } => {
+                match state.posts.iter_mut().find(|p| p.uuid() == post.uuid()) {
+                    Some(existing) => *existing = post,
+                    None => state.posts.push(post),
+                }
+                for revision in revisions {
+                    let kept = state.revisions.entry(*revision.post_uuid()).or_default();
+                    // Numbers only go up, so the list stays sorted by them.
+                    let replayed = kept
+                        .binary_search_by_key(&revision.number(), Revision::number)
+                        .is_ok();
+                    if !replayed {
+                        kept.push(revision);
+                    }
+                }
+            }
+            Change::Delete { uuid } => {
+                state.posts.retain(|p| p.uuid() != &uuid);
+                state.comments.remove(&uuid);
+                state.revisions.remove(&uuid);
+            }
+            Change::Purge { uuids } => {
+                state.posts.retain(|p| !uuids.contains(p.uuid()));
+                for uuid in &uuids {
+                    state.comments.remove(uuid);
+                    state.revisions.remove(uuid);
+                }
+            }
+            Change::PutUser { user } => {
+                match state.users.iter_mut().find(|u| u.username() == user.username()) {
+                    Some(existing) => *existing = user,
+                    None => state.users.push(user),
+                }
+            }
+            Change::PutComment { comment } => {
+                let comments = state.comments.entry(*comment.post_uuid()).or_default();
+                match comments.iter_mut().find(|c| c.uuid() == comment.uuid()) {
+                    Some(existing) => *existing = comment,
+                    None => comments.push(comment),
+                }
+            }
+            Change::PutWebhook { webhook } => {
+                match state.webhooks.iter_mut().find(|w| w.id() == webhook.id()) {
+                    Some(existing) => *existing = webhook,
+                    None => state.webhooks.push(webhook),
+                }
+            }
+            Change::DeleteWebhook { id } => {
+                state.webhooks.retain(|w| w.id() != &id);
+                state.deliveries.retain(|d| d.webhook_id != id);
+            }
+            Change::PutDelivery { delivery } => {
+                let webhook_id = delivery.webhook_id;
+                let deliveries = &mut state.deliveries;
+                match deliveries.iter_mut().find(|d| d.delivery.id == delivery.delivery.id) {
+                    Some(existing) => *existing = delivery,
+                    None => deliveries.push(delivery),
+                }
+                let kept = deliveries.iter().filter(|d| d.webhook_id == webhook_id).count();
+                let mut excess = kept.saturating_sub(DELIVERIES_KEPT);
+                deliveries.retain(|d| {
+                    let forget = excess > 0 && d.webhook_id == webhook_id;
+                    excess -= usize::from(forget);
+                    !forget
+                });
+            }
+        }
+    }
+}
+
+/// Something stored in `State` with the post it belongs to.
+pub trait OfPost {
+    fn post_uuid(&self) -> &Uuid;
+}
+
+impl OfPost for Comment {
+    fn post_uuid(&self) -> &Uuid {
+        Comment::post_uuid(self)
+    }
+}
+
+impl OfPost for Revision {
+    fn post_uuid(&self) -> &Uuid {
+        Revision::post_uuid(self)
+    }
+}
+
+/// (De)serializes things kept by post as one flat list, so that snapshots
+/// keep the shape they had before they were grouped.
+mod by_post {
+    use super::OfPost;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    pub fn serialize<T, S>(
+        grouped: &HashMap<Uuid, Vec<T>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(grouped.values().flatten())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<HashMap<Uuid, Vec<T>>, D::Error>
+    where
+        T: Deserialize<'de> + OfPost,
+        D: Deserializer<'de>,
+    {
+        let mut grouped: HashMap<Uuid, Vec<T>> = HashMap::new();
+        for item in Vec::<T>::deserialize(deserializer)? {
+            grouped.entry(*item.post_uuid()).or_default().push(item);
+        }
+        Ok(grouped)
+    }
+}
+
+/// Snapshot files written before users were stored held a bare post list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotFormat {
+    State(State),
+    Posts(Vec<Post>),
+}
+
+/// Where `Database` keeps its state between restarts.
+pub trait Storage: Send + Sync + fmt::Debug {
+    /// Returns everything persisted so far, posts in insertion order.
+    fn load(&mut self) -> io::Result<State>;
+
+    /// Durably records one change. When this returns `Ok` the change must
+    /// survive a crash.
+    fn record(&mut self, change: &Change) -> io::Result<()>;
+
+    /// Gives the backend the full current state after a change so it can
+    /// compact whatever it has recorded so far.
+    fn compact(&mut self, state: &State) -> io::Result<()>;
+
+    /// Writes out the full current state regardless of how much has been
+    /// recorded since the last compaction, e.g. before shutting down.
+    fn flush(&mut self, state: &State) -> io::Result<()>;
+}
+
+/// Keeps nothing: the state lives only as long as the process.
+#[derive(Debug, Default)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load(&mut self) -> io::Result<State> {
+        Ok(State::default())
+    }
+
+    fn record(&mut self, _: &Change) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn compact(&mut self, _: &State) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self, _: &State) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.json.tmp";
+const LOG_FILE: &str = "changes.jsonl";
+
+/// Number of log entries after which the log is folded into a new snapshot.
+const DEFAULT_COMPACT_EVERY: usize = 1000;
+
+/// Stores the state in a directory as a snapshot plus an append-only log.
+///
+/// Every change is appended to `changes.jsonl` as one JSON line and synced
+/// before `record` returns. Every `compact_every` changes the full state is
+/// written to `snapshot.json` (via a temporary file and a rename, so the old
+/// snapshot stays intact until the new one is complete) and the log is
+/// truncated. On load the snapshot is read and the log replayed on top of it;
+/// a torn last line left by a crash mid-write is discarded, and so is the
+/// part of an entry a failed `record` got written.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+    log: Option<File>,
+    /// Length of the log up to the end of its last complete entry, once
+    /// known. A failed append is cut back to it.
+    log_len: Option<u64>,
+    pending: usize,
+    compact_every: usize,
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<FileStorage> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(FileStorage {
+            dir: dir.as_ref().to_path_buf(),
+            log: None,
+            log_len: None,
+            pending: 0,
+            compact_every: DEFAULT_COMPACT_EVERY,
+        })
+    }
+
+    fn read_snapshot(&self) -> io::Result<State> {
+        match File::open(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                Ok(SnapshotFormat::State(state)) => Ok(state),
+                Ok(SnapshotFormat::Posts(posts)) => Ok(State {
+                    posts,
+                    ..State::default()
+                }),
+                Err(e) => Err(invalid_data(e)),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replays the log onto `state` and returns the log opened for appending,
+    /// truncated after the last complete entry.
+    fn replay_log(&mut self, state: &mut State) -> io::Result<File> {
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(self.dir.join(LOG_FILE))?;
+
+        let valid_len = self.read_log(BufReader::new(&mut log), state)?;
+        if log.metadata()?.len() != valid_len {
+            log.set_len(valid_len)?;
+            log.sync_all()?;
+        }
+        log.seek(SeekFrom::End(0))?;
+        self.log_len = Some(valid_len);
+        Ok(log)
+    }
+
+    /// Applies every complete entry of the log and returns the length of the
+    /// log up to and including the last complete entry.
+    fn read_log<R: BufRead>(&mut self, mut reader: R, state: &mut State) -> io::Result<u64> {
+        let mut valid_len = 0u64;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                // Either the end of the log or an entry the process died
+                // halfway through writing.
+                break;
+            }
+            match serde_json::from_str::<Change>(&line) {
+                Ok(change) => change.apply(state),
+                // A garbled last entry is a torn write as well; anything
+                // earlier means the log itself is damaged.
+                Err(_) if reader.fill_buf()?.is_empty() => break,
+                Err(e) => return Err(invalid_data(e)),
+            }
+            valid_len += read as u64;
+            self.pending += 1;
+        }
+        Ok(valid_len)
+    }
+
+    fn write_snapshot(&mut self, state: &State) -> io::Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let file = File::create(&tmp_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, state).map_err(invalid_data)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        sync_dir(&self.dir)?;
+
+        if let Some(log) = self.log.as_mut() {
+            log.set_len(0)?;
+            log.sync_all()?;
+            self.log_len = Some(0);
+        }
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&mut self) -> io::Result<State> {
+        let mut state = self.read_snapshot()?;
+        self.pending = 0;
+        let log = self.replay_log(&mut state)?;
+        self.log = Some(log);
+        if self.pending > 0 {
+            self.write_snapshot(&state)?;
+        }
+        Ok(state)
+    }
+
+    fn record(&mut self, change: &Change) -> io::Result<()> {
+        let mut line = serde_json::to_vec(change).map_err(invalid_data)?;
+        line.push(b'\n');
+
+        if self.log.is_none() {
+            let log = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.dir.join(LOG_FILE))?;
+            // Drops what is left of an append that failed before.
+            match self.log_len {
+                Some(len) if log.metadata()?.len() != len => {
+                    log.set_len(len)?;
+                    log.sync_all()?;
+                }
+                Some(_) => {}
+                None => self.log_len = Some(log.metadata()?.len()),
+            }
+            self.log = Some(log);
+        }
+        let log = self.log.as_mut().unwrap();
+        let len = self.log_len.unwrap_or_default();
+        if let Err(e) = log.write_all(&line).and_then(|()| log.sync_data()) {
+            // Cuts off whatever part of the entry made it, so that the next
+            // one does not land after it. Failing that, the log is closed
+            // and cut when it is next opened.
+            if log.set_len(len).and_then(|()| log.sync_all()).is_err() {
+                self.log = None;
+            }
+            return Err(e);
+        }
+        self.log_len = Some(len + line.len() as u64);
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn compact(&mut self, state: &State) -> io::Result<()> {
+        if self.pending >= self.compact_every {
+            self.write_snapshot(state)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, state: &State) -> io::Result<()> {
+        if self.pending > 0 {
+            self.write_snapshot(state)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
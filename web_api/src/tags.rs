@@ -0,0 +1,76 @@
+use crate::models::Post;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// A tag and how many posts carry it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TagCount<'a> {
+    pub name: &'a str,
+    pub count: usize,
+}
+
+/// Secondary index from tag to the uuids of the posts carrying it.
+#[derive(Debug, Default)]
+pub struct TagIndex {
+    posts: BTreeMap<String, BTreeSet<Uuid>>,
+    tags: HashMap<Uuid, BTreeSet<String>>,
+}
+
+impl TagIndex {
+    pub fn new() -> TagIndex {
+        TagIndex::default()
+    }
+
+    /// Indexes a post, replacing what was indexed for the same uuid before.
+    pub fn insert(&mut self, post: &Post) {
+        self.remove(post.uuid());
+        for tag in post.tags() {
+            self.posts.entry(tag.clone()).or_default().insert(*post.uuid());
+        }
+        self.tags.insert(*post.uuid(), post.tags().clone());
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) {
+        for tag in self.tags.remove(uuid).unwrap_or_default() {
+            if let Some(uuids) = self.posts.get_mut(&tag) {
+                uuids.remove(uuid);
+                if uuids.is_empty() {
+                    self.posts.remove(&tag);
+                }
+            }
+        }
+    }
+
+    /// Every tag in use, by name.
+    pub fn counts(&self) -> Vec<TagCount<'_>> {
+        self.posts
+            .iter()
+            .map(|(name, uuids)| TagCount {
+                name,
+                count: uuids.len(),
+            })
+            .collect()
+    }
+
+    /// Uuids of the posts matching a tag expression in conjunctive form: every
+    /// clause must match, and a clause matches a post carrying any of its tags.
+    pub fn matching(&self, clauses: &[Vec<String>]) -> BTreeSet<Uuid> {
+        let mut result: Option<BTreeSet<Uuid>> = None;
+        for clause in clauses {
+            let any: BTreeSet<Uuid> = clause
+                .iter()
+                .filter_map(|tag| self.posts.get(tag))
+                .flatten()
+                .cloned()
+                .collect();
+            result = Some(match result {
+                Some(all) => all.intersection(&any).cloned().collect(),
+                None => any,
+            });
+        }
+        result.unwrap_or_default()
+    }
+}
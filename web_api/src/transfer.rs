@@ -0,0 +1,330 @@
+use crate::database::{Database, DatabaseError};
+use crate::models::{Edit, NewPost, Post, PostStatus};
+use crate::negotiation::Format;
+
+use chrono::{DateTime, Utc};
+use iron::response::WriteBody;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, PoisonError, RwLock};
+use uuid::Uuid;
+
+/// One post to import: the fields of an exported `Post`, of which the uuid
+/// and timestamps may be left out to have them assigned.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PostRecord {
+    /// Kept if given, so that a post keeps its uuid across environments; a
+    /// post with the same uuid is replaced.
+    pub uuid: Option<Uuid>,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub author: Option<String>,
+    /// Now if left out.
+    pub datetime: Option<DateTime<Utc>>,
+    /// `datetime` if left out.
+    pub updated_at: Option<DateTime<Utc>>,
+    pub tags: Option<Vec<String>>,
+    /// `published` if left out.
+    pub status: Option<PostStatus>,
+    pub publish_at: Option<DateTime<Utc>>,
+}
+
+impl PostRecord {
+    /// The post to store, or the names of the invalid fields.
+    fn into_post(self) -> Result<Post, Vec<&'static str>> {
+        let new_post = NewPost {
+            title: self.title,
+            body: self.body,
+            author: self.author,
+            tags: self.tags,
+            note: None,
+            status: self.status,
+            publish_at: self.publish_at,
+        };
+        let invalid = new_post.invalid_fields();
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        let datetime = self.datetime.unwrap_or_else(Utc::now);
+        let uuid = self.uuid.unwrap_or_else(Uuid::new_v4);
+        Ok(new_post.into_post_at(uuid, datetime, self.updated_at.unwrap_or(datetime)))
+    }
+}
+
+/// A post as a CSV row. Tags are separated by spaces, which no tag
+/// contains.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    uuid: Option<Uuid>,
+    title: Option<String>,
+    body: Option<String>,
+    author: Option<String>,
+    datetime: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    tags: Option<String>,
+    status: Option<PostStatus>,
+    publish_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Post> for CsvRow {
+    fn from(post: &Post) -> CsvRow {
+        let tags: Vec<&str> = post.tags().iter().map(String::as_str).collect();
+        CsvRow {
+            uuid: Some(*post.uuid()),
+            title: Some(post.title().to_string()),
+            body: Some(post.body().to_string()),
+            author: Some(post.author().to_string()),
+            datetime: Some(*post.datetime()),
+            updated_at: Some(*post.updated_at()),
+            tags: Some(tags.join(" ")),
+            status: Some(post.status()),
+            publish_at: post.publish_at().copied(),
+        }
+    }
+}
+
+impl From<CsvRow> for PostRecord {
+    fn from(row: CsvRow) -> PostRecord {
+        PostRecord {
+            uuid: row.uuid,
+            title: row.title,
+            body: row.body,
+            author: row.author,
+            datetime: row.datetime,
+            updated_at: row.updated_at,
+            tags: row
+                .tags
+                .map(|tags| tags.split_whitespace().map(String::from).collect()),
+            status: row.status,
+            publish_at: row.publish_at,
+        }
+    }
+}
+
+/// A record read from an import, or why it could not be read, with the line
+/// it starts on.
+pub type Line = (usize, Result<PostRecord, String>);
+
+/// Reads JSON Lines as they arrive: one post per line, blank lines skipped.
+/// An `Err` means the body itself could not be read, and ends the import.
+pub fn json_lines<'a, R: BufRead + 'a>(input: R) -> impl Iterator<Item = io::Result<Line>> + 'a {
+    input
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => {
+                let record = serde_json::from_str(&line).map_err(|e| e.to_string());
+                Some(Ok((index + 1, record)))
+            }
+            Err(e) => Some(Err(e)),
+        })
+}
+
+/// Reads CSV with a header row naming the columns, in any order, as it
+/// arrives. An `Err` means the body itself could not be read.
+pub fn csv_lines<'a, R: Read + 'a>(input: R) -> impl Iterator<Item = io::Result<Line>> + 'a {
+    let mut reader = csv::Reader::from_reader(input);
+    let (headers, failure) = match reader.headers() {
+        Ok(headers) => (Some(headers.clone()), None),
+        Err(e) => (None, Some(csv_failure(1, e))),
+    };
+    let line = |position: Option<&csv::Position>| position.map_or(0, |p| p.line() as usize);
+    let records = headers.map(|headers| {
+        reader.into_records().map(move |row| match row {
+            Ok(row) => Ok((
+                line(row.position()),
+                row.deserialize::<CsvRow>(Some(&headers))
+                    .map(PostRecord::from)
+                    .map_err(|e| e.to_string()),
+            )),
+            Err(e) => csv_failure(line(e.position()), e),
+        })
+    });
+    failure.into_iter().chain(records.into_iter().flatten())
+}
+
+/// A row that could not be parsed, or the error reading the body.
+fn csv_failure(line: usize, e: csv::Error) -> io::Result<Line> {
+    if e.is_io_error() {
+        Err(e.into())
+    } else {
+        Ok((line, Err(e.to_string())))
+    }
+}
+
+/// Why one line of an import was skipped.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+    /// The fields that are missing, blank or invalid, if that is the
+    /// problem.
+    pub fields: Vec<String>,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct ImportReport {
+    /// Whether nothing was actually stored.
+    pub dry_run: bool,
+    /// Posts that are new, or would be on a dry run.
+    pub created: usize,
+    /// Posts that replaced one with the same uuid, or would on a dry run.
+    pub updated: usize,
+    /// Lines that were skipped; see `errors`.
+    pub failed: usize,
+    pub errors: Vec<LineError>,
+}
+
+impl ImportReport {
+    fn fail(&mut self, line: usize, message: &str, fields: Vec<&str>) {
+        self.failed += 1;
+        self.errors.push(LineError {
+            line,
+            message: message.to_string(),
+            fields: fields.into_iter().map(String::from).collect(),
+        });
+    }
+}
+
+/// Records an import takes at a time. The database is locked while a chunk
+/// is stored and released between chunks, so readers are not held up for
+/// the whole import.
+pub const IMPORT_CHUNK: usize = 100;
+
+/// An import in progress, fed the records of the body a chunk at a time.
+pub struct Import {
+    editor: String,
+    report: ImportReport,
+    seen: HashSet<Uuid>,
+    last_line: usize,
+}
+
+impl Import {
+    /// Starts an import done as the work of `editor`, which on a dry run only
+    /// reports what would happen.
+    pub fn new(editor: &str, dry_run: bool) -> Import {
+        Import {
+            editor: editor.to_string(),
+            report: ImportReport {
+                dry_run,
+                ..ImportReport::default()
+            },
+            seen: HashSet::new(),
+            last_line: 0,
+        }
+    }
+
+    /// Stores every valid record of `lines` and reports the rest. Posts in the
+    /// trash are left alone: they have to be restored or purged first. Fails
+    /// only when storage does.
+    pub fn add(&mut self, database: &mut Database, lines: Vec<Line>) -> Result<(), DatabaseError> {
+        let report = &mut self.report;
+        for (line, record) in lines {
+            self.last_line = self.last_line.max(line);
+            let post = match record.map(PostRecord::into_post) {
+                Ok(Ok(post)) => post,
+                Ok(Err(fields)) => {
+                    report.fail(line, "fields are missing, blank or invalid", fields);
+                    continue;
+                }
+                Err(message) => {
+                    report.fail(line, &message, Vec::new());
+                    continue;
+                }
+            };
+            if !self.seen.insert(*post.uuid()) {
+                report.fail(line, "the uuid appears on an earlier line", vec!["uuid"]);
+                continue;
+            }
+            if database.trashed(post.uuid()).is_ok() {
+                report.fail(line, "the post with this uuid is in the trash", vec!["uuid"]);
+                continue;
+            }
+            let exists = database.post(post.uuid()).is_some();
+            if !report.dry_run {
+                let edit = Edit::new(&self.editor, Some("Imported".to_string()));
+                database.save_post(post, edit)?;
+            }
+            if exists {
+                report.updated += 1;
+            } else {
+                report.created += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports that the body could not be read past the last line added;
+    /// whatever came before it stays imported.
+    pub fn cut_off(&mut self, e: &io::Error) {
+        let message = format!("the rest of the body could not be read: {}", e);
+        self.report.fail(self.last_line + 1, &message, Vec::new());
+    }
+
+    pub fn finish(self) -> ImportReport {
+        self.report
+    }
+}
+
+/// Posts an export reads at a time, so that the database is only locked
+/// while a chunk of them is copied, never while the client reads the body.
+pub const EXPORT_CHUNK: usize = 100;
+
+/// Body of an export: posts in the order they are stored, as JSON Lines or
+/// CSV, written out as the client reads them. Posts deleted while the export
+/// is under way are left out.
+pub struct Export {
+    database: Arc<RwLock<Database>>,
+    uuids: Vec<Uuid>,
+    format: Format,
+}
+
+impl Export {
+    /// Exports the posts `database` holds now as `format`, which is
+    /// `Format::Csv` or JSON Lines.
+    pub fn new(database: Arc<RwLock<Database>>, format: Format) -> Export {
+        let uuids = database
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .posts()
+            .map(|post| *post.uuid())
+            .collect();
+        Export {
+            database,
+            uuids,
+            format,
+        }
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = Vec<Post>> + '_ {
+        self.uuids.chunks(EXPORT_CHUNK).map(|uuids| {
+            let database = self.database.read().unwrap_or_else(PoisonError::into_inner);
+            uuids.iter().filter_map(|uuid| database.post(uuid).cloned()).collect()
+        })
+    }
+}
+
+impl WriteBody for Export {
+    fn write_body(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.format == Format::Csv {
+            let mut writer = csv::Writer::from_writer(out);
+            for posts in self.chunks() {
+                for post in &posts {
+                    writer.serialize(CsvRow::from(post))?;
+                }
+            }
+            return writer.flush();
+        }
+        for posts in self.chunks() {
+            for post in &posts {
+                serde_json::to_writer(&mut *out, post)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
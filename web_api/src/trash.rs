@@ -0,0 +1,44 @@
+use crate::database::Database;
+use crate::models::Post;
+use crate::periodic;
+
+use log::{error, info};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the purger looks for posts that have been in the trash for
+/// longer than the retention period.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Body of a `GET /trash` response, most recently deleted first.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TrashList<'a> {
+    pub posts: Vec<&'a Post>,
+}
+
+/// Starts a thread that, every `interval`, purges for good the posts that
+/// went in the trash more than `retention` ago.
+pub fn spawn(
+    database: Arc<RwLock<Database>>,
+    interval: Duration,
+    retention: chrono::Duration,
+) -> JoinHandle<()> {
+    periodic::spawn(
+        "trash",
+        database,
+        interval,
+        move |database, now| database.oldest_trashed().is_some_and(|at| at <= now - retention),
+        move |database, now| match database.purge_trash(now - retention) {
+            Ok(uuids) => {
+                for uuid in uuids {
+                    info!("purged post {} from the trash", uuid);
+                }
+            }
+            // Left in the trash, so the next tick tries again.
+            Err(e) => error!("unable to purge the trash: {:?}", e),
+        },
+    )
+}
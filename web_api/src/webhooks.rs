@@ -0,0 +1,467 @@
+use crate::database::Database;
+use crate::events::{Event, EventKind};
+use crate::models::Webhook;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::warn;
+use rand::RngCore;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>`, keyed with the
+/// webhook's secret.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// Header naming the event, e.g. `created`.
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+/// Header carrying the delivery id, the same on every attempt.
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// Wait before the first retry; every further retry waits twice as long.
+pub const RETRY_BASE: Duration = Duration::from_secs(5);
+
+/// Attempts a delivery gets before it is given up on.
+pub const MAX_ATTEMPTS: u32 = 6;
+
+/// Deliveries kept per webhook; older ones are forgotten.
+pub const DELIVERIES_KEPT: usize = 100;
+
+/// How long a receiver has to answer.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deliveries sent at once, each to a different webhook.
+const WORKERS: usize = 4;
+
+/// How long a thread sleeps when it has nothing to do, at most.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+
+/// A fresh secret for a new webhook.
+pub fn random_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+}
+
+/// The `X-Webhook-Signature` of `payload`, as a receiver should compute it
+/// to check that a delivery is genuine.
+pub fn signature(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// A webhook as the API shows it, without its secret.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WebhookInfo<'a> {
+    pub id: &'a Uuid,
+    pub url: &'a str,
+    pub events: &'a [EventKind],
+    pub created_by: &'a str,
+    pub created_at: &'a DateTime<Utc>,
+}
+
+impl<'a> From<&'a Webhook> for WebhookInfo<'a> {
+    fn from(webhook: &'a Webhook) -> WebhookInfo<'a> {
+        WebhookInfo {
+            id: webhook.id(),
+            url: webhook.url(),
+            events: webhook.events(),
+            created_by: webhook.created_by(),
+            created_at: webhook.created_at(),
+        }
+    }
+}
+
+/// Body of a `POST /admin/webhooks` response: the only time the secret is
+/// shown.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RegisteredWebhook<'a> {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo<'a>,
+    pub secret: &'a str,
+}
+
+/// Body of a `GET /admin/webhooks` response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WebhookList<'a> {
+    pub webhooks: Vec<WebhookInfo<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not sent yet, or failed and waiting to be retried.
+    Pending,
+    Succeeded,
+    /// Failed `MAX_ATTEMPTS` times.
+    Failed,
+}
+
+/// One event sent, or to be sent, to one webhook.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub event: EventKind,
+    /// Id of the event on `/post_feed/stream`.
+    pub event_id: u64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// HTTP status of the last answer, if the receiver answered.
+    pub response_status: Option<u16>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// When the next attempt is due, while the delivery is pending.
+    pub next_attempt_at: Option<DateTime<Utc>>,
+}
+
+/// Body of a `GET /admin/webhooks/:id/deliveries` response.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DeliveryList {
+    /// Newest first.
+    pub deliveries: Vec<Delivery>,
+}
+
+/// A delivery as stored, with the webhook it goes to and, while it is
+/// pending, the payload to send, so that it can be retried after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredDelivery {
+    pub webhook_id: Uuid,
+    #[serde(flatten)]
+    pub delivery: Delivery,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+/// A delivery waiting for its next attempt.
+struct Job {
+    webhook_id: Uuid,
+    delivery_id: Uuid,
+    event: EventKind,
+    payload: String,
+    attempts: u32,
+    due: Instant,
+}
+
+#[derive(Default)]
+struct Pending {
+    jobs: Vec<Job>,
+    /// Webhooks a delivery is being sent to right now.
+    sending: HashSet<Uuid>,
+}
+
+/// Deliveries waiting for their next attempt, shared by the workers. A
+/// webhook gets one delivery at a time, so a slow receiver holds up only
+/// its own deliveries and at most one worker.
+#[derive(Default)]
+struct Queue {
+    pending: Mutex<Pending>,
+    changed: Condvar,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, job: Job) {
+        self.lock().jobs.push(job);
+        self.changed.notify_all();
+    }
+
+    /// Waits for the earliest due job to a webhook nothing is being sent to
+    /// and takes it.
+    fn take(&self) -> Job {
+        let mut pending = self.lock();
+        loop {
+            let now = Instant::now();
+            let earliest = pending
+                .jobs
+                .iter()
+                .enumerate()
+                .filter(|(_, job)| !pending.sending.contains(&job.webhook_id))
+                .min_by_key(|(_, job)| job.due)
+                .map(|(index, job)| (index, job.due));
+            let wait = match earliest {
+                Some((index, due)) if due <= now => {
+                    // Not `swap_remove`: jobs due at once go in the order
+                    // they were queued.
+                    let job = pending.jobs.remove(index);
+                    pending.sending.insert(job.webhook_id);
+                    return job;
+                }
+                Some((_, due)) => due - now,
+                None => IDLE_WAIT,
+            };
+            pending = self
+                .changed
+                .wait_timeout(pending, wait)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Marks the delivery to a webhook as sent, queueing `retry` if it is
+    /// to be tried again.
+    fn finish(&self, webhook_id: &Uuid, retry: Option<Job>) {
+        let mut pending = self.lock();
+        pending.sending.remove(webhook_id);
+        pending.jobs.extend(retry);
+        drop(pending);
+        self.changed.notify_all();
+    }
+}
+
+/// Turns published events into deliveries and sends them.
+struct Dispatcher {
+    database: Arc<RwLock<Database>>,
+    agent: ureq::Agent,
+    retry_base: Duration,
+}
+
+impl Dispatcher {
+    fn database(&self) -> RwLockWriteGuard<'_, Database> {
+        self.database.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues a delivery of `event` to every webhook that wants it.
+    fn enqueue(&self, event: &Event, queue: &Queue) {
+        let webhook_ids: Vec<Uuid> = self
+            .database
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .webhooks()
+            .iter()
+            .filter(|webhook| webhook.wants(event.kind))
+            .map(|webhook| *webhook.id())
+            .collect();
+        let data: Value = serde_json::from_str(&event.data).expect("events are JSON");
+        for webhook_id in webhook_ids {
+            let delivery_id = Uuid::new_v4();
+            let payload = json!({
+                "delivery": delivery_id,
+                "webhook": webhook_id,
+                "event": event.kind,
+                "event_id": event.id,
+                "data": data,
+            });
+            let now = Utc::now();
+            let payload = payload.to_string();
+            let delivery = StoredDelivery {
+                webhook_id,
+                delivery: Delivery {
+                    id: delivery_id,
+                    event: event.kind,
+                    event_id: event.id,
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    response_status: None,
+                    error: None,
+                    created_at: now,
+                    last_attempt_at: None,
+                    next_attempt_at: Some(now),
+                },
+                payload: Some(payload.clone()),
+            };
+            if let Err(e) = self.database().record_delivery(delivery) {
+                warn!("unable to record delivery {}: {:?}", delivery_id, e);
+            }
+            queue.push(Job {
+                webhook_id,
+                delivery_id,
+                event: event.kind,
+                payload,
+                attempts: 0,
+                due: Instant::now(),
+            });
+        }
+    }
+
+    /// Sends one delivery and records how it went. Returns the job again if
+    /// it is to be retried.
+    fn attempt(&self, mut job: Job) -> Option<Job> {
+        let webhook = self
+            .database
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .webhook(&job.webhook_id)
+            .ok()
+            .cloned();
+        // Deleted since the event; its deliveries went with it.
+        let webhook = webhook?;
+
+        let result = self.send(&webhook, &job);
+        job.attempts += 1;
+        let now = Utc::now();
+        let retry_in = match &result {
+            Err(_) if job.attempts < MAX_ATTEMPTS => {
+                Some(self.retry_base * 2u32.pow(job.attempts - 1))
+            }
+            _ => None,
+        };
+        self.record_attempt(&job, now, retry_in, &result);
+        match (&result, retry_in) {
+            (Err((_, error)), None) => {
+                warn!("giving up on delivery {} to {}: {}", job.delivery_id, webhook.url(), error);
+                None
+            }
+            (_, Some(delay)) => {
+                job.due = Instant::now() + delay;
+                Some(job)
+            }
+            (Ok(_), None) => None,
+        }
+    }
+
+    /// Stores the outcome of an attempt. The payload is dropped once the
+    /// delivery is no longer pending.
+    fn record_attempt(
+        &self,
+        job: &Job,
+        now: DateTime<Utc>,
+        retry_in: Option<Duration>,
+        result: &Result<u16, (Option<u16>, String)>,
+    ) {
+        let mut database = self.database();
+        let Some(mut stored) = database.delivery(&job.webhook_id, &job.delivery_id).cloned()
+        else {
+            return;
+        };
+        let delivery = &mut stored.delivery;
+        delivery.attempts = job.attempts;
+        delivery.last_attempt_at = Some(now);
+        delivery.next_attempt_at = retry_in
+            .and_then(|delay| chrono::Duration::from_std(delay).ok().map(|delay| now + delay));
+        match result {
+            Ok(status) => {
+                delivery.status = DeliveryStatus::Succeeded;
+                delivery.response_status = Some(*status);
+                delivery.error = None;
+            }
+            Err((status, error)) => {
+                delivery.status = if retry_in.is_some() {
+                    DeliveryStatus::Pending
+                } else {
+                    DeliveryStatus::Failed
+                };
+                delivery.response_status = *status;
+                delivery.error = Some(error.clone());
+            }
+        }
+        if delivery.status != DeliveryStatus::Pending {
+            stored.payload = None;
+        }
+        if let Err(e) = database.record_delivery(stored) {
+            warn!("unable to record delivery {}: {:?}", job.delivery_id, e);
+        }
+    }
+
+    /// POSTs the payload. Only a 2xx answer counts as delivered.
+    fn send(&self, webhook: &Webhook, job: &Job) -> Result<u16, (Option<u16>, String)> {
+        let request = self
+            .agent
+            .post(webhook.url())
+            .set("Content-Type", "application/json")
+            .set(SIGNATURE_HEADER, &signature(webhook.secret(), job.payload.as_bytes()))
+            .set(EVENT_HEADER, job.event.name())
+            .set(DELIVERY_HEADER, &job.delivery_id.to_string());
+        match request.send_string(&job.payload) {
+            Ok(response) if (200..300).contains(&response.status()) => Ok(response.status()),
+            Ok(response) | Err(ureq::Error::Status(_, response)) => {
+                let status = response.status();
+                Err((Some(status), format!("the receiver answered {}", status)))
+            }
+            Err(e) => Err((None, e.to_string())),
+        }
+    }
+}
+
+/// Starts the threads that deliver every event published from now on to the
+/// webhooks registered for it: one that queues deliveries and `WORKERS` that
+/// send them, one at a time per webhook. A failed delivery is retried after
+/// `retry_base`, then twice as long each time, up to `MAX_ATTEMPTS` attempts
+/// in all. Deliveries are stored with the database, and those still pending
+/// from before a restart are queued again when it is due. Returns the
+/// queueing thread.
+pub fn spawn(database: Arc<RwLock<Database>>, retry_base: Duration) -> JoinHandle<()> {
+    let queue = Arc::new(Queue::default());
+    let events = {
+        let database = database.read().unwrap_or_else(PoisonError::into_inner);
+        let now = Utc::now();
+        for stored in database.pending_deliveries() {
+            let Some(payload) = stored.payload.clone() else {
+                continue;
+            };
+            let wait = stored
+                .delivery
+                .next_attempt_at
+                .and_then(|at| (at - now).to_std().ok())
+                .unwrap_or_default();
+            queue.push(Job {
+                webhook_id: stored.webhook_id,
+                delivery_id: stored.delivery.id,
+                event: stored.delivery.event,
+                payload,
+                attempts: stored.delivery.attempts,
+                due: Instant::now() + wait,
+            });
+        }
+        database.events()
+    };
+    let dispatcher = Arc::new(Dispatcher {
+        database,
+        // Redirects are answers like any other; following them could send
+        // the payload somewhere the admin did not register.
+        agent: ureq::AgentBuilder::new()
+            .timeout(SEND_TIMEOUT)
+            .redirects(0)
+            .build(),
+        retry_base,
+    });
+    for n in 0..WORKERS {
+        let (dispatcher, queue) = (dispatcher.clone(), queue.clone());
+        thread::Builder::new()
+            .name(format!("webhooks-{}", n))
+            .spawn(move || loop {
+                let job = queue.take();
+                let webhook_id = job.webhook_id;
+                let retry = dispatcher.attempt(job);
+                queue.finish(&webhook_id, retry);
+            })
+            .expect("Unable to start a webhook worker thread");
+    }
+
+    let mut last_id = events.last_id();
+    thread::Builder::new()
+        .name("webhooks".to_string())
+        .spawn(move || loop {
+            match events.wait(last_id, IDLE_WAIT) {
+                Some(new) => {
+                    for event in new {
+                        last_id = event.id;
+                        dispatcher.enqueue(&event, &queue);
+                    }
+                }
+                None => {
+                    warn!("webhooks fell behind the event log; some events were not delivered");
+                    last_id = events.last_id();
+                }
+            }
+        })
+        .expect("Unable to start the webhook thread")
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::status::Status;
+use serde_json::json;
+use web_api::auth::{self, Auth};
+use web_api::config::{AdminAccount, Config};
+use web_api::models::{Role, User};
+
+#[test]
+fn register_then_log_in() {
+    let app = TestApp::new();
+    let credentials = json!({ "username": "alice", "password": "correct horse" });
+
+    let res = app.post("/register", None, &credentials);
+    assert_eq!(res.status, Status::Created);
+    assert_eq!(res.json(), json!({ "username": "alice", "role": "user" }));
+
+    let res = app.post("/register", None, &credentials);
+    assert_eq!(res.status, Status::Conflict);
+
+    let res = app.post("/login", None, &credentials);
+    assert_eq!(res.status, Status::Ok);
+    let token = res.json()["token"].as_str().unwrap().to_string();
+    assert!(res.json()["expires_at"].is_string());
+
+    // The token works.
+    let res = app.post("/post", Some(&token), &json!({ "title": "t", "body": "b" }));
+    assert_eq!(res.status, Status::Created);
+    assert_eq!(res.json()["author"], "alice");
+}
+
+#[test]
+fn registration_validates_credentials() {
+    let app = TestApp::new();
+    let res = app.post(
+        "/register",
+        None,
+        &json!({ "username": "a b", "password": "short" }),
+    );
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["username", "password"]));
+}
+
+#[test]
+fn login_refuses_bad_credentials() {
+    let app = TestApp::new();
+    app.post(
+        "/register",
+        None,
+        &json!({ "username": "alice", "password": "correct horse" }),
+    );
+
+    for credentials in [
+        json!({ "username": "alice", "password": "wrong horse" }),
+        json!({ "username": "nobody", "password": "correct horse" }),
+    ] {
+        let res = app.post("/login", None, &credentials);
+        assert_eq!(res.status, Status::Unauthorized);
+        assert_eq!(res.error_code(), "invalid_credentials");
+    }
+}
+
+#[test]
+fn admins_come_only_from_the_configuration() {
+    let app = TestApp::new();
+    let credentials = json!({ "username": ADMIN, "password": "correct horse" });
+    assert_eq!(app.post("/register", None, &credentials).status, Status::Conflict);
+
+    let mut config = Config::default();
+    let args = ["--admin", "root:pbkdf2-sha256$1$c2FsdA$aGFzaA"].map(String::from);
+    assert_eq!(Config::from_args(args.into_iter()).unwrap().admins.len(), 1);
+    assert!(Config::from_args(["--admin", "root"].map(String::from).into_iter()).is_err());
+    config.admins.push(AdminAccount {
+        username: "root".to_string(),
+        password_hash: auth::hash_password("correct horse"),
+    });
+    let app = TestApp::with_config(config);
+    let credentials = json!({ "username": "root", "password": "correct horse" });
+    let res = app.post("/login", None, &credentials);
+    assert_eq!(res.status, Status::Ok);
+    let authorization = format!("Bearer {}", res.json()["token"].as_str().unwrap());
+    let res = app.get_with("/admin/webhooks", &[("Authorization", &authorization)]);
+    assert_eq!(res.status, Status::Ok);
+
+    let mut database = app.database.write().unwrap();
+    database.set_admins(&[]).unwrap();
+    assert_eq!(database.user("root").unwrap().role(), Role::User);
+    assert_eq!(database.user(ADMIN).unwrap().role(), Role::User);
+    drop(database);
+
+    // A token issued while the account was an admin no longer acts as one.
+    let res = app.get_with("/admin/webhooks", &[("Authorization", &authorization)]);
+    assert_eq!(res.status, Status::Forbidden);
+}
+
+#[test]
+fn tokens_of_unknown_accounts_are_refused() {
+    let app = TestApp::new();
+    let (token, _) = app.auth.issue(&User::new("ghost", String::new(), Role::Admin));
+    let res = app.post("/post", Some(&token), &json!({ "title": "t", "body": "b" }));
+    assert_eq!(res.status, Status::Unauthorized);
+}
+
+#[test]
+fn forged_and_expired_tokens_are_refused() {
+    let app = TestApp::new();
+    let alice = User::new("alice", String::new(), Role::User);
+    let token = app.token("alice");
+    let post = json!({ "title": "t", "body": "b" });
+
+    // Signed with another secret.
+    let (forged, _) = Auth::new(b"guessed".to_vec(), chrono::Duration::hours(1)).issue(&alice);
+    // The claims swapped for an admin's, keeping alice's signature.
+    let (admin, _) = app.auth.issue(&User::new(ADMIN, String::new(), Role::Admin));
+    let signature = token.rsplit('.').next().unwrap();
+    let (claims, _) = admin.rsplit_once('.').unwrap();
+    let tampered = format!("{}.{}", claims, signature);
+    for token in [forged, tampered] {
+        let res = app.post("/post", Some(&token), &post);
+        assert_eq!(res.status, Status::Unauthorized, "{}", token);
+    }
+
+    let expired = Auth::new(b"secret".to_vec(), chrono::Duration::seconds(-1));
+    let (token, _) = expired.issue(&alice);
+    assert!(expired.verify(&token).is_none());
+    assert!(Auth::new(b"secret".to_vec(), chrono::Duration::hours(1)).verify(&token).is_none());
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::TestApp;
+use iron::status::Status;
+use serde_json::json;
+use web_api::database::Database;
+use web_api::models::{Comment, Post};
+use web_api::storage::FileStorage;
+
+#[test]
+fn threaded_comments() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "t", "body": "b" }));
+    let path = format!("/post/{}/comments", post["uuid"].as_str().unwrap());
+
+    let res = app.post(&path, Some(&token), &json!({ "body": "first" }));
+    assert_eq!(res.status, Status::Created);
+    let first = res.json();
+    assert_eq!(first["author"], "alice");
+
+    let res = app.post(
+        &path,
+        Some(&app.token("bob")),
+        &json!({ "body": "reply", "parent": first["uuid"] }),
+    );
+    assert_eq!(res.status, Status::Created);
+
+    let res = app.get(&path);
+    assert_eq!(res.status, Status::Ok);
+    let page = res.json();
+    assert_eq!(page["comments"].as_array().unwrap().len(), 1);
+    assert_eq!(page["comments"][0]["body"], "first");
+    assert_eq!(page["comments"][0]["reply_count"], 1);
+    assert_eq!(page["comments"][0]["replies"][0]["author"], "bob");
+
+    let res = app.get(&format!("{}?depth=0", path));
+    assert_eq!(res.json()["comments"][0]["replies"], json!([]));
+    assert_eq!(res.json()["comments"][0]["reply_count"], 1);
+}
+
+#[test]
+fn threads_are_paged_and_cut_at_the_depth() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "t", "body": "b" }));
+    let path = format!("/post/{}/comments", post["uuid"].as_str().unwrap());
+    let mut parent = serde_json::Value::Null;
+    for n in 0..3 {
+        let comment = json!({ "body": format!("level {}", n), "parent": parent });
+        parent = app.post(&path, Some(&token), &comment).json()["uuid"].clone();
+    }
+    for body in ["second", "third"] {
+        app.post(&path, Some(&token), &json!({ "body": body }));
+    }
+
+    let page = app.get(&format!("{}?limit=2&depth=1", path)).json();
+    let threads = page["comments"].as_array().unwrap();
+    assert_eq!(threads.len(), 2);
+    let child = &threads[0]["replies"][0];
+    assert_eq!(child["body"], "level 1");
+    assert_eq!(child["reply_count"], 1);
+    assert_eq!(child["replies"], json!([]));
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page = app.get(&format!("{}?limit=2&cursor={}", path, cursor)).json();
+    assert_eq!(page["comments"][0]["body"], "third");
+    assert!(page["next_cursor"].is_null());
+}
+
+#[test]
+fn replies_are_capped_and_paged() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "t", "body": "b" }));
+    let path = format!("/post/{}/comments", post["uuid"].as_str().unwrap());
+    let first = app.post(&path, Some(&token), &json!({ "body": "first" })).json();
+    for n in 0..12 {
+        let reply = json!({ "body": format!("reply {}", n), "parent": first["uuid"] });
+        assert_eq!(app.post(&path, Some(&token), &reply).status, Status::Created);
+    }
+
+    let thread = &app.get(&path).json()["comments"][0];
+    assert_eq!(thread["reply_count"], 12);
+    assert_eq!(thread["replies"].as_array().unwrap().len(), 10);
+    let thread = &app.get(&format!("{}?replies=3", path)).json()["comments"][0];
+    assert_eq!(thread["replies"].as_array().unwrap().len(), 3);
+    assert_eq!(thread["replies"][2]["body"], "reply 2");
+
+    // The rest are paged through like top-level comments.
+    let replies = format!("{}?parent={}&limit=5", path, first["uuid"].as_str().unwrap());
+    let page = app.get(&replies).json();
+    assert_eq!(page["comments"][0]["body"], "reply 0");
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page = app.get(&format!("{}&cursor={}", replies, cursor)).json();
+    assert_eq!(page["comments"][0]["body"], "reply 5");
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page = app.get(&format!("{}&cursor={}", replies, cursor)).json();
+    assert_eq!(page["comments"].as_array().unwrap().len(), 2);
+    assert!(page["next_cursor"].is_null());
+
+    for query in ["replies=0", "replies=101", "parent=nope"] {
+        let res = app.get(&format!("{}?{}", path, query));
+        assert_eq!(res.status, Status::BadRequest, "{}", query);
+    }
+}
+
+#[test]
+fn comments_are_validated() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "t", "body": "b" }));
+    let path = format!("/post/{}/comments", post["uuid"].as_str().unwrap());
+
+    let res = app.post(&path, None, &json!({ "body": "anonymous" }));
+    assert_eq!(res.status, Status::Unauthorized);
+
+    let res = app.post(&path, Some(&token), &json!({ "body": "  " }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["body"]));
+
+    let stranger = "00000000-0000-0000-0000-000000000000";
+    let res = app.post(&path, Some(&token), &json!({ "body": "x", "parent": stranger }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["parent"]));
+
+    let res = app.get(&format!("{}?depth=many", path));
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.json()["error"]["details"], "depth");
+}
+
+#[test]
+fn comments_on_missing_posts() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let missing = "/post/00000000-0000-0000-0000-000000000000/comments";
+    assert_eq!(app.get(missing).status, Status::NotFound);
+    let res = app.post(missing, Some(&token), &json!({ "body": "x" }));
+    assert_eq!(res.status, Status::NotFound);
+
+    assert_eq!(app.get("/post/nope/comments").status, Status::BadRequest);
+    let res = app.post("/post/nope/comments", Some(&token), &json!({ "body": "x" }));
+    assert_eq!(res.status, Status::BadRequest);
+}
+
+#[test]
+fn comments_survive_a_restart() {
+    let dir = std::env::temp_dir().join(format!("web_api-comments-{}", uuid::Uuid::new_v4()));
+    let open = || Database::open(Box::new(FileStorage::new(&dir).unwrap())).unwrap();
+    let mut database = open();
+    let mut ids = Vec::new();
+    for title in ["One", "Two"] {
+        let post = Post::new(title, "b", "alice", chrono::Utc::now(), uuid::Uuid::new_v4());
+        ids.push(*post.uuid());
+        database.add_post(post).unwrap();
+    }
+    let first = Comment::new(ids[0], None, "bob", "First");
+    let reply = Comment::new(ids[0], Some(*first.uuid()), "alice", "Reply");
+    database.add_comment(first).unwrap();
+    database.add_comment(Comment::new(ids[1], None, "bob", "Elsewhere")).unwrap();
+    database.add_comment(reply).unwrap();
+    drop(database);
+
+    // Once replayed from the change log, then from a snapshot.
+    for _ in 0..2 {
+        let mut database = open();
+        let bodies: Vec<&str> = database.comments(&ids[0]).iter().map(Comment::body).collect();
+        assert_eq!(bodies, ["First", "Reply"]);
+        assert_eq!(database.comments(&ids[1]).len(), 1);
+        database.flush().unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
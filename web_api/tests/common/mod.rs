@@ -0,0 +1,237 @@
+//! In-process harness: builds the application's real `Chain` around a fresh
+//! in-memory `Database` and sends requests through it without a socket.
+#![allow(dead_code)]
+
+use web_api::app;
+use web_api::auth::Auth;
+use web_api::config::{AdminAccount, Config};
+use web_api::database::Database;
+use web_api::limits::RateLimit;
+use web_api::models::{Role, User};
+
+use iron::headers::Headers;
+use iron::method::Method;
+use iron::status::Status;
+use iron::{Chain, Response};
+use iron_test::{request, response};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::{Arc, RwLock};
+
+/// Username of the admin account `TestApp` is configured with.
+pub const ADMIN: &str = "admin";
+
+/// A rate limit no test run gets near.
+const UNLIMITED: RateLimit = RateLimit {
+    burst: 1e9,
+    per_second: 1e9,
+};
+
+pub struct TestApp {
+    chain: Chain,
+    pub database: Arc<RwLock<Database>>,
+    pub auth: Arc<Auth>,
+}
+
+impl TestApp {
+    /// The application with default settings, minus rate limiting.
+    pub fn new() -> TestApp {
+        let mut config = Config::default();
+        config.limits.reads = UNLIMITED;
+        config.limits.writes = UNLIMITED;
+        TestApp::with_config(config)
+    }
+
+    pub fn with_config(mut config: Config) -> TestApp {
+        config.admins.push(AdminAccount {
+            username: ADMIN.to_string(),
+            password_hash: String::from("not a hash; log in with `token`"),
+        });
+        let mut database = Database::new();
+        database.set_admins(&config.admins).unwrap();
+        let database = Arc::new(RwLock::new(database));
+        let auth = Arc::new(Auth::new(Auth::random_secret(), chrono::Duration::hours(1)));
+        let chain = app::chain(&config, database.clone(), auth.clone());
+        TestApp {
+            chain,
+            database,
+            auth,
+        }
+    }
+
+    /// A bearer token for `username`, whose account is created, without a
+    /// usable password, if it does not exist yet.
+    pub fn token(&self, username: &str) -> String {
+        let mut database = self.database.write().unwrap();
+        if database.user(username).is_none() {
+            let user = User::new(username, String::from("no password"), Role::User);
+            database.add_user(user).unwrap();
+        }
+        let (token, _) = self.auth.issue(database.user(username).unwrap());
+        token
+    }
+
+    /// Sends a request through the chain. Like iron's server, answers with
+    /// the response of an error that reaches the end of the chain.
+    fn send(&self, method: Method, path: &str, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut raw = Headers::new();
+        for (name, value) in headers {
+            raw.append_raw(name.to_string(), value.as_bytes().to_vec());
+        }
+        let url = format!("http://localhost:8000{}", path);
+        match request::request(method, &url, body, raw, &self.chain) {
+            Ok(res) => res,
+            Err(err) => err.response,
+        }
+    }
+
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> TestResponse {
+        let res = self.send(method, path, headers, body);
+        TestResponse {
+            status: res.status.expect("responses have a status"),
+            headers: res.headers.clone(),
+            body: response::extract_body_to_string(res),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestResponse {
+        self.request(Method::Get, path, &[], "")
+    }
+
+    pub fn get_with(&self, path: &str, headers: &[(&str, &str)]) -> TestResponse {
+        self.request(Method::Get, path, headers, "")
+    }
+
+    /// POSTs `body` as the user `token` belongs to, if any.
+    pub fn post(&self, path: &str, token: Option<&str>, body: &Value) -> TestResponse {
+        let authorization = token.map(|token| format!("Bearer {}", token));
+        let mut headers = vec![("Content-Type", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        self.request(Method::Post, path, &headers, &body.to_string())
+    }
+
+    /// Creates a post as the user `token` belongs to and returns it.
+    pub fn create_post(&self, token: &str, post: &Value) -> Value {
+        let res = self.post("/post", Some(token), post);
+        assert_eq!(res.status, Status::Created, "{}", res.body);
+        res.json()
+    }
+
+    /// Sends an update or delete of `/post/:id` with the `If-Match` it needs.
+    pub fn edit(
+        &self,
+        method: Method,
+        id: &str,
+        token: &str,
+        if_match: &str,
+        body: &Value,
+    ) -> TestResponse {
+        let authorization = format!("Bearer {}", token);
+        let body = if body.is_null() {
+            String::new()
+        } else {
+            body.to_string()
+        };
+        self.request(
+            method,
+            &format!("/post/{}", id),
+            &[("Authorization", &authorization), ("If-Match", if_match)],
+            &body,
+        )
+    }
+
+    /// Opens an event stream without reading it, so that it stays open
+    /// until the response is dropped.
+    pub fn open_stream(&self, path: &str) -> Response {
+        let res = self.send(Method::Get, path, &[], "");
+        assert_eq!(res.status, Some(Status::Ok));
+        res
+    }
+
+    /// Opens an event stream and reads it until `count` events arrived, then
+    /// hangs up. Returns the events without the comments between them.
+    pub fn events(&self, path: &str, headers: &[(&str, &str)], count: usize) -> Vec<String> {
+        let mut res = self.send(Method::Get, path, headers, "");
+        assert_eq!(res.status, Some(Status::Ok));
+        let mut client = EventClient {
+            received: String::new(),
+            wanted: count,
+        };
+        let body = res.body.as_mut().expect("event streams have a body");
+        // The stream ends with the error `EventClient` hangs up with.
+        let _ = body.write_body(&mut client);
+        client.events()
+    }
+
+    /// The current `ETag` of a post.
+    pub fn etag(&self, id: &str) -> String {
+        let res = self.get(&format!("/post/{}", id));
+        assert_eq!(res.status, Status::Ok, "{}", res.body);
+        res.header("ETag").expect("posts have an ETag")
+    }
+}
+
+/// Receiving end of an event stream, which hangs up once it has enough.
+struct EventClient {
+    received: String,
+    wanted: usize,
+}
+
+impl EventClient {
+    fn events(&self) -> Vec<String> {
+        self.received
+            .split("\n\n")
+            .filter(|block| block.starts_with("id: "))
+            .map(String::from)
+            .collect()
+    }
+}
+
+impl Write for EventClient {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.received.push_str(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.events().len() >= self.wanted {
+            return Err(io::Error::other("hung up"));
+        }
+        Ok(())
+    }
+}
+
+pub struct TestResponse {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|e| panic!("body is not JSON ({}): {}", e, self.body))
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        let values = self.headers.get_raw(name)?;
+        Some(String::from_utf8_lossy(&values[0]).into_owned())
+    }
+
+    /// The `code` of a structured error body.
+    pub fn error_code(&self) -> String {
+        self.json()["error"]["code"]
+            .as_str()
+            .unwrap_or_else(|| panic!("not an error body: {}", self.body))
+            .to_string()
+    }
+}
+
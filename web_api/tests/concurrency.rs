@@ -0,0 +1,53 @@
+mod common;
+
+use common::TestApp;
+use iron::status::Status;
+use serde_json::json;
+use std::thread;
+
+#[test]
+fn a_panicking_writer_does_not_take_the_server_down() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    app.create_post(&token, &json!({ "title": "Before", "body": "b" }));
+
+    let database = app.database.clone();
+    let panicked = thread::spawn(move || {
+        let _guard = database.write().unwrap();
+        panic!("a handler blew up holding the lock");
+    })
+    .join();
+    assert!(panicked.is_err());
+    assert!(app.database.is_poisoned());
+
+    assert_eq!(app.get("/post_feed").status, Status::Ok);
+    app.create_post(&token, &json!({ "title": "After", "body": "b" }));
+    let posts = app.get("/post_feed").json()["posts"].as_array().unwrap().len();
+    assert_eq!(posts, 2);
+}
+
+#[test]
+fn reads_and_writes_run_side_by_side() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    thread::scope(|scope| {
+        for writer in 0..4 {
+            let (app, token) = (&app, &token);
+            scope.spawn(move || {
+                for n in 0..10 {
+                    let title = format!("Post {} by {}", n, writer);
+                    app.create_post(token, &json!({ "title": title, "body": "b" }));
+                }
+            });
+        }
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    assert_eq!(app.get("/post_feed").status, Status::Ok);
+                }
+            });
+        }
+    });
+    let feed = app.get("/post_feed?limit=100").json();
+    assert_eq!(feed["posts"].as_array().unwrap().len(), 40);
+}
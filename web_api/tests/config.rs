@@ -0,0 +1,123 @@
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use web_api::config::{load_fixtures, AdminAccount, Backend, Config};
+
+fn args(args: &[&str]) -> Result<Config, String> {
+    Config::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("web_api-{}-{}", uuid::Uuid::new_v4(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn the_example_config_loads() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("web_api.toml");
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.bind, "localhost:8000");
+    assert_eq!(config.storage.backend, Backend::Memory);
+    assert_eq!(config.limits.writes.burst, 20.0);
+    assert_eq!(config.trash_retention_days, 30);
+}
+
+#[test]
+fn flags_override_the_config_file() {
+    let file = temp_file(
+        "config.toml",
+        "bind = \"0.0.0.0:80\"\nthreads = 4\ncors_origins = [\"https://a.example\"]\n\
+         [storage]\nbackend = \"file\"\n",
+    );
+    let file = file.to_str().unwrap();
+
+    // Wherever `--config` comes, the other flags win over it.
+    let config = args(&["--bind", "127.0.0.1:9000", "--config", file, "--threads", "2"]).unwrap();
+    assert_eq!(config.bind, "127.0.0.1:9000");
+    assert_eq!(config.threads, Some(2));
+    assert_eq!(config.worker_threads(), 2);
+    assert_eq!(config.max_streams(), 1);
+    assert_eq!(config.storage.backend, Backend::File);
+
+    let config = args(&["--config", file, "--cors-origin", "https://b.example"]).unwrap();
+    assert_eq!(config.cors_origins, ["https://a.example", "https://b.example"]);
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn bad_arguments_are_reported() {
+    for (arguments, message) in [
+        (&["--nope"][..], "unknown argument `--nope`"),
+        (&["--threads", "many"], "invalid value `many` for --threads"),
+        (&["--threads", "0"], "threads must be at least 1"),
+        (&["--bind"], "--bind needs a value"),
+        (&["--storage", "cloud"], "unknown storage backend `cloud`"),
+        (&["--threads", "2", "--max-streams", "2"], "max_streams must be at least 1"),
+    ] {
+        let error = args(arguments).unwrap_err();
+        assert!(error.contains(message), "{:?}: {}", arguments, error);
+    }
+
+    let file = temp_file("typo.toml", "bnd = \"localhost:1\"\n");
+    assert!(Config::load(&file).unwrap_err().contains("bnd"));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn admins_are_given_as_name_and_hash() {
+    let config = args(&["--admin", "root:pbkdf2-sha256$x"]).unwrap();
+    let admin = AdminAccount {
+        username: "root".to_string(),
+        password_hash: "pbkdf2-sha256$x".to_string(),
+    };
+    assert_eq!(config.admins, [admin]);
+    assert!(args(&["--admin", "root"]).is_err());
+}
+
+#[test]
+fn fixtures_are_validated() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/posts.json");
+    assert!(!load_fixtures(&path).unwrap().is_empty());
+
+    let file = temp_file(
+        "fixtures.json",
+        r#"[{ "title": "t", "body": "b", "author": "a" }, { "title": "t", "body": " " }]"#,
+    );
+    let error = load_fixtures(&file).unwrap_err();
+    assert!(error.ends_with("post 1 has invalid fields: body, author"), "{}", error);
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn the_server_flushes_storage_on_sigterm() {
+    let dir = std::env::temp_dir().join(format!("web_api-shutdown-{}", uuid::Uuid::new_v4()));
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/posts.json");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_web_api"))
+        .args(["--bind", &addr.to_string(), "--storage", "file", "--log-level", "off"])
+        .arg("--data-dir")
+        .arg(&dir)
+        .arg("--fixtures")
+        .arg(&fixtures)
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "the server did not start");
+        thread::sleep(Duration::from_millis(20));
+    }
+    // The seeded posts are only in the change log so far.
+    assert!(!dir.join("snapshot.json").exists());
+
+    let killed = Command::new("kill").args(["-TERM", &server.id().to_string()]).status();
+    assert!(killed.unwrap().success());
+    assert!(server.wait().unwrap().success());
+    let snapshot = fs::read_to_string(dir.join("snapshot.json")).unwrap();
+    assert!(snapshot.contains("The First Post"));
+    assert!(fs::read_to_string(dir.join("changes.jsonl")).unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::TestApp;
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::json;
+use std::thread;
+use std::time::Duration;
+use web_api::config::Config;
+use web_api::events::{EventKind, EventLog};
+use web_api::models::NewPost;
+
+fn kind(event: &str) -> &str {
+    event
+        .lines()
+        .find_map(|line| line.strip_prefix("event: "))
+        .expect("events have a name")
+}
+
+#[test]
+fn reconnecting_clients_catch_up() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let first = app.create_post(&token, &json!({ "title": "One", "body": "b" }));
+    let first = first["uuid"].as_str().unwrap();
+    app.create_post(&token, &json!({ "title": "Two", "body": "b" }));
+    let etag = app.etag(first);
+    let patch = json!({ "title": "One, edited" });
+    assert_eq!(app.edit(Method::Patch, first, &token, &etag, &patch).status, Status::Ok);
+    let etag = app.etag(first);
+    let res = app.edit(Method::Delete, first, &token, &etag, &serde_json::Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+
+    let events = app.events("/post_feed/stream", &[("Last-Event-ID", "0")], 4);
+    let kinds: Vec<&str> = events.iter().map(|event| kind(event)).collect();
+    assert_eq!(kinds, ["created", "created", "updated", "deleted"]);
+    assert!(events[0].starts_with("id: 1\n"));
+    assert!(events[2].contains("One, edited"));
+    assert!(events[3].ends_with(&format!("data: {{\"uuid\":\"{}\"}}", first)));
+
+    let events = app.events("/post_feed/stream", &[("Last-Event-ID", "2")], 2);
+    assert!(events[0].starts_with("id: 3\n"));
+    assert!(events[1].starts_with("id: 4\n"));
+}
+
+#[test]
+fn new_posts_are_pushed() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let events = thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            app.create_post(&token, &json!({ "title": "Fresh", "body": "b" }));
+        });
+        app.events("/post_feed/stream", &[], 1)
+    });
+    assert_eq!(kind(&events[0]), "created");
+    assert!(events[0].contains("\"title\":\"Fresh\""));
+}
+
+#[test]
+fn missed_events_reset_the_client() {
+    let app = TestApp::new();
+    let events = app.events("/post_feed/stream", &[("Last-Event-ID", "99")], 1);
+    assert_eq!(kind(&events[0]), "reset");
+
+    let res = app.get_with("/post_feed/stream", &[("Last-Event-ID", "soon")]);
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.error_code(), "invalid_header");
+}
+
+#[test]
+fn the_log_is_bounded() {
+    let log = EventLog::new(2);
+    let post: NewPost = serde_json::from_value(json!({ "title": "t", "body": "b" })).unwrap();
+    let post = post.into_post();
+    for _ in 0..3 {
+        log.publish(EventKind::Updated, &post);
+    }
+    assert_eq!(log.last_id(), 3);
+    assert!(log.since(0).is_none());
+    let ids: Vec<u64> = log.since(1).unwrap().iter().map(|event| event.id).collect();
+    assert_eq!(ids, [2, 3]);
+    assert_eq!(log.since(3), Some(vec![]));
+}
+
+#[test]
+fn open_streams_are_capped() {
+    let mut config = Config::default();
+    config.limits.max_streams = Some(2);
+    let app = TestApp::with_config(config);
+    let first = app.open_stream("/post_feed/stream");
+    let _second = app.open_stream("/post_feed/stream");
+
+    let res = app.get("/post_feed/stream");
+    assert_eq!(res.status, Status::ServiceUnavailable);
+    assert_eq!(res.error_code(), "too_many_streams");
+    assert_eq!(res.header("Retry-After").unwrap(), "3");
+    assert_eq!(app.get("/tags").status, Status::Ok);
+
+    // A stream that ends gives its place back.
+    drop(first);
+    assert!(app.events("/post_feed/stream", &[], 0).is_empty());
+
+    let args = ["--threads", "4", "--max-streams", "4"].map(String::from);
+    assert!(Config::from_args(args.into_iter()).is_err());
+    let args = ["--threads", "4", "--max-streams", "3"].map(String::from);
+    assert_eq!(Config::from_args(args.into_iter()).unwrap().max_streams(), 3);
+    let args = ["--threads", "4"].map(String::from);
+    assert_eq!(Config::from_args(args.into_iter()).unwrap().max_streams(), 2);
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::TestApp;
+use iron::status::Status;
+use serde_json::{json, Value};
+
+fn titles(page: &Value) -> Vec<&str> {
+    page["posts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["title"].as_str().unwrap())
+        .collect()
+}
+
+fn app_with_posts() -> TestApp {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    for (title, body, tags) in [
+        ("Rust ownership", "Borrowing explained", json!(["rust"])),
+        ("Iron middleware", "Chains in iron and Rust", json!(["rust", "iron"])),
+        ("Gardening", "Tomatoes all summer", json!(["garden"])),
+    ] {
+        app.create_post(&token, &json!({ "title": title, "body": body, "tags": tags }));
+    }
+    app
+}
+
+#[test]
+fn feed_pages_through_posts() {
+    let app = app_with_posts();
+
+    let res = app.get("/post_feed?limit=2");
+    assert_eq!(res.status, Status::Ok);
+    let page = res.json();
+    assert_eq!(titles(&page), ["Rust ownership", "Iron middleware"]);
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let page = app.get(&format!("/post_feed?limit=2&cursor={}", cursor)).json();
+    assert_eq!(titles(&page), ["Gardening"]);
+    assert!(page["next_cursor"].is_null());
+
+    let page = app.get("/post_feed?sort=title").json();
+    assert_eq!(titles(&page), ["Gardening", "Iron middleware", "Rust ownership"]);
+
+    let res = app.get("/post_feed?limit=0");
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.json()["error"]["details"], "limit");
+}
+
+#[test]
+fn feed_filters_by_author_date_and_title() {
+    let app = app_with_posts();
+    app.create_post(&app.token("bob"), &json!({ "title": "Bob's rust", "body": "Rusty" }));
+
+    let page = app.get("/post_feed?author=bob").json();
+    assert_eq!(titles(&page), ["Bob's rust"]);
+    let page = app.get("/post_feed?title=RUST").json();
+    assert_eq!(titles(&page), ["Rust ownership", "Bob's rust"]);
+
+    let all = app.get("/post_feed").json();
+    let second = all["posts"][1]["datetime"].as_str().unwrap();
+    let page = app.get(&format!("/post_feed?since={}&until={}", second, "2999-01-01")).json();
+    assert_eq!(titles(&page), ["Iron middleware", "Gardening", "Bob's rust"]);
+    // `until` is exclusive.
+    let page = app.get(&format!("/post_feed?until={}", second)).json();
+    assert_eq!(titles(&page), ["Rust ownership"]);
+
+    let page = app.get("/post_feed?sort=-datetime&limit=2").json();
+    assert_eq!(titles(&page), ["Bob's rust", "Gardening"]);
+
+    for query in ["since=yesterday", "sort=author", "limit=101"] {
+        let res = app.get(&format!("/post_feed?{}", query));
+        assert_eq!(res.status, Status::BadRequest, "{}", query);
+    }
+}
+
+#[test]
+fn cursors_hold_their_place() {
+    let app = app_with_posts();
+    let page = app.get("/post_feed?sort=title&limit=2").json();
+    assert_eq!(titles(&page), ["Gardening", "Iron middleware"]);
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    // A post sorting before the cursor does not push the next page back.
+    app.create_post(&app.token("alice"), &json!({ "title": "Apples", "body": "Crisp" }));
+    let page = app.get(&format!("/post_feed?limit=2&cursor={}", cursor)).json();
+    assert_eq!(titles(&page), ["Rust ownership"]);
+
+    // A cursor only goes with the order it was issued for.
+    let res = app.get(&format!("/post_feed?sort=-datetime&cursor={}", cursor));
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.json()["error"]["details"], "cursor");
+}
+
+#[test]
+fn feed_is_cacheable() {
+    let app = app_with_posts();
+    let res = app.get("/post_feed");
+    let etag = res.header("ETag").unwrap();
+    let last_modified = res.header("Last-Modified").unwrap();
+
+    let res = app.get_with("/post_feed", &[("If-None-Match", &etag)]);
+    assert_eq!(res.status, Status::NotModified);
+    assert!(res.body.is_empty());
+
+    // Other representations and other queries are tagged apart.
+    let atom = app.get_with("/post_feed", &[("Accept", "application/atom+xml")]);
+    assert_ne!(atom.header("ETag").unwrap(), etag);
+    assert_ne!(app.get("/post_feed?limit=1").header("ETag").unwrap(), etag);
+
+    // Any write makes the tag stale.
+    app.create_post(&app.token("alice"), &json!({ "title": "New", "body": "Fresh" }));
+    let res = app.get_with("/post_feed", &[("If-None-Match", &etag)]);
+    assert_eq!(res.status, Status::Ok);
+    assert_ne!(res.header("ETag").unwrap(), etag);
+    // Even within the second `Last-Modified` was given in: a tagged response
+    // is never judged by its date.
+    for headers in [
+        vec![("If-Modified-Since", last_modified.as_str())],
+        vec![("If-None-Match", &etag), ("If-Modified-Since", &last_modified)],
+    ] {
+        assert_eq!(app.get_with("/post_feed", &headers).status, Status::Ok);
+    }
+}
+
+#[test]
+fn feed_as_atom_and_rss() {
+    let app = app_with_posts();
+
+    let res = app.get_with("/post_feed", &[("Accept", "application/atom+xml")]);
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(
+        res.header("Content-Type").unwrap(),
+        "application/atom+xml; charset=utf-8"
+    );
+    assert!(res.body.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert_eq!(res.body.matches("<entry>").count(), 3);
+
+    let res = app.get_with("/post_feed?limit=1", &[("Accept", "application/rss+xml")]);
+    assert!(res.body.contains("<rss version=\"2.0\""));
+    assert!(res.body.contains("<atom:link rel=\"next\""));
+
+    let res = app.get_with("/post_feed", &[("Accept", "text/csv")]);
+    assert_eq!(res.status, Status::NotAcceptable);
+    assert_eq!(res.error_code(), "not_acceptable");
+}
+
+#[test]
+fn tags_and_tag_feeds() {
+    let app = app_with_posts();
+
+    let res = app.get("/tags");
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(
+        res.json()["tags"],
+        json!([
+            { "name": "garden", "count": 1 },
+            { "name": "iron", "count": 1 },
+            { "name": "rust", "count": 2 },
+        ])
+    );
+
+    let page = app.get("/tags/Rust/posts").json();
+    assert_eq!(titles(&page), ["Rust ownership", "Iron middleware"]);
+
+    let page = app.get("/tags/rust/posts?tag=iron").json();
+    assert_eq!(titles(&page), ["Iron middleware"]);
+
+    let page = app.get("/post_feed?tag=garden|iron").json();
+    assert_eq!(titles(&page), ["Iron middleware", "Gardening"]);
+
+    assert_eq!(app.get("/tags/no%20way/posts").status, Status::BadRequest);
+}
+
+#[test]
+fn search_ranks_and_highlights() {
+    let app = app_with_posts();
+
+    let res = app.get("/search?q=rust");
+    assert_eq!(res.status, Status::Ok);
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 2);
+    assert!(results[0]["score"].as_f64().unwrap() >= results[1]["score"].as_f64().unwrap());
+    assert!(results
+        .iter()
+        .any(|hit| hit["snippet"].as_str().unwrap().contains("<mark>Rust</mark>")));
+
+    let res = app.get("/search?q=rust+-iron");
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["post"]["title"], "Rust ownership");
+
+    let res = app.get("/search?q=");
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.error_code(), "invalid_query");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::TestApp;
+use log::{Log, Metadata, Record};
+use serde_json::Value;
+use std::sync::{Mutex, Once};
+
+/// Keeps every access log line, for the tests to look through.
+struct AccessLog;
+
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+impl Log for AccessLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "web_api::access"
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            LINES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture_access_log() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&AccessLog).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+    });
+}
+
+/// The access log line of the request sent with `request_id`.
+fn logged(request_id: &str) -> Value {
+    LINES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| line["request_id"] == request_id)
+        .unwrap_or_else(|| panic!("{} was not logged", request_id))
+}
+
+#[test]
+fn every_request_is_logged_as_one_json_line() {
+    capture_access_log();
+    let app = TestApp::new();
+
+    let res = app.get_with("/tags", &[("X-Request-Id", "log-ok")]);
+    let line = logged("log-ok");
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["route"], "tags");
+    assert_eq!(line["path"], "/tags");
+    assert_eq!(line["status"], 200);
+    assert!(line["latency_ms"].as_f64().unwrap() >= 0.0);
+    assert_eq!(line["bytes"], res.body.len());
+
+    let res = app.get_with("/post/not-a-uuid", &[("X-Request-Id", "log-error")]);
+    let line = logged("log-error");
+    assert_eq!(line["route"], "post");
+    assert_eq!(line["status"], 400);
+    assert_eq!(line["bytes"], res.body.len());
+
+    app.get_with("/nothing/here", &[("X-Request-Id", "log-unmatched")]);
+    let line = logged("log-unmatched");
+    assert_eq!(line["route"], "unmatched");
+    assert_eq!(line["status"], 404);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::TestApp;
+use serde_json::json;
+
+/// Payloads that try to run script through raw HTML, attributes or links.
+const XSS_PAYLOADS: [&str; 14] = [
+    "<script>alert(1)</script>",
+    "<SCRIPT SRC=//evil.example/x.js></SCRIPT>",
+    "<img src=x onerror=alert(1)>",
+    "<svg onload=alert(1)><circle r=1 /></svg>",
+    "<body onload=alert(1)>",
+    "<iframe src=\"javascript:alert(1)\"></iframe>",
+    "<a href=\"JaVaScRiPt:alert(1)\">link</a>",
+    "<a href=\"&#106;avascript:alert(1)\">entity</a>",
+    "<a href=\"java\tscript:alert(1)\">tab</a>",
+    "[click](javascript:alert(1))",
+    "![img](javascript:alert(1))",
+    "<div style=\"background:url(javascript:alert(1))\">styled</div>",
+    "<form action=\"javascript:alert(1)\"><button>go</button></form>",
+    "<math><mi xlink:href=\"javascript:alert(1)\">x</mi></math>",
+];
+
+fn body_html(app: &TestApp, token: &str, body: &str) -> String {
+    let post = app.create_post(token, &json!({ "title": "T", "body": body }));
+    assert_eq!(post["body"], body);
+    post["body_html"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn markdown_is_rendered() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let body = "# Title\n\nSome *emphasis* and a [link](https://example.com).\n\n\
+        | a | b |\n|---|---|\n| 1 | 2 |\n\n```rust\nfn main() {}\n```\n";
+
+    let html = body_html(&app, &token, body);
+    assert!(html.contains("<h1>Title</h1>"), "{}", html);
+    assert!(html.contains("<em>emphasis</em>"), "{}", html);
+    assert!(html.contains("<a href=\"https://example.com\" rel=\"noopener noreferrer\">link</a>"));
+    assert!(html.contains("<table>") && html.contains("<td>2</td>"), "{}", html);
+    assert!(html.contains("<pre><code class=\"language-rust\">fn main() {}"), "{}", html);
+
+    let post = app.create_post(&token, &json!({ "title": "T", "body": "a < b" }));
+    let page = app.get_with(
+        &format!("/post/{}", post["uuid"].as_str().unwrap()),
+        &[("Accept", "text/html")],
+    );
+    assert!(page.body.contains("<p>a &lt; b</p>"), "{}", page.body);
+}
+
+#[test]
+fn xss_payloads_come_out_harmless() {
+    let app = TestApp::new();
+    let token = app.token("mallory");
+    for payload in XSS_PAYLOADS {
+        let html = body_html(&app, &token, payload).to_lowercase();
+        for forbidden in [
+            "<script", "javascript:", "onerror", "onload", "<iframe", "<svg", "style=", "<form",
+            "xlink:href",
+        ] {
+            assert!(!html.contains(forbidden), "{} survived in {:?}", forbidden, html);
+        }
+    }
+
+    let feed = app.get_with("/post_feed?limit=100", &[("Accept", "application/atom+xml")]);
+    assert!(!feed.body.contains("&lt;script"), "{}", feed.body);
+    assert!(feed.body.contains("<content type=\"html\">"));
+}
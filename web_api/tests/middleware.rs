@@ -0,0 +1,254 @@
+mod common;
+
+use common::TestApp;
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::json;
+use web_api::config::Config;
+use web_api::limits::RateLimit;
+
+#[test]
+fn request_ids_are_echoed_or_made_up() {
+    let app = TestApp::new();
+    let res = app.get_with("/tags", &[("X-Request-Id", "abc-123")]);
+    assert_eq!(res.header("X-Request-Id").unwrap(), "abc-123");
+
+    let res = app.get("/tags");
+    assert_eq!(res.header("X-Request-Id").unwrap().len(), 36);
+}
+
+#[test]
+fn unknown_routes_are_not_found() {
+    let app = TestApp::new();
+    let res = app.get("/nothing/here");
+    assert_eq!(res.status, Status::NotFound);
+    assert_eq!(res.error_code(), "not_found");
+}
+
+#[test]
+fn errors_carry_the_request_id() {
+    let app = TestApp::new();
+    let res = app.get_with("/post/not-a-uuid", &[("X-Request-Id", "abc-123")]);
+    assert_eq!(res.header("Content-Type").unwrap(), "application/json");
+    let error = &res.json()["error"];
+    assert_eq!(error["request_id"], "abc-123");
+    assert!(error["message"].is_string());
+    // So that the access log has their size too.
+    assert_eq!(res.header("Content-Length").unwrap(), res.body.len().to_string());
+
+    // Errors raised by middleware get the same shape.
+    let res = app.get_with("/tags", &[("Authorization", "Bearer nonsense")]);
+    assert_eq!(res.status, Status::Unauthorized);
+    assert_eq!(res.error_code(), "unauthorized");
+    assert_eq!(res.json()["error"]["request_id"].as_str().unwrap().len(), 36);
+}
+
+#[test]
+fn metrics_count_requests_by_route() {
+    let app = TestApp::new();
+    app.get("/tags");
+    app.get("/post/not-a-uuid");
+
+    let res = app.get("/metrics");
+    assert_eq!(res.status, Status::Ok);
+    assert!(res
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/plain"));
+    assert!(res
+        .body
+        .contains("http_requests_total{route=\"tags\",method=\"GET\",status=\"200\"} 1"));
+    assert!(res
+        .body
+        .contains("http_requests_total{route=\"post\",method=\"GET\",status=\"400\"} 1"));
+}
+
+#[test]
+fn openapi_document_is_served() {
+    let app = TestApp::new();
+    let res = app.get("/openapi.json");
+    assert_eq!(res.status, Status::Ok);
+    let document = res.json();
+    assert_eq!(document["openapi"], "3.0.3");
+    assert!(document["paths"]["/post/{id}"]["get"].is_object());
+}
+
+#[test]
+fn cors_preflight_and_headers() {
+    let mut config = Config::default();
+    config.cors_origins.push("https://app.example".to_string());
+    let app = TestApp::with_config(config);
+
+    let res = app.request(
+        Method::Options,
+        "/post/00000000-0000-0000-0000-000000000000",
+        &[
+            ("Origin", "https://app.example"),
+            ("Access-Control-Request-Method", "PATCH"),
+        ],
+        "",
+    );
+    assert_eq!(res.status, Status::NoContent);
+    assert_eq!(
+        res.header("Access-Control-Allow-Origin").unwrap(),
+        "https://app.example"
+    );
+    assert_eq!(
+        res.header("Access-Control-Allow-Methods").unwrap(),
+        "GET, PUT, PATCH, DELETE, HEAD, OPTIONS"
+    );
+
+    let res = app.request(
+        Method::Options,
+        "/tags",
+        &[
+            ("Origin", "https://evil.example"),
+            ("Access-Control-Request-Method", "GET"),
+        ],
+        "",
+    );
+    assert_eq!(res.status, Status::Forbidden);
+    assert_eq!(res.error_code(), "origin_not_allowed");
+
+    let res = app.get_with("/tags", &[("Origin", "https://app.example")]);
+    assert_eq!(
+        res.header("Access-Control-Allow-Origin").unwrap(),
+        "https://app.example"
+    );
+    assert_eq!(res.header("Vary").unwrap(), "Origin");
+
+    let res = app.get_with("/tags", &[("Origin", "https://evil.example")]);
+    assert!(res.header("Access-Control-Allow-Origin").is_none());
+}
+
+#[test]
+fn cors_covers_errors_and_plain_options() {
+    let mut config = Config::default();
+    config.cors_origins.push("https://app.example".to_string());
+    let app = TestApp::with_config(config);
+    let origin = ("Origin", "https://app.example");
+
+    let res = app.request(
+        Method::Options,
+        "/tags",
+        &[origin, ("Access-Control-Request-Method", "GET")],
+        "",
+    );
+    assert_eq!(res.header("Access-Control-Max-Age").unwrap(), "600");
+    assert!(res.header("Access-Control-Allow-Headers").unwrap().contains("If-Match"));
+
+    // A script on the page can read the error and the id to report it with.
+    let res = app.get_with("/post/not-a-uuid", &[origin]);
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.header("Access-Control-Allow-Origin").unwrap(), "https://app.example");
+    assert!(res.header("Access-Control-Expose-Headers").unwrap().contains("X-Request-Id"));
+
+    let res = app.request(Method::Options, "/tags", &[], "");
+    assert_eq!(res.status, Status::NoContent);
+    assert_eq!(res.header("Allow").unwrap(), "GET, HEAD, OPTIONS");
+    assert!(res.header("Access-Control-Allow-Methods").is_none());
+    let res = app.request(Method::Options, "/nothing/here", &[], "");
+    assert_eq!(res.status, Status::NotFound);
+}
+
+#[test]
+fn cors_wildcard_answers_a_literal_star() {
+    let mut config = Config::default();
+    config.cors_origins.push("*".to_string());
+    let app = TestApp::with_config(config);
+
+    for headers in [vec![("Origin", "https://any.example")], vec![]] {
+        let res = app.get_with("/tags", &headers);
+        assert_eq!(res.header("Access-Control-Allow-Origin").unwrap(), "*");
+        assert!(res.header("Vary").is_none_or(|vary| !vary.contains("Origin")));
+    }
+}
+
+#[test]
+fn rate_limits_reads_and_writes_separately() {
+    let mut config = Config::default();
+    config.limits.reads = RateLimit {
+        burst: 2.0,
+        per_second: 0.01,
+    };
+    config.limits.writes = RateLimit {
+        burst: 1.0,
+        per_second: 0.01,
+    };
+    let app = TestApp::with_config(config);
+
+    assert_eq!(app.get("/tags").status, Status::Ok);
+    assert_eq!(app.get("/tags").status, Status::Ok);
+    let res = app.get("/tags");
+    assert_eq!(res.status, Status::TooManyRequests);
+    assert_eq!(res.error_code(), "rate_limited");
+    assert_eq!(res.header("Retry-After").unwrap(), "100");
+
+    let credentials = json!({ "username": "x", "password": "y" });
+    assert_eq!(app.post("/login", None, &credentials).status, Status::Unauthorized);
+    let res = app.post("/login", None, &credentials);
+    assert_eq!(res.status, Status::TooManyRequests);
+
+    // Authenticated users have budgets of their own.
+    let token = app.token("alice");
+    let res = app.post("/post", Some(&token), &json!({ "title": "t", "body": "b" }));
+    assert_eq!(res.status, Status::Created);
+}
+
+#[test]
+fn rate_limits_refill_over_time() {
+    let mut config = Config::default();
+    config.limits.reads = RateLimit {
+        burst: 1.0,
+        per_second: 20.0,
+    };
+    let app = TestApp::with_config(config);
+
+    assert_eq!(app.get("/tags").status, Status::Ok);
+    let res = app.get("/tags");
+    assert_eq!(res.status, Status::TooManyRequests);
+    assert_eq!(res.header("Retry-After").unwrap(), "1");
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(app.get("/tags").status, Status::Ok);
+}
+
+#[test]
+fn bad_tokens_are_rate_limited() {
+    let mut config = Config::default();
+    config.limits.reads = RateLimit {
+        burst: 2.0,
+        per_second: 0.01,
+    };
+    let app = TestApp::with_config(config);
+
+    let headers = [("Authorization", "Bearer guessed.token")];
+    assert_eq!(app.get_with("/tags", &headers).status, Status::Unauthorized);
+    assert_eq!(app.get_with("/tags", &headers).status, Status::Unauthorized);
+    let res = app.get_with("/tags", &headers);
+    assert_eq!(res.status, Status::TooManyRequests);
+    // They come out of the same budget as anonymous requests.
+    assert_eq!(app.get("/tags").status, Status::TooManyRequests);
+}
+
+#[test]
+fn large_bodies_are_refused() {
+    let mut config = Config::default();
+    config.limits.max_body_bytes = 64;
+    let app = TestApp::with_config(config);
+
+    let body = json!({ "title": "t", "body": "b".repeat(100) });
+    let res = app.post("/post", Some(&app.token("alice")), &body);
+    assert_eq!(res.status, Status::PayloadTooLarge);
+    assert_eq!(res.error_code(), "payload_too_large");
+}
+
+#[test]
+fn the_largest_body_limit_does_not_overflow() {
+    let mut config = Config::default();
+    config.limits.max_body_bytes = u64::MAX;
+    let app = TestApp::with_config(config);
+
+    let body = json!({ "title": "t", "body": "b".repeat(100) });
+    let res = app.post("/post", Some(&app.token("alice")), &body);
+    assert_eq!(res.status, Status::Created);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::TestApp;
+use iron::status::Status;
+use serde_json::json;
+
+fn content_type(app: &TestApp, path: &str, accept: &str) -> Option<String> {
+    let res = app.get_with(path, &[("Accept", accept)]);
+    assert!(res.header("Vary").unwrap().contains("Accept"), "{}", accept);
+    match res.status {
+        Status::Ok => res.header("Content-Type"),
+        Status::NotAcceptable => None,
+        other => panic!("{} for {}", other, accept),
+    }
+}
+
+#[test]
+fn the_best_quality_wins() {
+    let app = TestApp::new();
+    let post = app.create_post(&app.token("alice"), &json!({ "title": "t", "body": "b" }));
+    let post = format!("/post/{}", post["uuid"].as_str().unwrap());
+    let json = Some("application/json".to_string());
+    let html = Some("text/html; charset=utf-8".to_string());
+    let atom = Some("application/atom+xml; charset=utf-8".to_string());
+
+    assert_eq!(content_type(&app, &post, "*/*"), json);
+    assert_eq!(content_type(&app, &post, "text/*"), html);
+    assert_eq!(content_type(&app, &post, "application/json;q=0.5, text/html"), html);
+    assert_eq!(content_type(&app, &post, "text/html;q=0.2, */*;q=0.4"), json);
+    // `q=0` rules a type out, even when a wider range would let it in.
+    assert_eq!(content_type(&app, &post, "text/html, application/json;q=0"), html);
+    assert_eq!(content_type(&app, &post, "*/*, application/json;q=0, text/html;q=0"), None);
+
+    let feed = "/post_feed";
+    assert_eq!(content_type(&app, feed, "application/xml, application/atom+xml"), atom);
+    assert_eq!(content_type(&app, feed, "text/html"), None);
+}
@@ -0,0 +1,42 @@
+use web_api::app;
+use web_api::auth::Auth;
+use web_api::database::Database;
+use web_api::handlers::Handlers;
+use web_api::metrics::Metrics;
+use web_api::openapi::{self, path_template};
+
+use std::sync::{Arc, RwLock};
+
+#[test]
+fn every_route_is_documented() {
+    let database = Arc::new(RwLock::new(Database::new()));
+    let auth = Arc::new(Auth::new(Auth::random_secret(), chrono::Duration::hours(1)));
+    let routes = app::routes(Handlers::new(database, auth, 1), Arc::new(Metrics::new()));
+    let document = openapi::document();
+
+    let undocumented: Vec<String> = routes
+        .table()
+        .routes()
+        .iter()
+        .filter(|route| {
+            let method = route.method.as_ref().to_lowercase();
+            document["paths"][path_template(route.path)][method.as_str()].is_null()
+        })
+        .map(|route| format!("{} {}", route.method, route.path))
+        .collect();
+    assert!(undocumented.is_empty(), "undocumented routes: {:?}", undocumented);
+}
+
+#[test]
+fn schemas_are_generated_from_the_models() {
+    let document = openapi::document();
+    let schemas = &document["components"]["schemas"];
+    let post = &schemas["Post"]["properties"];
+    for field in ["title", "body", "author", "datetime", "updated_at", "uuid", "tags"] {
+        assert!(!post[field].is_null(), "Post schema lacks `{}`", field);
+    }
+    let error = &schemas["ErrorDetail"]["properties"];
+    for field in ["code", "message", "details", "request_id"] {
+        assert!(!error[field].is_null(), "error schema lacks `{}`", field);
+    }
+}
@@ -0,0 +1,279 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+
+fn example() -> Value {
+    json!({ "title": "Hello", "body": "First words", "tags": ["Rust", "iron"] })
+}
+
+#[test]
+fn create_and_get_a_post() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+
+    let res = app.post("/post", Some(&token), &example());
+    assert_eq!(res.status, Status::Created);
+    let post = res.json();
+    assert_eq!(post["author"], "alice");
+    assert_eq!(post["tags"], json!(["iron", "rust"]));
+    let id = post["uuid"].as_str().unwrap();
+    assert_eq!(res.header("Location").unwrap(), format!("/post/{}", id));
+    assert!(res.header("ETag").is_some());
+
+    let res = app.get(&format!("/post/{}", id));
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(res.header("Content-Type").unwrap(), "application/json");
+    assert_eq!(res.json(), post);
+}
+
+#[test]
+fn ids_and_times_are_assigned_by_the_server() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let before = chrono::Utc::now();
+    let nil = "00000000-0000-0000-0000-000000000000";
+    let sent = json!({
+        "title": "t",
+        "body": "b",
+        "uuid": nil,
+        "datetime": "2001-01-01T00:00:00Z",
+    });
+
+    let one = app.create_post(&token, &sent);
+    let two = app.create_post(&token, &sent);
+    assert_ne!(one["uuid"], nil);
+    assert_ne!(one["uuid"], two["uuid"]);
+    let datetime: chrono::DateTime<chrono::Utc> =
+        one["datetime"].as_str().unwrap().parse().unwrap();
+    assert!(datetime >= before && datetime <= chrono::Utc::now());
+}
+
+#[test]
+fn creating_needs_a_token() {
+    let app = TestApp::new();
+    let res = app.post("/post", None, &example());
+    assert_eq!(res.status, Status::Unauthorized);
+    assert_eq!(res.error_code(), "unauthorized");
+
+    let res = app.post("/post", Some("not-a-token"), &example());
+    assert_eq!(res.status, Status::Unauthorized);
+    assert_eq!(res.header("WWW-Authenticate").unwrap(), "Bearer");
+}
+
+#[test]
+fn creating_validates_the_body() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+
+    let res = app.post("/post", Some(&token), &json!({ "title": " ", "body": "x" }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["title"]));
+
+    let res = app.request(
+        Method::Post,
+        "/post",
+        &[("Authorization", &format!("Bearer {}", token))],
+        "{not json",
+    );
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.error_code(), "invalid_json");
+
+    let res = app.post("/post", Some(&token), &json!({ "title": "t" }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.error_code(), "invalid_fields");
+    assert_eq!(res.json()["error"]["details"], json!(["body"]));
+
+    let res = app.post("/post", Some(&token), &json!({ "title": 1 }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.error_code(), "invalid_body");
+}
+
+#[test]
+fn only_admins_post_as_someone_else() {
+    let app = TestApp::new();
+    let post = json!({ "title": "t", "body": "b", "author": "bob" });
+
+    let res = app.post("/post", Some(&app.token("alice")), &post);
+    assert_eq!(res.status, Status::Forbidden);
+
+    let res = app.post("/post", Some(&app.token(ADMIN)), &post);
+    assert_eq!(res.status, Status::Created);
+    assert_eq!(res.json()["author"], "bob");
+}
+
+#[test]
+fn unknown_and_malformed_ids() {
+    let app = TestApp::new();
+    let res = app.get("/post/00000000-0000-0000-0000-000000000000");
+    assert_eq!(res.status, Status::NotFound);
+    assert_eq!(res.error_code(), "not_found");
+
+    // `try_handler!` turns the parse error into a 400.
+    let res = app.get("/post/not-a-uuid");
+    assert_eq!(res.status, Status::BadRequest);
+    assert_eq!(res.error_code(), "invalid_id");
+    assert_eq!(res.json()["error"]["details"], "id");
+
+    let token = app.token("alice");
+    for method in [Method::Put, Method::Patch, Method::Delete] {
+        let res = app.edit(method, "not-a-uuid", &token, "*", &example());
+        assert_eq!(res.status, Status::BadRequest);
+        assert_eq!(res.error_code(), "invalid_id");
+    }
+}
+
+#[test]
+fn put_replaces_a_post() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &example());
+    let id = post["uuid"].as_str().unwrap();
+    let etag = app.etag(id);
+
+    let replacement = json!({ "title": "New", "body": "Other words" });
+    let res = app.edit(Method::Put, id, &token, &etag, &replacement);
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    let updated = res.json();
+    assert_eq!(updated["title"], "New");
+    assert_eq!(updated["tags"], json!([]));
+    assert_eq!(updated["datetime"], post["datetime"]);
+    assert_ne!(res.header("ETag").unwrap(), etag);
+
+    // The old tag no longer matches.
+    let res = app.edit(Method::Put, id, &token, &etag, &replacement);
+    assert_eq!(res.status, Status::PreconditionFailed);
+    assert_eq!(res.error_code(), "precondition_failed");
+
+    let res = app.edit(Method::Put, id, &token, "*", &json!({ "title": "New" }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+}
+
+#[test]
+fn patch_merges_into_a_post() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &example());
+    let id = post["uuid"].as_str().unwrap();
+
+    let res = app.edit(Method::Patch, id, &token, &app.etag(id), &json!({ "title": "Patched" }));
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert_eq!(res.json()["title"], "Patched");
+    assert_eq!(res.json()["body"], "First words");
+
+    let res = app.edit(Method::Patch, id, &token, "*", &json!({ "uuid": "x" }));
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["uuid"]));
+
+    // A null removes the field, as in a JSON merge patch.
+    let res = app.edit(Method::Patch, id, &token, "*", &json!({ "tags": null }));
+    assert_eq!(res.json()["tags"], json!([]));
+    assert_eq!(res.json()["title"], "Patched");
+}
+
+#[test]
+fn edits_move_updated_at_only() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &example());
+    let id = post["uuid"].as_str().unwrap();
+    assert_eq!(post["updated_at"], post["datetime"]);
+
+    let res = app.edit(Method::Patch, id, &token, "*", &json!({ "body": "Later words" }));
+    let patched = res.json();
+    assert_eq!(patched["datetime"], post["datetime"]);
+    let updated_at = |post: &Value| -> chrono::DateTime<chrono::Utc> {
+        post["updated_at"].as_str().unwrap().parse().unwrap()
+    };
+    assert!(updated_at(&patched) > updated_at(&post));
+}
+
+#[test]
+fn editing_a_missing_post() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let missing = "00000000-0000-0000-0000-000000000000";
+    for method in [Method::Put, Method::Patch] {
+        let res = app.edit(method, missing, &token, "*", &example());
+        assert_eq!(res.status, Status::NotFound);
+        assert_eq!(res.error_code(), "not_found");
+    }
+}
+
+#[test]
+fn edits_need_if_match_and_the_author() {
+    let app = TestApp::new();
+    let post = app.create_post(&app.token("alice"), &example());
+    let id = post["uuid"].as_str().unwrap();
+
+    let res = app.request(
+        Method::Delete,
+        &format!("/post/{}", id),
+        &[("Authorization", &format!("Bearer {}", app.token("alice")))],
+        "",
+    );
+    assert_eq!(res.status, Status::PreconditionRequired);
+
+    let res = app.edit(Method::Delete, id, &app.token("mallory"), "*", &Value::Null);
+    assert_eq!(res.status, Status::Forbidden);
+
+    let res = app.edit(Method::Delete, id, &app.token(ADMIN), "*", &Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+}
+
+#[test]
+fn delete_removes_a_post() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &example());
+    let id = post["uuid"].as_str().unwrap();
+
+    let res = app.edit(Method::Delete, id, &token, &app.etag(id), &Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+    assert_eq!(app.get(&format!("/post/{}", id)).status, Status::NotFound);
+
+    let res = app.edit(Method::Delete, id, &token, "*", &Value::Null);
+    assert_eq!(res.status, Status::NotFound);
+}
+
+#[test]
+fn a_post_answers_if_none_match() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &example());
+    let id = post["uuid"].as_str().unwrap();
+    let path = format!("/post/{}", id);
+    let etag = app.etag(id);
+
+    for if_none_match in [etag.as_str(), "*", &format!("\"other\", W/{}", etag)] {
+        let res = app.get_with(&path, &[("If-None-Match", if_none_match)]);
+        assert_eq!(res.status, Status::NotModified, "{}", if_none_match);
+        assert!(res.body.is_empty());
+    }
+
+    app.edit(Method::Patch, id, &token, &etag, &json!({ "title": "Changed" }));
+    let res = app.get_with(&path, &[("If-None-Match", &etag)]);
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(res.json()["title"], "Changed");
+}
+
+#[test]
+fn a_post_as_html() {
+    let app = TestApp::new();
+    let post = app.create_post(
+        &app.token("alice"),
+        &json!({ "title": "<script>", "body": "a & b" }),
+    );
+    let path = format!("/post/{}", post["uuid"].as_str().unwrap());
+
+    let res = app.get_with(&path, &[("Accept", "text/html")]);
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(res.header("Content-Type").unwrap(), "text/html; charset=utf-8");
+    assert!(res.body.contains("<h1>&lt;script&gt;</h1>"));
+    assert!(res.body.contains("a &amp; b"));
+
+    let res = app.get_with(&path, &[("Accept", "image/png")]);
+    assert_eq!(res.status, Status::NotAcceptable);
+}
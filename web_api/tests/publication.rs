@@ -0,0 +1,147 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+use web_api::scheduler;
+
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+fn uuids(page: &Value) -> Vec<&str> {
+    page["posts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["uuid"].as_str().unwrap())
+        .collect()
+}
+
+/// Creates a draft by alice next to a published post and returns the
+/// draft's uuid.
+fn app_with_draft() -> (TestApp, String) {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    app.create_post(&token, &json!({ "title": "Out", "body": "Shipped", "tags": ["rust"] }));
+    let draft = app.create_post(
+        &token,
+        &json!({ "title": "Soon", "body": "Not yet", "tags": ["rust"], "status": "draft" }),
+    );
+    assert_eq!(draft["status"], "draft");
+    let id = draft["uuid"].as_str().unwrap().to_string();
+    (app, id)
+}
+
+#[test]
+fn drafts_are_seen_only_by_their_author() {
+    let (app, id) = app_with_draft();
+    let alice = bearer(&app.token("alice"));
+    let bob = bearer(&app.token("bob"));
+    let path = format!("/post/{}", id);
+
+    assert_eq!(uuids(&app.get("/post_feed").json()).len(), 1);
+    assert!(!uuids(&app.get("/post_feed").json()).contains(&id.as_str()));
+    assert_eq!(app.get("/tags").json()["tags"][0]["count"], 1);
+    assert_eq!(app.get("/search?q=yet").json()["results"], json!([]));
+
+    assert_eq!(app.get(&path).error_code(), "not_found");
+    assert_eq!(app.get_with(&path, &[("Authorization", &bob)]).status, Status::NotFound);
+    let res = app.get_with(&path, &[("Authorization", &alice)]);
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(res.header("Cache-Control").unwrap(), "private");
+    let comments = format!("{}/comments", path);
+    let res = app.post(&comments, Some(&app.token("bob")), &json!({ "body": "Hi" }));
+    assert_eq!(res.status, Status::NotFound);
+
+    let res = app.get_with("/post_feed?status=draft", &[("Authorization", &alice)]);
+    assert_eq!(res.status, Status::Ok);
+    assert_eq!(uuids(&res.json()), [id.as_str()]);
+    assert!(res.header("Vary").unwrap().contains("Authorization"));
+    let res = app.get_with("/tags/rust/posts?status=draft", &[("Authorization", &alice)]);
+    assert_eq!(uuids(&res.json()), [id.as_str()]);
+    let res = app.get_with("/post_feed?status=draft", &[("Authorization", &bob)]);
+    assert_eq!(res.json()["posts"], json!([]));
+
+    assert_eq!(app.get("/post_feed?status=draft").error_code(), "unauthorized");
+    let res = app.get_with("/post_feed?status=draft&author=alice", &[("Authorization", &bob)]);
+    assert_eq!(res.status, Status::Forbidden);
+    let admin = bearer(&app.token(ADMIN));
+    let res = app.get_with("/post_feed?status=draft&author=alice", &[("Authorization", &admin)]);
+    assert_eq!(uuids(&res.json()), [id.as_str()]);
+    assert_eq!(app.get("/post_feed?status=hidden").json()["error"]["details"], "status");
+}
+
+#[test]
+fn publishing_a_draft_lists_it_as_new() {
+    let (app, id) = app_with_draft();
+    let token = app.token("alice");
+    let alice = bearer(&token);
+    let events = app.database.read().unwrap().events();
+    let last_id = events.last_id().to_string();
+
+    let path = format!("/post/{}", id);
+    let etag = app.get_with(&path, &[("Authorization", &alice)]).header("ETag").unwrap();
+    let res = app.edit(Method::Patch, &id, &token, &etag, &json!({ "status": "published" }));
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    let post = res.json();
+    assert_eq!(post["status"], "published");
+    assert_eq!(post["datetime"], post["updated_at"]);
+    let revisions = app.get(&format!("{}/revisions", path)).json()["revisions"].clone();
+    assert_eq!(revisions.as_array().unwrap().len(), 1, "only the status changed");
+    assert_eq!(app.get("/post_feed?sort=-datetime").json()["posts"][0]["uuid"], id.as_str());
+
+    let etag = app.etag(&id);
+    let res = app.edit(Method::Patch, &id, &token, &etag, &json!({ "status": "archived" }));
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert_eq!(app.get(&path).status, Status::Ok);
+    assert!(!uuids(&app.get("/post_feed").json()).contains(&id.as_str()));
+    let res = app.get("/post_feed?status=archived");
+    assert_eq!(uuids(&res.json()), [id.as_str()]);
+
+    let received = app.events("/post_feed/stream", &[("Last-Event-ID", &last_id)], 2);
+    assert!(received[0].contains("event: created\n"), "{}", received[0]);
+    assert!(received[1].contains("event: deleted\n"), "{}", received[1]);
+}
+
+#[test]
+fn scheduled_posts_are_published_when_due() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let unscheduled = json!({ "title": "T", "body": "B", "status": "scheduled" });
+    let res = app.post("/post", Some(&token), &unscheduled);
+    assert_eq!(res.status, Status::UnprocessableEntity);
+    assert_eq!(res.json()["error"]["details"], json!(["publish_at"]));
+
+    let publish_at = chrono::Utc::now();
+    let post = app.create_post(
+        &token,
+        &json!({
+            "title": "Later",
+            "body": "On time",
+            "status": "scheduled",
+            "publish_at": publish_at,
+        }),
+    );
+    assert_eq!(post["status"], "scheduled");
+    assert!(post["publish_at"].is_string());
+    let id = post["uuid"].as_str().unwrap().to_string();
+    assert_eq!(app.get("/post_feed").json()["posts"], json!([]));
+
+    scheduler::spawn(app.database.clone(), Duration::from_millis(10));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while app.get("/post_feed").json()["posts"] == json!([]) {
+        assert!(Instant::now() < deadline, "the scheduled post was not published");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let post = app.get(&format!("/post/{}", id)).json();
+    assert_eq!(post["status"], "published");
+    assert!(post["publish_at"].is_null());
+    let revisions = app.get(&format!("/post/{}/revisions", id)).json();
+    assert_eq!(revisions["revisions"].as_array().unwrap().len(), 1);
+    assert!(app.database.read().unwrap().next_due().is_none());
+}
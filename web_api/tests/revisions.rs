@@ -0,0 +1,166 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+use web_api::database::Database;
+use web_api::models::{NewPost, Post};
+use web_api::storage::FileStorage;
+
+/// A post by alice, edited once by her and once by an admin.
+fn edited_post(app: &TestApp) -> String {
+    let token = app.token("alice");
+    let post = app.create_post(
+        &token,
+        &json!({ "title": "Draft", "body": "one\ntwo\nthree", "tags": ["a"] }),
+    );
+    let id = post["uuid"].as_str().unwrap().to_string();
+
+    let etag = app.etag(&id);
+    let replacement = json!({
+        "title": "Draft",
+        "body": "one\n2\nthree\nfour",
+        "tags": ["a", "b"],
+        "note": "Numbers",
+    });
+    let res = app.edit(Method::Put, &id, &token, &etag, &replacement);
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+
+    let etag = app.etag(&id);
+    let patch = json!({ "title": "Final", "note": "Better title" });
+    let res = app.edit(Method::Patch, &id, &app.token(ADMIN), &etag, &patch);
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert!(res.json().get("note").is_none());
+    id
+}
+
+fn restore(app: &TestApp, id: &str, n: u32, token: &str, body: &str) -> common::TestResponse {
+    let authorization = format!("Bearer {}", token);
+    app.request(
+        Method::Post,
+        &format!("/post/{}/revisions/{}/restore", id, n),
+        &[("Authorization", &authorization)],
+        body,
+    )
+}
+
+#[test]
+fn every_change_is_a_revision() {
+    let app = TestApp::new();
+    let id = edited_post(&app);
+
+    let res = app.get(&format!("/post/{}/revisions", id));
+    assert_eq!(res.status, Status::Ok);
+    let revisions = res.json()["revisions"].clone();
+    let summary: Vec<(u64, &str, &Value)> = revisions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| (r["number"].as_u64().unwrap(), r["editor"].as_str().unwrap(), &r["note"]))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, "alice", &Value::Null),
+            (2, "alice", &json!("Numbers")),
+            (3, ADMIN, &json!("Better title")),
+        ]
+    );
+
+    let revision = app.get(&format!("/post/{}/revisions/2", id)).json();
+    assert_eq!(revision["title"], "Draft");
+    assert_eq!(revision["body"], "one\n2\nthree\nfour");
+
+    assert_eq!(app.get(&format!("/post/{}/revisions/4", id)).status, Status::NotFound);
+    let res = app.get(&format!("/post/{}/revisions/0", id));
+    assert_eq!(res.error_code(), "invalid_revision");
+}
+
+#[test]
+fn revisions_diff_line_by_line() {
+    let app = TestApp::new();
+    let id = edited_post(&app);
+
+    let diff = app.get(&format!("/post/{}/revisions/2/diff", id)).json();
+    assert_eq!(diff["from"], 1);
+    let fields = diff["fields"].as_array().unwrap();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0]["field"], "body");
+    let lines: Vec<String> = fields[0]["lines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|line| {
+            let change = line["change"].as_str().unwrap();
+            format!("{} {}", change, line["text"].as_str().unwrap())
+        })
+        .collect();
+    assert_eq!(lines, ["equal one", "delete two", "insert 2", "equal three", "insert four"]);
+    assert_eq!(fields[1]["field"], "tags");
+
+    let diff = app.get(&format!("/post/{}/revisions/3/diff?from=1", id)).json();
+    let names: Vec<&str> = diff["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["title", "body", "tags"]);
+
+    let diff = app.get(&format!("/post/{}/revisions/1/diff", id)).json();
+    assert_eq!(diff["from"], 0);
+    assert_eq!(diff["fields"][0]["lines"][0]["change"], "insert");
+}
+
+#[test]
+fn restoring_makes_a_new_revision() {
+    let app = TestApp::new();
+    let id = edited_post(&app);
+
+    let res = restore(&app, &id, 1, &app.token("bob"), "");
+    assert_eq!(res.status, Status::Forbidden);
+
+    let res = restore(&app, &id, 1, &app.token("alice"), "");
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert_eq!(res.json()["title"], "Draft");
+    assert_eq!(res.json()["body"], "one\ntwo\nthree");
+
+    let revisions = app.get(&format!("/post/{}/revisions", id)).json()["revisions"].clone();
+    assert_eq!(revisions[3]["number"], 4);
+    assert_eq!(revisions[3]["note"], "Restored revision 1");
+
+    let res = restore(&app, &id, 2, &app.token("alice"), r#"{ "note": "Back again" }"#);
+    assert_eq!(res.status, Status::Ok);
+    let revisions = app.get(&format!("/post/{}/revisions", id)).json()["revisions"].clone();
+    assert_eq!(revisions[4]["note"], "Back again");
+
+    assert_eq!(restore(&app, &id, 9, &app.token("alice"), "").status, Status::NotFound);
+}
+
+#[test]
+fn revisions_survive_a_restart() {
+    let dir = std::env::temp_dir().join(format!("web_api-revisions-{}", uuid::Uuid::new_v4()));
+    let open = || Database::open(Box::new(FileStorage::new(&dir).unwrap())).unwrap();
+    let post = Post::new("Kept", "one", "alice", chrono::Utc::now(), uuid::Uuid::new_v4());
+    let id = *post.uuid();
+
+    let mut database = open();
+    database.add_post(post).unwrap();
+    let edit: NewPost = serde_json::from_value(json!({
+        "title": "Kept", "body": "two", "author": "alice",
+    }))
+    .unwrap();
+    database.update_post(&id, edit, "alice").unwrap();
+    drop(database);
+
+    // Once replayed from the change log, then from a snapshot.
+    for _ in 0..2 {
+        let mut database = open();
+        let numbers: Vec<u32> = database.revisions(&id).iter().map(|r| r.number()).collect();
+        assert_eq!(numbers, [1, 2]);
+        assert_eq!(database.revision(&id, 2).unwrap().body(), "two");
+        database.flush().unwrap();
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::TestApp;
+use iron::method::Method;
+use serde_json::{json, Value};
+
+fn titles(app: &TestApp, q: &str) -> Vec<String> {
+    app.get(&format!("/search?q={}", q)).json()["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["post"]["title"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn phrases_must_appear_in_order() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    for (title, body) in [
+        ("Borrow checker", "The borrow checker is strict"),
+        ("Checker games", "A checker can borrow nothing"),
+        ("Split", "borrow"),
+    ] {
+        app.create_post(&token, &json!({ "title": title, "body": body }));
+    }
+
+    assert_eq!(titles(&app, "%22BORROW+checker%22"), ["Borrow checker"]);
+    assert_eq!(titles(&app, "borrow+-%22borrow+checker%22").len(), 2);
+    // The end of the title and the start of the body are not one phrase.
+    assert!(titles(&app, "%22split+borrow%22").is_empty());
+}
+
+#[test]
+fn the_index_follows_edits_and_deletes() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "Old", "body": "Walrus" }));
+    let id = post["uuid"].as_str().unwrap();
+    assert_eq!(titles(&app, "walrus"), ["Old"]);
+
+    let res = app.edit(Method::Patch, id, &token, "*", &json!({ "body": "Narwhal" }));
+    assert_eq!(res.json()["body"], "Narwhal");
+    assert!(titles(&app, "walrus").is_empty());
+    assert_eq!(titles(&app, "narwhal"), ["Old"]);
+
+    app.edit(Method::Delete, id, &token, "*", &Value::Null);
+    assert!(titles(&app, "narwhal").is_empty());
+}
@@ -0,0 +1,167 @@
+use web_api::database::Database;
+use web_api::models::Post;
+use web_api::storage::{Change, FileStorage, State, Storage};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Records every change but fails to compact.
+#[derive(Debug, Default)]
+struct CannotCompact {
+    recorded: Arc<AtomicUsize>,
+    compactions: Arc<AtomicUsize>,
+}
+
+impl Storage for CannotCompact {
+    fn load(&mut self) -> io::Result<State> {
+        Ok(State::default())
+    }
+
+    fn record(&mut self, _: &Change) -> io::Result<()> {
+        self.recorded.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn compact(&mut self, _: &State) -> io::Result<()> {
+        self.compactions.fetch_add(1, Ordering::SeqCst);
+        Err(io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self, _: &State) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_failed_compaction_does_not_fail_the_write() {
+    let storage = CannotCompact::default();
+    let (recorded, compactions) = (storage.recorded.clone(), storage.compactions.clone());
+    let mut database = Database::open(Box::new(storage)).unwrap();
+
+    for title in ["One", "Two"] {
+        let post = Post::new(title, "b", "alice", chrono::Utc::now(), uuid::Uuid::new_v4());
+        let uuid = *post.uuid();
+        database.add_post(post).unwrap();
+        assert_eq!(database.post(&uuid).unwrap().title(), title);
+    }
+    assert_eq!(recorded.load(Ordering::SeqCst), 2);
+    // Tried again after every change.
+    assert_eq!(compactions.load(Ordering::SeqCst), 2);
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("web_api-{}-{}", name, uuid::Uuid::new_v4()))
+}
+
+fn open(dir: &Path) -> Database {
+    Database::open(Box::new(FileStorage::new(dir).unwrap())).unwrap()
+}
+
+fn titles(database: &Database) -> Vec<String> {
+    database.posts().map(|post| post.title().to_string()).collect()
+}
+
+fn add(database: &mut Database, title: &str) -> uuid::Uuid {
+    let post = Post::new(title, "b", "alice", chrono::Utc::now(), uuid::Uuid::new_v4());
+    let uuid = *post.uuid();
+    database.add_post(post).unwrap();
+    uuid
+}
+
+#[test]
+fn the_log_is_replayed_on_top_of_the_snapshot() {
+    let dir = data_dir("replay");
+    let mut database = open(&dir);
+    add(&mut database, "One");
+    let two = add(&mut database, "Two");
+    database.flush().unwrap();
+    add(&mut database, "Three");
+    database.delete_post(&two).unwrap();
+    // No flush: the last two changes are only in the log.
+    drop(database);
+
+    let database = open(&dir);
+    assert_eq!(titles(&database), ["One", "Three"]);
+    assert!(database.trashed(&two).is_ok());
+    drop(database);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_flush_folds_the_log_into_the_snapshot() {
+    let dir = data_dir("flush");
+    let mut database = open(&dir);
+    add(&mut database, "One");
+    assert!(!fs::read_to_string(dir.join("changes.jsonl")).unwrap().is_empty());
+    database.flush().unwrap();
+    assert!(fs::read_to_string(dir.join("changes.jsonl")).unwrap().is_empty());
+    assert!(fs::read_to_string(dir.join("snapshot.json")).unwrap().contains("\"One\""));
+    drop(database);
+
+    assert_eq!(titles(&open(&dir)), ["One"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_snapshot_of_a_bare_post_list_still_loads() {
+    let dir = data_dir("bare");
+    fs::create_dir_all(&dir).unwrap();
+    let posts = ["One", "Two"]
+        .map(|title| Post::new(title, "b", "alice", chrono::Utc::now(), uuid::Uuid::new_v4()));
+    fs::write(dir.join("snapshot.json"), serde_json::to_vec(&posts).unwrap()).unwrap();
+
+    let database = open(&dir);
+    assert_eq!(titles(&database), ["One", "Two"]);
+    assert!(database.post(posts[1].uuid()).is_some());
+    drop(database);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_torn_last_entry_is_dropped() {
+    let dir = data_dir("torn");
+    let mut database = open(&dir);
+    add(&mut database, "One");
+    drop(database);
+    let mut log = OpenOptions::new().append(true).open(dir.join("changes.jsonl")).unwrap();
+    log.write_all(b"{\"op\":\"put\",\"post\":{\"uuid\":").unwrap();
+    drop(log);
+
+    let mut database = open(&dir);
+    assert_eq!(titles(&database), ["One"]);
+    add(&mut database, "Two");
+    drop(database);
+    // The entry after the torn one is not lost behind it.
+    assert_eq!(titles(&open(&dir)), ["One", "Two"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn expired_posts_are_purged_in_one_entry() {
+    let dir = data_dir("purge");
+    let mut database = open(&dir);
+    let kept = add(&mut database, "Kept");
+    for title in ["One", "Two", "Three"] {
+        let uuid = add(&mut database, title);
+        database.delete_post(&uuid).unwrap();
+    }
+    database.flush().unwrap();
+
+    let purged = database.purge_trash(chrono::Utc::now()).unwrap();
+    assert_eq!(purged.len(), 3);
+    assert_eq!(titles(&database), ["Kept"]);
+    assert!(database.post(&kept).is_some());
+    let log = fs::read_to_string(dir.join("changes.jsonl")).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    drop(database);
+
+    let database = open(&dir);
+    assert_eq!(titles(&database), ["Kept"]);
+    assert!(database.trash().is_empty());
+    assert!(purged.iter().all(|uuid| database.trashed(uuid).is_err()));
+    drop(database);
+    fs::remove_dir_all(&dir).unwrap();
+}
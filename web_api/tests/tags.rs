@@ -0,0 +1,69 @@
+mod common;
+
+use common::TestApp;
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+
+#[test]
+fn tags_are_normalized_and_validated() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+
+    let post = app.create_post(
+        &token,
+        &json!({ "title": "t", "body": "b", "tags": ["Rust", " rust ", "web-dev"] }),
+    );
+    assert_eq!(post["tags"], json!(["rust", "web-dev"]));
+
+    let too_many: Vec<String> = (0..21).map(|n| format!("tag{}", n)).collect();
+    for tags in [json!(["two words"]), json!([""]), json!(["x".repeat(51)]), json!(too_many)] {
+        let post = json!({ "title": "t", "body": "b", "tags": tags });
+        let res = app.post("/post", Some(&token), &post);
+        assert_eq!(res.status, Status::UnprocessableEntity);
+        assert_eq!(res.json()["error"]["details"], json!(["tags"]));
+    }
+}
+
+#[test]
+fn tag_counts_follow_edits() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "t", "body": "b", "tags": ["old"] }));
+    let id = post["uuid"].as_str().unwrap();
+    assert_eq!(app.get("/tags").json()["tags"], json!([{ "name": "old", "count": 1 }]));
+
+    app.edit(Method::Patch, id, &token, "*", &json!({ "tags": ["new"] }));
+    assert_eq!(app.get("/tags").json()["tags"], json!([{ "name": "new", "count": 1 }]));
+    assert_eq!(app.get("/tags/old/posts").json()["posts"], json!([]));
+    assert_eq!(app.get("/tags/new/posts").json()["posts"][0]["uuid"], id);
+
+    app.edit(Method::Delete, id, &token, "*", &Value::Null);
+    assert_eq!(app.get("/tags").json()["tags"], json!([]));
+}
+
+#[test]
+fn tag_expressions_combine_and_and_or() {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    for (title, tags) in [
+        ("A", json!(["a"])),
+        ("AB", json!(["a", "b"])),
+        ("BC", json!(["b", "c"])),
+    ] {
+        app.create_post(&token, &json!({ "title": title, "body": "b", "tags": tags }));
+    }
+    let titles = |query: &str| -> Vec<String> {
+        app.get(&format!("/post_feed?{}", query)).json()["posts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|post| post["title"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(titles("tag=a&tag=b"), ["AB"]);
+    assert_eq!(titles("tag=a|c"), ["A", "AB", "BC"]);
+    assert_eq!(titles("tag=a|c&tag=b"), ["AB", "BC"]);
+    assert!(titles("tag=nothing").is_empty());
+    assert_eq!(app.get("/post_feed?tag=a|").status, Status::BadRequest);
+}
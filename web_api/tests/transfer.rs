@@ -0,0 +1,217 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+use std::io::BufReader;
+use web_api::config::Config;
+use web_api::database::Database;
+use web_api::limits::LimitedReader;
+use web_api::transfer::{self, Import};
+
+fn import(app: &TestApp, query: &str, content_type: &str, body: &str) -> Value {
+    let authorization = format!("Bearer {}", app.token(ADMIN));
+    let res = app.request(
+        Method::Post,
+        &format!("/admin/import{}", query),
+        &[("Authorization", &authorization), ("Content-Type", content_type)],
+        body,
+    );
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    res.json()
+}
+
+fn export(app: &TestApp, accept: &str) -> String {
+    let authorization = format!("Bearer {}", app.token(ADMIN));
+    let res = app.get_with(
+        "/admin/export",
+        &[("Authorization", &authorization), ("Accept", accept)],
+    );
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert!(res.header("Content-Type").unwrap().starts_with(accept));
+    // Streamed, not buffered to be tagged.
+    assert!(res.header("ETag").is_none());
+    res.body
+}
+
+fn app_with_posts() -> TestApp {
+    let app = TestApp::new();
+    let token = app.token("alice");
+    app.create_post(&token, &json!({ "title": "Plain", "body": "Words", "tags": ["a", "b"] }));
+    app.create_post(&token, &json!({ "title": "Quoted, \"really\"", "body": "Two\nlines" }));
+    app
+}
+
+#[test]
+fn exports_round_trip() {
+    let source = app_with_posts();
+    for media_type in ["application/x-ndjson", "text/csv"] {
+        let exported = export(&source, media_type);
+        let target = TestApp::new();
+        let report = import(&target, "", media_type, &exported);
+        assert_eq!(report["created"], 2, "{}", report);
+        assert_eq!(report["failed"], 0);
+        assert_eq!(export(&target, media_type), exported);
+
+        // Importing again replaces the posts with the same uuids.
+        let report = import(&target, "", media_type, &exported);
+        assert_eq!(report["updated"], 2);
+        assert_eq!(target.database.read().unwrap().posts().count(), 2);
+    }
+}
+
+#[test]
+fn bad_lines_are_reported_and_skipped() {
+    let app = TestApp::new();
+    let uuid = "11111111-1111-1111-1111-111111111111";
+    let lines = [
+        json!({ "title": "Fine", "body": "b", "author": "x" }).to_string(),
+        String::new(),
+        json!({ "title": " ", "body": "b" }).to_string(),
+        "{ not json".to_string(),
+        json!({ "uuid": uuid, "title": "Kept", "body": "b", "author": "y" }).to_string(),
+        json!({ "uuid": uuid, "title": "Again", "body": "b", "author": "y" }).to_string(),
+    ];
+    let body = lines.join("\n");
+
+    let report = import(&app, "?dry_run=true", "application/x-ndjson", &body);
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["created"], 2);
+    assert!(app.database.read().unwrap().posts().next().is_none());
+
+    let report = import(&app, "", "application/x-ndjson", &body);
+    assert_eq!(report["created"], 2);
+    assert_eq!(report["failed"], 3);
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors[0]["line"], 3);
+    assert_eq!(errors[0]["fields"], json!(["title", "author"]));
+    assert_eq!(errors[1]["line"], 4);
+    assert_eq!(errors[2]["fields"], json!(["uuid"]));
+    assert_eq!(app.get(&format!("/post/{}", uuid)).json()["title"], "Kept");
+
+    let csv = "title,body,author,tags\nOne,b,x,rust iron\nTwo,b,x\n";
+    let report = import(&app, "", "text/csv", csv);
+    assert_eq!(report["created"], 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+    assert_eq!(app.get("/tags/iron/posts").json()["posts"][0]["title"], "One");
+}
+
+#[test]
+fn posts_in_the_trash_are_not_imported() {
+    let app = app_with_posts();
+    let exported = export(&app, "application/x-ndjson");
+    let post = app.database.read().unwrap().posts().next().unwrap().clone();
+    let id = post.uuid().to_string();
+    let token = app.token("alice");
+    let res = app.edit(Method::Delete, &id, &token, &app.etag(&id), &Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+
+    let report = import(&app, "", "application/x-ndjson", &exported);
+    assert_eq!(report["updated"], 1, "{}", report);
+    assert_eq!(report["created"], 0);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["errors"][0]["line"], 1);
+    assert_eq!(report["errors"][0]["message"], "the post with this uuid is in the trash");
+    assert_eq!(report["errors"][0]["fields"], json!(["uuid"]));
+    let database = app.database.read().unwrap();
+    assert!(database.trashed(post.uuid()).is_ok());
+    assert!(database.post(post.uuid()).is_none());
+}
+
+#[test]
+fn imports_have_a_limit_of_their_own() {
+    let mut config = Config::default();
+    config.limits.max_body_bytes = 256;
+    config.limits.max_import_bytes = 64 * 1024;
+    let app = TestApp::with_config(config);
+    let line = |n: usize| json!({ "title": format!("Post {}", n), "body": "b", "author": "x" });
+    let body: Vec<String> = (1..=250).map(|n| line(n).to_string()).collect();
+    let body = body.join("\n");
+    assert!(body.len() > 256);
+
+    // Several chunks' worth, well over the cap on other bodies.
+    let report = import(&app, "", "application/x-ndjson", &body);
+    assert_eq!(report["created"], 250, "{}", report);
+    assert_eq!(app.database.read().unwrap().posts().count(), 250);
+    // And exported over several chunks, in order.
+    let exported = export(&app, "application/x-ndjson");
+    let titles: Vec<String> = exported
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["title"].to_string())
+        .collect();
+    assert_eq!(titles.len(), 250);
+    assert_eq!(titles[249], "\"Post 250\"");
+
+    let authorization = format!("Bearer {}", app.token(ADMIN));
+    let res = app.request(
+        Method::Post,
+        "/admin/import",
+        &[("Authorization", &authorization)],
+        &"x".repeat(64 * 1024 + 1),
+    );
+    assert_eq!(res.status, Status::PayloadTooLarge);
+}
+
+#[test]
+fn imports_cut_off_midway_keep_what_was_read() {
+    let mut database = Database::new();
+    let lines: Vec<String> = (1..=3)
+        .map(|n| json!({ "title": format!("Post {}", n), "body": "b", "author": "x" }).to_string())
+        .collect();
+    let body = format!("{}\n{}", lines.join("\n"), "x".repeat(100));
+    let max_bytes = lines.join("\n").len() as u64 + 10;
+
+    let mut import = Import::new(ADMIN, false);
+    let mut read = Vec::new();
+    let input = BufReader::new(LimitedReader::new(body.as_bytes(), max_bytes));
+    for line in transfer::json_lines(input) {
+        match line {
+            Ok(line) => read.push(line),
+            Err(e) => {
+                import.add(&mut database, read).unwrap();
+                import.cut_off(&e);
+                break;
+            }
+        }
+    }
+    let report = import.finish();
+    assert_eq!(report.created, 3);
+    assert_eq!(report.failed, 1);
+    assert_eq!(report.errors[0].line, 4);
+    assert!(report.errors[0].message.contains("larger than"), "{}", report.errors[0].message);
+    assert_eq!(database.posts().count(), 3);
+}
+
+#[test]
+fn only_admins_transfer_posts() {
+    let app = app_with_posts();
+    assert_eq!(app.get("/admin/export").status, Status::Unauthorized);
+
+    let authorization = format!("Bearer {}", app.token("alice"));
+    let res = app.get_with("/admin/export", &[("Authorization", &authorization)]);
+    assert_eq!(res.status, Status::Forbidden);
+    let res = app.request(
+        Method::Post,
+        "/admin/import",
+        &[("Authorization", &authorization)],
+        "",
+    );
+    assert_eq!(res.status, Status::Forbidden);
+
+    let authorization = format!("Bearer {}", app.token(ADMIN));
+    let res = app.request(
+        Method::Post,
+        "/admin/import",
+        &[("Authorization", &authorization), ("Content-Type", "text/plain")],
+        "",
+    );
+    assert_eq!(res.status, Status::UnsupportedMediaType);
+    let res = app.request(
+        Method::Post,
+        "/admin/import?dry_run=maybe",
+        &[("Authorization", &authorization)],
+        "",
+    );
+    assert_eq!(res.error_code(), "invalid_query");
+}
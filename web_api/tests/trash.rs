@@ -0,0 +1,105 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+use web_api::trash;
+
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+fn trash_uuids(app: &TestApp, user: &str) -> Vec<String> {
+    let res = app.get_with("/trash", &[("Authorization", &bearer(&app.token(user)))]);
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    res.json()["posts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["uuid"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Creates a post by alice and moves it to the trash, returning its uuid.
+fn trashed_post(app: &TestApp) -> String {
+    let token = app.token("alice");
+    let post = app.create_post(
+        &token,
+        &json!({ "title": "Binned", "body": "Rubbish", "tags": ["waste"] }),
+    );
+    let id = post["uuid"].as_str().unwrap().to_string();
+    let res = app.edit(Method::Delete, &id, &token, &app.etag(&id), &Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+    id
+}
+
+#[test]
+fn trashed_posts_are_hidden_until_restored() {
+    let app = TestApp::new();
+    let last_id = app.database.read().unwrap().events().last_id().to_string();
+    let id = trashed_post(&app);
+    let path = format!("/post/{}", id);
+
+    assert_eq!(app.get(&path).error_code(), "not_found");
+    let alice = bearer(&app.token("alice"));
+    assert_eq!(app.get_with(&path, &[("Authorization", &alice)]).status, Status::NotFound);
+    assert_eq!(app.get(&format!("{}/comments", path)).status, Status::NotFound);
+    assert_eq!(app.get(&format!("{}/revisions", path)).status, Status::NotFound);
+    assert_eq!(app.get("/post_feed").json()["posts"], json!([]));
+    assert_eq!(app.get("/tags/waste/posts").json()["posts"], json!([]));
+    assert_eq!(app.get("/tags").json()["tags"], json!([]));
+    assert_eq!(app.get("/search?q=rubbish").json()["results"], json!([]));
+
+    assert_eq!(app.get("/trash").status, Status::Unauthorized);
+    assert_eq!(trash_uuids(&app, "alice"), [id.as_str()]);
+    assert_eq!(trash_uuids(&app, ADMIN), [id.as_str()]);
+    assert!(trash_uuids(&app, "bob").is_empty());
+    let res = app.get_with("/trash", &[("Authorization", &alice)]);
+    assert!(res.json()["posts"][0]["deleted_at"].is_string());
+
+    let restore = format!("/trash/{}/restore", id);
+    let res = app.post(&restore, Some(&app.token("bob")), &Value::Null);
+    assert_eq!(res.json()["error"]["message"], "no post with this id in the trash");
+    let res = app.post(&restore, Some(&app.token("alice")), &Value::Null);
+    assert_eq!(res.status, Status::Ok, "{}", res.body);
+    assert!(res.json()["deleted_at"].is_null());
+    assert_eq!(res.header("ETag").unwrap(), app.etag(&id));
+    assert_eq!(app.get("/post_feed").json()["posts"][0]["uuid"], id.as_str());
+    assert_eq!(app.get("/search?q=rubbish").json()["results"][0]["post"]["uuid"], id.as_str());
+    assert!(trash_uuids(&app, "alice").is_empty());
+    let res = app.post(&restore, Some(&app.token("alice")), &Value::Null);
+    assert_eq!(res.status, Status::NotFound);
+
+    let received = app.events("/post_feed/stream", &[("Last-Event-ID", &last_id)], 3);
+    assert!(received[1].contains("event: deleted\n"), "{}", received[1]);
+    assert!(received[2].contains("event: created\n"), "{}", received[2]);
+}
+
+#[test]
+fn expired_posts_are_purged() {
+    let app = TestApp::new();
+    let id = trashed_post(&app);
+    let comments = format!("/post/{}/comments", id);
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    let purged = app.database.write().unwrap().purge_trash(yesterday).unwrap();
+    assert!(purged.is_empty());
+    assert_eq!(trash_uuids(&app, "alice"), [id.as_str()]);
+
+    trash::spawn(app.database.clone(), Duration::from_millis(10), chrono::Duration::zero());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !trash_uuids(&app, "alice").is_empty() {
+        assert!(Instant::now() < deadline, "the trashed post was not purged");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let database = app.database.read().unwrap();
+    assert!(database.oldest_trashed().is_none());
+    assert!(database.revisions(&id.parse().unwrap()).is_empty());
+    drop(database);
+    let res = app.post(&format!("/trash/{}/restore", id), Some(&app.token("alice")), &Value::Null);
+    assert_eq!(res.status, Status::NotFound);
+    assert_eq!(app.get(&comments).status, Status::NotFound);
+}
@@ -0,0 +1,294 @@
+mod common;
+
+use common::{TestApp, ADMIN};
+use hmac::{Hmac, Mac};
+use iron::method::Method;
+use iron::status::Status;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use web_api::database::Database;
+use web_api::events::EventKind;
+use web_api::models::Webhook;
+use web_api::storage::FileStorage;
+use web_api::webhooks::{self, Delivery, DeliveryStatus, StoredDelivery, MAX_ATTEMPTS};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// A request the stand-in receiver got, with header names lowercased.
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+fn read_request(stream: &TcpStream) -> Received {
+    let mut reader = BufReader::new(stream);
+    let mut headers = HashMap::new();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.to_lowercase(), value.trim().to_string());
+            }
+            None => break,
+        }
+    }
+    let length: usize = headers["content-length"].parse().unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    Received {
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    }
+}
+
+/// Starts a local stand-in for a webhook receiver, which answers the n-th
+/// request with the n-th of `statuses`, or the last one once they run out.
+/// Returns its URL and what it receives.
+fn receiver(statuses: &[u16]) -> (String, mpsc::Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let statuses = statuses.to_vec();
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        for (n, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let request = read_request(&stream);
+            let status = statuses[n.min(statuses.len() - 1)];
+            write!(
+                stream,
+                "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            if sender.send(request).is_err() {
+                break;
+            }
+        }
+    });
+    (url, received)
+}
+
+fn register(app: &TestApp, body: &Value) -> Value {
+    let res = app.post("/admin/webhooks", Some(&app.token(ADMIN)), body);
+    assert_eq!(res.status, Status::Created, "{}", res.body);
+    res.json()
+}
+
+/// Waits until the newest delivery to a webhook has `status` and returns it.
+fn newest_delivery(app: &TestApp, webhook_id: &str, status: &str) -> Value {
+    let authorization = format!("Bearer {}", app.token(ADMIN));
+    let path = format!("/admin/webhooks/{}/deliveries", webhook_id);
+    let deadline = Instant::now() + WAIT;
+    loop {
+        let res = app.get_with(&path, &[("Authorization", &authorization)]);
+        assert_eq!(res.status, Status::Ok, "{}", res.body);
+        let delivery = res.json()["deliveries"][0].clone();
+        if delivery["status"] == status {
+            return delivery;
+        }
+        assert!(Instant::now() < deadline, "no {} delivery: {}", status, res.body);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn webhooks_are_managed_by_admins() {
+    let app = TestApp::new();
+    let admin = app.token(ADMIN);
+    let authorization = format!("Bearer {}", admin);
+
+    let body = json!({ "url": "https://example.com/hook" });
+    let res = app.post("/admin/webhooks", Some(&app.token("bob")), &body);
+    assert_eq!(res.status, Status::Forbidden);
+    for (body, field) in [
+        (json!({ "url": "ftp://example.com/hook" }), "url"),
+        (json!({ "url": "not a url" }), "url"),
+        (json!({ "url": "https://example.com/hook", "events": [] }), "events"),
+    ] {
+        let res = app.post("/admin/webhooks", Some(&admin), &body);
+        assert_eq!(res.status, Status::UnprocessableEntity);
+        assert_eq!(res.json()["error"]["details"], json!([field]));
+    }
+
+    let webhook = register(
+        &app,
+        &json!({ "url": "https://example.com/hook", "events": ["deleted", "created"] }),
+    );
+    assert_eq!(webhook["events"], json!(["created", "deleted"]));
+    assert!(webhook["secret"].as_str().unwrap().len() >= 32);
+    let path = format!("/admin/webhooks/{}", webhook["id"].as_str().unwrap());
+
+    let list = app.get_with("/admin/webhooks", &[("Authorization", &authorization)]).json();
+    assert_eq!(list["webhooks"][0]["url"], "https://example.com/hook");
+    assert!(list["webhooks"][0].get("secret").is_none());
+    assert_eq!(app.get("/admin/webhooks").status, Status::Unauthorized);
+
+    let res = app.get_with(&path, &[("Authorization", &authorization)]);
+    assert!(res.json().get("secret").is_none());
+    let etag = res.header("ETag").unwrap();
+    let res = app.request(Method::Delete, &path, &[("Authorization", &authorization)], "");
+    assert_eq!(res.status, Status::PreconditionRequired);
+    let headers = [("Authorization", authorization.as_str()), ("If-Match", etag.as_str())];
+    assert_eq!(app.request(Method::Delete, &path, &headers, "").status, Status::NoContent);
+    let res = app.get_with(&path, &[("Authorization", &authorization)]);
+    assert_eq!(res.json()["error"]["message"], "no webhook with this id");
+    let res = app.get_with(&format!("{}/deliveries", path), &[("Authorization", &authorization)]);
+    assert_eq!(res.status, Status::NotFound);
+}
+
+#[test]
+fn deliveries_are_signed_and_recorded() {
+    let app = TestApp::new();
+    webhooks::spawn(app.database.clone(), Duration::from_millis(10));
+    let (url, received) = receiver(&[200]);
+    let webhook = register(&app, &json!({ "url": url }));
+    let secret = webhook["secret"].as_str().unwrap();
+    let webhook_id = webhook["id"].as_str().unwrap();
+
+    let token = app.token("alice");
+    let post = app.create_post(&token, &json!({ "title": "Hooked", "body": "Sent out" }));
+    let request = received.recv_timeout(WAIT).expect("the post was not delivered");
+    assert_eq!(request.headers["x-webhook-event"], "created");
+    assert_eq!(request.headers["content-type"], "application/json");
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(request.body.as_bytes());
+    let expected: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(request.headers["x-webhook-signature"], format!("sha256={}", expected));
+
+    let payload: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload["event"], "created");
+    assert_eq!(payload["webhook"], webhook_id);
+    assert_eq!(payload["data"]["title"], "Hooked");
+    assert_eq!(request.headers["x-webhook-delivery"], payload["delivery"].as_str().unwrap());
+
+    let delivery = newest_delivery(&app, webhook_id, "succeeded");
+    assert_eq!(delivery["id"], payload["delivery"]);
+    assert_eq!(delivery["attempts"], 1);
+    assert_eq!(delivery["response_status"], 200);
+
+    let id = post["uuid"].as_str().unwrap();
+    let res = app.edit(Method::Delete, id, &token, &app.etag(id), &Value::Null);
+    assert_eq!(res.status, Status::NoContent);
+    let request = received.recv_timeout(WAIT).expect("the delete was not delivered");
+    let payload: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload["event"], "deleted");
+    assert_eq!(payload["data"], json!({ "uuid": id }));
+}
+
+#[test]
+fn failed_deliveries_are_retried_with_backoff() {
+    let app = TestApp::new();
+    webhooks::spawn(app.database.clone(), Duration::from_millis(10));
+    let (url, received) = receiver(&[500, 503, 200]);
+    let flaky = register(&app, &json!({ "url": url, "events": ["created"] }));
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let gone = register(&app, &json!({ "url": format!("http://{}/hook", closed) }));
+
+    app.create_post(&app.token("alice"), &json!({ "title": "Retry", "body": "Again" }));
+
+    let delivery = newest_delivery(&app, flaky["id"].as_str().unwrap(), "succeeded");
+    assert_eq!(delivery["attempts"], 3);
+    assert_eq!(delivery["response_status"], 200);
+    assert!(delivery["error"].is_null());
+    let ids: Vec<String> = (0..3)
+        .map(|_| received.recv_timeout(WAIT).unwrap().headers["x-webhook-delivery"].clone())
+        .collect();
+    assert!(ids.iter().all(|id| *id == ids[0]));
+
+    let delivery = newest_delivery(&app, gone["id"].as_str().unwrap(), "failed");
+    assert_eq!(delivery["attempts"], MAX_ATTEMPTS);
+    assert!(delivery["response_status"].is_null());
+    assert!(delivery["error"].is_string());
+    assert!(delivery["next_attempt_at"].is_null());
+}
+
+#[test]
+fn a_slow_receiver_does_not_hold_up_the_others() {
+    let app = TestApp::new();
+    webhooks::spawn(app.database.clone(), Duration::from_millis(10));
+    // Takes connections but never answers, so every send to it times out.
+    let blackhole = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", blackhole.local_addr().unwrap());
+    register(&app, &json!({ "url": url }));
+    let (url, received) = receiver(&[200]);
+    register(&app, &json!({ "url": url }));
+
+    let token = app.token("alice");
+    for title in ["One", "Two"] {
+        app.create_post(&token, &json!({ "title": title, "body": "Quick" }));
+    }
+    for title in ["One", "Two"] {
+        let request = received.recv_timeout(WAIT).expect("held up by the slow receiver");
+        let payload: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(payload["data"]["title"], title);
+    }
+}
+
+#[test]
+fn pending_deliveries_are_sent_after_a_restart() {
+    let dir = std::env::temp_dir().join(format!("web_api-deliveries-{}", Uuid::new_v4()));
+    let open = || Database::open(Box::new(FileStorage::new(&dir).unwrap())).unwrap();
+    let (url, received) = receiver(&[200]);
+    let webhook = Webhook::new(&url, "secret".to_string(), vec![EventKind::Created], ADMIN);
+    let webhook_id = *webhook.id();
+    let delivery_id = Uuid::new_v4();
+
+    let mut database = open();
+    database.add_webhook(webhook).unwrap();
+    let now = chrono::Utc::now();
+    let pending = StoredDelivery {
+        webhook_id,
+        delivery: Delivery {
+            id: delivery_id,
+            event: EventKind::Created,
+            event_id: 1,
+            status: DeliveryStatus::Pending,
+            attempts: 1,
+            response_status: Some(503),
+            error: Some("the receiver answered 503".to_string()),
+            created_at: now,
+            last_attempt_at: Some(now),
+            next_attempt_at: Some(now),
+        },
+        payload: Some(json!({ "delivery": delivery_id }).to_string()),
+    };
+    database.record_delivery(pending).unwrap();
+    drop(database);
+
+    let database = Arc::new(RwLock::new(open()));
+    webhooks::spawn(database.clone(), Duration::from_millis(10));
+    let request = received.recv_timeout(WAIT).expect("the pending delivery was not sent");
+    assert_eq!(request.headers["x-webhook-delivery"], delivery_id.to_string());
+    let deadline = Instant::now() + WAIT;
+    loop {
+        let deliveries =
+            database.read().unwrap_or_else(PoisonError::into_inner).deliveries(&webhook_id);
+        assert_eq!(deliveries.len(), 1);
+        if deliveries[0].status == DeliveryStatus::Succeeded {
+            assert_eq!(deliveries[0].attempts, 2);
+            break;
+        }
+        assert!(Instant::now() < deadline, "the delivery was not recorded as sent");
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(database);
+    // What the attempt recorded outlives the restart too.
+    let deliveries = open().deliveries(&webhook_id);
+    assert_eq!(deliveries[0].status, DeliveryStatus::Succeeded);
+    std::fs::remove_dir_all(&dir).unwrap();
+}